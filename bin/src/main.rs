@@ -3,12 +3,13 @@ extern crate log;
 
 use std::path::PathBuf;
 use std::thread::spawn;
+use std::time::Duration;
 
 use futures::Stream;
 
 use config::Config;
 use env_logger::Env;
-use fs::tail::Tailer as FSSource;
+use fs::tail::{OffsetStore, Tailer as FSSource};
 use futures::StreamExt;
 use http::client::Client;
 
@@ -19,12 +20,21 @@ use k8s::event_source::K8sEventStream;
 use k8s::middleware::K8sMetadata;
 use k8s::K8sEventLogConf;
 use metrics::Metrics;
+use middleware::filter::LineFilter;
+use middleware::json::JsonParser;
+use middleware::level::Levels;
+use middleware::metadata::Metadata;
+use middleware::redact::Redact;
+use middleware::timestamp::TimestampExtractor;
 use middleware::Executor;
 use pin_utils::pin_mut;
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use syslog::kmsg::SequenceStore as KmsgSequenceStore;
 
 use tokio::runtime::Runtime;
+use tokio::signal::unix::{signal, SignalKind};
 
 #[global_allocator]
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
@@ -38,9 +48,37 @@ pub static PKG_NAME: &str = env!("CARGO_PKG_NAME");
 #[no_mangle]
 pub static PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+mod pipe;
+mod rules;
+
+// how long the lines that were already read are given to be delivered before the agent exits
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+// how often the stores are checked for changes to checkpoint, even while no lines are read
+const CHECKPOINT_TICK: Duration = Duration::from_secs(1);
+
 fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.first().map(String::as_str) == Some("rules") {
+        env_logger::from_env(Env::default().default_filter_or("warn")).init();
+        std::process::exit(rules::run(&args[1..]));
+    }
+    let pipe = if !pipe::is_requested(&args) {
+        None
+    } else {
+        match pipe::parse_args(&args) {
+            Ok(v) => Some(v),
+            Err(usage) => {
+                eprintln!("{}", usage);
+                std::process::exit(2);
+            }
+        }
+    };
+
     env_logger::from_env(Env::default().default_filter_or("info")).init();
     info!("running version: {}", env!("CARGO_PKG_VERSION"));
+    if pipe.is_none() && !args.is_empty() {
+        warn!("ignoring arguments: {:?}", args);
+    }
 
     let config = match Config::new() {
         Ok(v) => v,
@@ -52,11 +90,9 @@ fn main() {
 
     spawn(Metrics::start);
 
-    let client = Rc::new(RefCell::new(Client::new(config.http.template)));
-    client
-        .borrow_mut()
-        .set_max_buffer_size(config.http.body_size);
-    client.borrow_mut().set_timeout(config.http.timeout);
+    let mut client = Client::new(config.http.template);
+    client.set_max_buffer_size(config.http.body_size);
+    client.set_timeout(config.http.timeout);
 
     let mut executor = Executor::new();
     if PathBuf::from("/var/log/containers/").exists() {
@@ -65,12 +101,98 @@ fn main() {
             Err(e) => warn!("{}", e),
         };
     }
+    if !config.log.metadata.is_empty() {
+        executor.register(Metadata::new(config.log.metadata));
+    }
+    if !config.log.line_filters.is_empty() {
+        executor.register(LineFilter::new(config.log.line_filters));
+    }
+    // lines are parsed before they are redacted, as redacting the raw JSON could leave it invalid
+    if let Some(options) = config.log.json {
+        executor.register(JsonParser::new(options));
+    }
+    if !config.log.redaction_rules.is_empty() {
+        executor.register(Redact::new(
+            config.log.redaction_rules,
+            config.log.redaction_salt,
+        ));
+    }
+    // levels promoted out of JSON lines are kept, the ones of other lines are detected
+    let levels = config.log.detect_levels
+        || config.log.min_level.is_some()
+        || !config.log.min_level_rules.is_empty();
+    if levels {
+        executor.register(Levels::new(
+            config.log.detect_levels,
+            config.log.min_level,
+            config.log.min_level_rules,
+        ));
+    }
+    // timestamps promoted out of JSON lines take precedence over the ones found in the line
+    if !config.log.timestamp_formats.is_empty() {
+        executor.register(TimestampExtractor::new(
+            config.log.timestamp_formats,
+            config.log.timestamp_max_skew,
+        ));
+    }
     executor.init();
 
+    // piped lines are the only source, the agent exits once they are delivered
+    if let Some(options) = pipe {
+        std::process::exit(pipe::run(options, executor, client, FLUSH_TIMEOUT));
+    }
+    let client = Rc::new(RefCell::new(client));
+
     let mut fs_tailer_buf = [0u8; 4096];
-    let mut fs_source = FSSource::new(config.log.dirs, config.log.rules, config.log.lookback);
+    let mut fs_source = FSSource::with_options(
+        config.log.dirs,
+        config.log.rules,
+        config.log.lookback,
+        fs::cache::Options {
+            backend: config.log.watch_backend,
+            poll_interval: config.log.poll_interval,
+            max_open_files: config.log.max_open_files,
+            pending_dirs: config.log.pending_dirs,
+        },
+    );
+    fs_source.set_multiline_rules(config.log.multiline);
+    fs_source.set_encoding_rules(config.log.encodings);
+    if let Some(timeout) = config.log.partial_line_timeout {
+        fs_source.set_partial_line_timeout(timeout);
+    }
+    fs_source.set_read_budget(config.log.read_budget);
 
-    let journald_source = create_source(&config.journald.paths);
+    let offsets = match OffsetStore::open(config.log.db_path.join("offsets.json")) {
+        Ok(v) => {
+            let offsets = Arc::new(Mutex::new(v));
+            fs_source.set_offset_store(offsets.clone());
+            Some(offsets)
+        }
+        Err(e) => {
+            warn!(
+                "unable to open offset store in {:?}, file offsets will not survive a restart: {}",
+                config.log.db_path, e
+            );
+            None
+        }
+    };
+
+    let journald_source = create_source(&config.journald.paths, levels);
+    let syslog_options = config.syslog;
+
+    let kmsg_sequences = match syslog_options.kmsg {
+        Some(_) => match KmsgSequenceStore::open(config.log.db_path.join("kmsg.json")) {
+            Ok(v) => Some(Arc::new(Mutex::new(v))),
+            Err(e) => {
+                warn!(
+                    "unable to open kmsg sequence store in {:?}, kernel messages may be sent again after a restart: {}",
+                    config.log.db_path, e
+                );
+                None
+            }
+        },
+        None => None,
+    };
 
     let k8s_event_stream = match config.log.log_k8s_events {
         K8sEventLogConf::Never => None,
@@ -85,6 +207,11 @@ fn main() {
             }),
         ),
     };
+    let syslog_kmsg_sequences = kmsg_sequences.clone();
+    let sources_client = client.clone();
+    let checkpoint_offsets = offsets.clone();
+    let checkpoint_sequences = kmsg_sequences.clone();
+
     // Create the runtime
     let mut rt = Runtime::new().unwrap();
 
@@ -111,6 +238,18 @@ fn main() {
 
         let mut k8s_event_source: Option<std::pin::Pin<&mut _>> = k8s_event_source.as_pin_mut();
 
+        // the listeners are spawned on the runtime, so the source is created within it
+        let syslog_source = if syslog_options.is_empty() {
+            None
+        } else {
+            Some(syslog::source::create_source(
+                syslog_options,
+                syslog_kmsg_sequences,
+            ))
+        };
+        pin_mut!(syslog_source);
+        let mut syslog_source: Option<std::pin::Pin<&mut _>> = syslog_source.as_pin_mut();
+
         let mut sources: futures::stream::SelectAll<&mut (dyn Stream<Item = Vec<_>> + Unpin)> =
             futures::stream::SelectAll::new();
 
@@ -123,15 +262,72 @@ fn main() {
             sources.push(k)
         };
 
-        sources
-            .for_each(|lines| async {
-                if let Some(lines) = executor.process(lines) {
-                    for line in lines {
-                        // TODO upgrade to async hyper
-                        client.borrow_mut().send(line)
-                    }
+        if let Some(s) = syslog_source.as_mut() {
+            info!("Enabling syslog");
+            sources.push(s)
+        };
+
+        let sources = sources.for_each(|lines| async {
+            if let Some(lines) = executor.process(lines) {
+                for line in lines {
+                    // TODO upgrade to async hyper
+                    sources_client.borrow_mut().send(line)
+                }
+            }
+        });
+
+        let shutdown = async {
+            let mut sigterm =
+                signal(SignalKind::terminate()).expect("unable to listen for SIGTERM");
+            let sigterm = sigterm.recv();
+            let sigint = tokio::signal::ctrl_c();
+            pin_mut!(sigterm);
+            pin_mut!(sigint);
+            futures::future::select(sigterm, sigint).await;
+            info!("received shutdown signal");
+        };
+
+        let checkpoints = tokio::time::interval(CHECKPOINT_TICK).for_each(|_| {
+            if let Some(offsets) = &checkpoint_offsets {
+                if let Err(e) = offsets.lock().expect("Couldn't lock offsets").checkpoint() {
+                    warn!("unable to checkpoint file offsets: {}", e);
+                }
+            }
+            if let Some(sequences) = &checkpoint_sequences {
+                if let Err(e) = sequences
+                    .lock()
+                    .expect("Couldn't lock kmsg sequences")
+                    .checkpoint()
+                {
+                    warn!("unable to checkpoint the kmsg sequence: {}", e);
                 }
-            })
-            .await
+            }
+            futures::future::ready(())
+        });
+
+        pin_mut!(sources);
+        pin_mut!(checkpoints);
+        pin_mut!(shutdown);
+        futures::future::select(futures::future::select(sources, checkpoints), shutdown).await;
     });
+
+    // the stored offsets may be past lines that are still buffered or in flight
+    if !client.borrow_mut().flush_and_wait(FLUSH_TIMEOUT) {
+        warn!("not all lines were delivered before shutting down");
+    }
+
+    if let Some(offsets) = offsets {
+        if let Err(e) = offsets.lock().expect("Couldn't lock offsets").flush() {
+            error!("unable to flush file offsets: {}", e);
+        }
+    }
+    if let Some(sequences) = kmsg_sequences {
+        if let Err(e) = sequences
+            .lock()
+            .expect("Couldn't lock kmsg sequences")
+            .flush()
+        {
+            error!("unable to flush the kmsg sequence: {}", e);
+        }
+    }
 }
@@ -0,0 +1,127 @@
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use futures::{Stream, StreamExt};
+use http::client::Client;
+use http::source::set_source;
+use http::types::body::LineBuilder;
+use middleware::Executor;
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::runtime::Runtime;
+
+const USAGE: &str = "usage: logdna-agent (--stdin | --fifo <path>) [--app <name>] [--file <name>]";
+
+/// Where the lines are read from
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    Stdin,
+    /// A named pipe, or any other file that's read to the end
+    Fifo(PathBuf),
+}
+
+/// The options of reading lines piped into the agent, e.g. `cmd | logdna-agent --stdin`
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub input: Input,
+    /// The app the lines are tagged with
+    pub app: Option<String>,
+    /// The file name the lines are tagged with
+    pub file: Option<String>,
+}
+
+/// Whether the arguments of the agent ask for piped lines to be read
+pub fn is_requested(args: &[String]) -> bool {
+    args.iter().any(|arg| arg == "--stdin" || arg == "--fifo")
+}
+
+/// Parses the arguments of the agent, returning the usage if they aren't valid
+pub fn parse_args(args: &[String]) -> Result<Options, &'static str> {
+    let mut input = None;
+    let mut app = None;
+    let mut file = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let target = match arg.as_str() {
+            "--stdin" if input.is_none() => {
+                input = Some(Input::Stdin);
+                continue;
+            }
+            "--fifo" if input.is_none() => {
+                input = Some(Input::Fifo(args.next().ok_or(USAGE)?.into()));
+                continue;
+            }
+            "--app" => &mut app,
+            "--file" => &mut file,
+            _ => return Err(USAGE),
+        };
+        *target = Some(args.next().ok_or(USAGE)?.clone());
+    }
+    Ok(Options {
+        input: input.ok_or(USAGE)?,
+        app,
+        file,
+    })
+}
+
+/// Sends the lines of the input until it ends and returns the exit code, which is 0 if all of
+/// them were delivered within `timeout` of the input ending
+pub fn run(options: Options, executor: Executor, mut client: Client, timeout: Duration) -> i32 {
+    let mut rt = Runtime::new().expect("Runtime::new()");
+    let result = rt.block_on(async {
+        let lines = match options.input {
+            Input::Stdin => lines(tokio::io::stdin()).boxed_local(),
+            Input::Fifo(ref path) => lines(File::open(path).await?).boxed_local(),
+        };
+        lines
+            .for_each(|line| {
+                let mut line = LineBuilder::new().line(line);
+                if let Some(ref app) = options.app {
+                    line = line.app(app.clone());
+                }
+                if let Some(ref file) = options.file {
+                    line = line.file(file.clone());
+                }
+                set_source(&mut line, "pipe");
+                for line in executor.process(vec![line]).unwrap_or_default() {
+                    client.send(line);
+                }
+                futures::future::ready(())
+            })
+            .await;
+        Ok::<_, io::Error>(())
+    });
+    if let Err(e) = result {
+        error!("unable to read {:?}: {}", options.input, e);
+        return 1;
+    }
+
+    if client.flush_and_wait(timeout) {
+        info!("all lines were delivered");
+        0
+    } else {
+        error!("not all lines were delivered");
+        1
+    }
+}
+
+// the lines of the input without their line endings, until it ends or can't be read
+fn lines<R: AsyncRead + Unpin>(reader: R) -> impl Stream<Item = String> {
+    futures::stream::unfold(BufReader::new(reader), |mut reader| async move {
+        let mut buf = Vec::new();
+        match reader.read_until(b'\n', &mut buf).await {
+            Ok(0) => None,
+            Ok(_) => {
+                let line = String::from_utf8_lossy(&buf)
+                    .trim_end_matches(['\n', '\r'])
+                    .to_string();
+                Some((line, reader))
+            }
+            Err(e) => {
+                warn!("stopped reading input: {}", e);
+                None
+            }
+        }
+    })
+}
@@ -0,0 +1,73 @@
+use std::convert::TryFrom;
+use std::env;
+
+use config::{Config, LogConfig};
+use fs::cache::{FileSystem, Options, WatchBackend};
+
+const USAGE: &str = "usage: logdna-agent rules test <path>...";
+
+/// Runs the `rules` subcommand and returns the exit code
+pub fn run(args: &[String]) -> i32 {
+    match args.split_first() {
+        Some((command, paths)) if command == "test" && !paths.is_empty() => test(paths),
+        _ => {
+            eprintln!("{}", USAGE);
+            2
+        }
+    }
+}
+
+// prints for every path which rules decide whether it's tailed with the effective config
+fn test(paths: &[String]) -> i32 {
+    let log = match LogConfig::try_from(Config::raw().log) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("config error: {}", e);
+            return 1;
+        }
+    };
+
+    // the dirs are polled instead of using inotify watches an agent running next to this may need
+    let fs = FileSystem::<()>::with_options(
+        log.dirs,
+        log.rules,
+        Options {
+            backend: WatchBackend::Poll,
+            poll_interval: log.poll_interval,
+            max_open_files: log.max_open_files,
+            pending_dirs: log.pending_dirs,
+        },
+    );
+
+    let cwd = env::current_dir().unwrap_or_default();
+    for path in paths {
+        let path = cwd.join(path);
+        let explanation = fs.explain(&path);
+
+        println!("{}: {:?}", path.display(), explanation.rules.status);
+        for rule in explanation.rules.inclusions.iter() {
+            println!("  included by {}", rule.describe());
+        }
+        for rule in explanation.rules.exclusions.iter() {
+            println!("  excluded by {}", rule.describe());
+        }
+        if let Some(rule) = explanation.rules.ordered {
+            println!("  decided by ordered rule {}", rule.describe());
+        }
+        println!(
+            "  in an initial dir: {}",
+            if explanation.initial_dir { "yes" } else { "no" }
+        );
+        for symlink in explanation.symlinks.iter() {
+            println!("  target of symlink {}", symlink.display());
+        }
+        if let Some(dir) = explanation.pruned {
+            println!("  in excluded dir {}", dir.display());
+        }
+        println!(
+            "  tailed: {}",
+            if explanation.tracked { "yes" } else { "no" }
+        );
+    }
+    0
+}
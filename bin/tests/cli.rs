@@ -5,6 +5,7 @@ use tempfile::tempdir;
 
 use std::fs::File;
 use std::io::{Read, Write};
+use std::net::TcpListener;
 use std::process::Command;
 use std::thread;
 
@@ -20,6 +21,114 @@ fn api_key_missing() {
         .failure();
 }
 
+#[test]
+fn rules_test() {
+    let dir = tempdir().expect("Couldn't create temp dir...");
+    File::create(dir.path().join("a.log")).expect("Couldn't create temp log file...");
+    File::create(dir.path().join("a.debug.log")).expect("Couldn't create temp log file...");
+
+    let mut cmd = Command::cargo_bin("logdna-agent").unwrap();
+    cmd.env_clear()
+        .env("LOGDNA_CONFIG_FILE", dir.path().join("missing.yaml"))
+        .env("LOGDNA_LOG_DIRS", dir.path())
+        .env("LOGDNA_LOG_RULES", "!*.debug.log")
+        .args(["rules", "test", "a.log", "a.debug.log", "b.txt"])
+        .current_dir(dir.path())
+        .assert()
+        .stdout(predicate::str::contains(format!(
+            "{}: Ok\n  included by glob *.log\n  in an initial dir: yes\n  tailed: yes",
+            dir.path().join("a.log").display()
+        )))
+        .stdout(predicate::str::contains(format!(
+            "{}: Excluded\n  included by glob *.log\n  decided by ordered rule !glob *.debug.log",
+            dir.path().join("a.debug.log").display()
+        )))
+        .stdout(predicate::str::contains(format!(
+            "{}: NotIncluded",
+            dir.path().join("b.txt").display()
+        )))
+        .success();
+
+    let mut cmd = Command::cargo_bin("logdna-agent").unwrap();
+    cmd.env_clear()
+        .args(["rules", "test"])
+        .assert()
+        .stderr(predicate::str::contains("usage: logdna-agent rules test"))
+        .code(2);
+}
+
+#[test]
+fn pipe_stdin() {
+    let dir = tempdir().expect("Couldn't create temp dir...");
+
+    // accepts a single ingest request and returns it's body
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0; 4096];
+        let body_start = loop {
+            let len = stream.read(&mut buf).unwrap();
+            request.extend_from_slice(&buf[..len]);
+            if let Some(idx) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                break idx + 4;
+            }
+        };
+        let headers = String::from_utf8_lossy(&request[..body_start]).to_lowercase();
+        let len: usize = headers
+            .lines()
+            .find_map(|header| header.strip_prefix("content-length: "))
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        while request.len() < body_start + len {
+            let read = stream.read(&mut buf).unwrap();
+            request.extend_from_slice(&buf[..read]);
+        }
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+            .unwrap();
+        String::from_utf8_lossy(&request[body_start..]).to_string()
+    });
+
+    let mut cmd = assert_cmd::Command::cargo_bin("logdna-agent").unwrap();
+    cmd.env_clear()
+        .env("LOGDNA_CONFIG_FILE", dir.path().join("missing.yaml"))
+        .env("LOGDNA_INGESTION_KEY", "ingestion_key")
+        .env("LOGDNA_HOST", addr.to_string())
+        .env("LOGDNA_USE_SSL", "false")
+        .env("LOGDNA_USE_COMPRESSION", "false")
+        .args(["--stdin", "--app", "batch"])
+        .write_stdin("first line\nsecond line\n")
+        .assert()
+        .stderr(predicate::str::contains("all lines were delivered"))
+        .success();
+
+    let body = server.join().unwrap();
+    assert!(body.contains(r#""line":"first line""#));
+    assert!(body.contains(r#""line":"second line""#));
+    assert!(body.contains(r#""app":"batch""#));
+
+    let mut cmd = Command::cargo_bin("logdna-agent").unwrap();
+    cmd.env_clear()
+        .args(["--stdin", "--verbose"])
+        .assert()
+        .stderr(predicate::str::contains("usage: logdna-agent (--stdin"))
+        .code(2);
+
+    // the daemon isn't stopped by arguments that aren't meant for piped lines
+    let mut cmd = Command::cargo_bin("logdna-agent").unwrap();
+    cmd.env_clear()
+        .env("LOGDNA_CONFIG_FILE", dir.path().join("missing.yaml"))
+        .args(["--app", "batch"])
+        .assert()
+        .stderr(predicate::str::contains("usage").not())
+        .stderr(predicate::str::contains("ignoring arguments"))
+        .code(1);
+}
+
 #[test]
 #[cfg_attr(not(feature = "integration_tests"), ignore)]
 fn api_key_present() {
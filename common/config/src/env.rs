@@ -1,4 +1,4 @@
-use crate::raw::{Config as RawConfig, Rules as RawRules};
+use crate::raw::{Config as RawConfig, RedactionRuleConfig, Rules as RawRules};
 use config_macro::env_config;
 use http::types::params::{Params, Tags};
 use serde::Deserialize;
@@ -60,7 +60,7 @@ pub struct Config {
 
     #[env(LOGDNA_EXCLUSION_RULES, LOGDNA_EXCLUDE)]
     #[example("/var/log/**,/var/data/**")]
-    pub exclusion_rules: Option<EnvList<String>>,
+    pub exclusion_rules: Option<GlobList>,
 
     #[env(LOGDNA_EXCLUSION_REGEX_RULES, LOGDNA_EXCLUDE_REGEX)]
     #[example("/var/log/.*,/var/data/.*")]
@@ -68,16 +68,52 @@ pub struct Config {
 
     #[env(LOGDNA_INCLUSION_RULES, LOGDNA_INCLUDE)]
     #[example("/var/log/**,/var/data/**")]
-    pub inclusion_rules: Option<EnvList<String>>,
+    pub inclusion_rules: Option<GlobList>,
 
     #[env(LOGDNA_INCLUSION_REGEX_RULES, LOGDNA_INCLUDE_REGEX)]
     #[example("/var/log/.*,/var/data/.*")]
     pub inclusion_regex_rules: Option<EnvList<String>>,
 
+    #[env(LOGDNA_LOG_RULES)]
+    #[example("/var/log/app/**,!*.debug.log,*/critical.debug.log")]
+    pub log_rules: Option<GlobList>,
+
     #[env(LOGDNA_JOURNALD_PATHS)]
     #[example("/var/log/journal")]
     pub journald_paths: Option<EnvList<PathBuf>>,
 
+    #[env(LOGDNA_SYSLOG_UDP)]
+    #[example("0.0.0.0:514")]
+    pub syslog_udp: Option<EnvList<String>>,
+
+    #[env(LOGDNA_SYSLOG_TCP)]
+    #[example("0.0.0.0:601")]
+    pub syslog_tcp: Option<EnvList<String>>,
+
+    #[env(LOGDNA_SYSLOG_TLS)]
+    #[example("0.0.0.0:6514")]
+    pub syslog_tls: Option<EnvList<String>>,
+
+    #[env(LOGDNA_SYSLOG_TLS_IDENTITY)]
+    #[example("/etc/logdna/syslog.p12")]
+    pub syslog_tls_identity: Option<PathBuf>,
+
+    #[env(LOGDNA_SYSLOG_TLS_PASSWORD)]
+    #[example("changeit")]
+    pub syslog_tls_password: Option<String>,
+
+    #[env(LOGDNA_SYSLOG_UNIX)]
+    #[example("/dev/log")]
+    pub syslog_unix: Option<EnvList<PathBuf>>,
+
+    #[env(LOGDNA_SYSLOG_UNIX_STREAM)]
+    #[example("/run/logdna/log.sock")]
+    pub syslog_unix_stream: Option<EnvList<PathBuf>>,
+
+    #[env(LOGDNA_SYSLOG_KMSG)]
+    #[example("/dev/kmsg")]
+    pub syslog_kmsg: Option<PathBuf>,
+
     #[env(LOGDNA_LOOKBACK)]
     #[example("none")]
     pub lookback: Option<String>,
@@ -85,6 +121,58 @@ pub struct Config {
     #[env(LOGDNA_LOG_K8S_EVENTS)]
     #[example("always")]
     pub log_k8s_events: Option<String>,
+
+    #[env(LOGDNA_DB_PATH)]
+    #[example("/var/lib/logdna")]
+    pub db_path: Option<PathBuf>,
+
+    #[env(LOGDNA_PARTIAL_LINE_TIMEOUT)]
+    #[example("5000")]
+    pub partial_line_timeout: Option<u64>,
+
+    #[env(LOGDNA_WATCH_BACKEND)]
+    #[example("poll")]
+    pub watch_backend: Option<String>,
+
+    #[env(LOGDNA_POLL_INTERVAL)]
+    #[example("1000")]
+    pub poll_interval: Option<u64>,
+
+    #[env(LOGDNA_MAX_OPEN_FILES)]
+    #[example("10000")]
+    pub max_open_files: Option<usize>,
+
+    #[env(LOGDNA_READ_BUDGET_BYTES)]
+    #[example("1048576")]
+    pub read_budget_bytes: Option<u64>,
+
+    #[env(LOGDNA_READ_BUDGET_LINES)]
+    #[example("10000")]
+    pub read_budget_lines: Option<u64>,
+
+    #[env(LOGDNA_REDACT_DETECTORS)]
+    #[example("pan,email,jwt")]
+    pub redact_detectors: Option<EnvList<String>>,
+
+    #[env(LOGDNA_REDACT_SALT)]
+    #[example("b4d5f1c2e3")]
+    pub redact_salt: Option<String>,
+
+    #[env(LOGDNA_JSON_LINES)]
+    #[example("true")]
+    pub json_lines: Option<bool>,
+
+    #[env(LOGDNA_TIMESTAMP_FORMATS)]
+    #[example("rfc3339,syslog")]
+    pub timestamp_formats: Option<EnvList<String>>,
+
+    #[env(LOGDNA_DETECT_LEVELS)]
+    #[example("true")]
+    pub detect_levels: Option<bool>,
+
+    #[env(LOGDNA_MIN_LEVEL)]
+    #[example("info")]
+    pub min_level: Option<String>,
 }
 
 impl Config {
@@ -207,11 +295,53 @@ impl Config {
             }
         }
 
+        if let Some(mut v) = self.log_rules {
+            let rules = raw.log.rules.get_or_insert(Vec::new());
+            rules.append(&mut v);
+        }
+
         if let Some(mut v) = self.journald_paths {
             let paths = raw.journald.paths.get_or_insert(Vec::new());
             paths.append(&mut v);
         }
 
+        if let Some(mut v) = self.syslog_udp {
+            let udp = raw.syslog.udp.get_or_insert(Vec::new());
+            udp.append(&mut v);
+        }
+
+        if let Some(mut v) = self.syslog_tcp {
+            let tcp = raw.syslog.tcp.get_or_insert(Vec::new());
+            tcp.append(&mut v);
+        }
+
+        if let Some(mut v) = self.syslog_tls {
+            let tls = raw.syslog.tls.get_or_insert(Vec::new());
+            tls.append(&mut v);
+        }
+
+        if self.syslog_tls_identity.is_some() {
+            raw.syslog.tls_identity = self.syslog_tls_identity;
+        }
+
+        if self.syslog_tls_password.is_some() {
+            raw.syslog.tls_password = self.syslog_tls_password;
+        }
+
+        if let Some(mut v) = self.syslog_unix {
+            let unix = raw.syslog.unix.get_or_insert(Vec::new());
+            unix.append(&mut v);
+        }
+
+        if let Some(mut v) = self.syslog_unix_stream {
+            let unix_stream = raw.syslog.unix_stream.get_or_insert(Vec::new());
+            unix_stream.append(&mut v);
+        }
+
+        if self.syslog_kmsg.is_some() {
+            raw.syslog.kmsg = self.syslog_kmsg;
+        }
+
         if self.log_k8s_events.is_some() {
             raw.log.log_k8s_events = self.log_k8s_events;
         }
@@ -220,6 +350,71 @@ impl Config {
             raw.log.lookback = self.lookback;
         }
 
+        if self.db_path.is_some() {
+            raw.log.db_path = self.db_path;
+        }
+
+        if self.partial_line_timeout.is_some() {
+            raw.log.partial_line_timeout = self.partial_line_timeout;
+        }
+
+        if self.watch_backend.is_some() {
+            raw.log.watch_backend = self.watch_backend;
+        }
+
+        if self.poll_interval.is_some() {
+            raw.log.poll_interval = self.poll_interval;
+        }
+
+        if self.max_open_files.is_some() {
+            raw.log.max_open_files = self.max_open_files;
+        }
+
+        if self.read_budget_bytes.is_some() {
+            raw.log.read_budget_bytes = self.read_budget_bytes;
+        }
+
+        if self.read_budget_lines.is_some() {
+            raw.log.read_budget_lines = self.read_budget_lines;
+        }
+
+        if let Some(v) = self.redact_detectors {
+            let redact = raw.log.redact.get_or_insert_with(Default::default);
+            redact
+                .rules
+                .extend(v.0.into_iter().map(|detector| RedactionRuleConfig {
+                    detector: Some(detector),
+                    name: None,
+                    regex: None,
+                    mode: None,
+                }));
+        }
+
+        if self.redact_salt.is_some() {
+            let redact = raw.log.redact.get_or_insert_with(Default::default);
+            redact.salt = self.redact_salt;
+        }
+
+        if self.json_lines.is_some() {
+            let json = raw.log.json.get_or_insert_with(Default::default);
+            json.enabled = self.json_lines;
+        }
+
+        if let Some(v) = self.timestamp_formats {
+            let timestamps = raw.log.timestamps.get_or_insert_with(Default::default);
+            timestamps.formats = Some(v.0);
+        }
+
+        if self.detect_levels.is_some() {
+            let levels = raw.log.levels.get_or_insert_with(Default::default);
+            levels.detect = self.detect_levels;
+        }
+
+        if self.min_level.is_some() {
+            let levels = raw.log.levels.get_or_insert_with(Default::default);
+            levels.min_level = self.min_level;
+        }
+
         raw
     }
 }
@@ -258,3 +453,47 @@ impl<T: FromStr> From<Vec<T>> for EnvList<T> {
         EnvList(vec)
     }
 }
+
+/// A comma separated list of glob patterns, the commas of braces like `*.{log,txt}` don't
+/// separate patterns
+#[derive(Deserialize, Debug, Ord, PartialOrd, Eq, PartialEq, Clone)]
+pub struct GlobList(pub Vec<String>);
+
+impl Deref for GlobList {
+    type Target = Vec<String>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for GlobList {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl FromStr for GlobList {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // an escaped brace is matched literally so it doesn't open or close one
+        let mut depth = 0;
+        let mut escaped = false;
+        Ok(GlobList(
+            s.split_terminator(|c| {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '{' => depth += 1,
+                    '}' => depth -= 1,
+                    ',' => return depth <= 0,
+                    _ => {}
+                }
+                false
+            })
+            .map(String::from)
+            .collect(),
+        ))
+    }
+}
@@ -8,10 +8,19 @@ pub enum ConfigError {
     Io(io::Error),
     Serde(serde_yaml::Error),
     Template(http::types::error::TemplateError),
-    Glob(globber::Error),
+    Glob(fs::glob::Error),
     Regex(pcre2::Error),
     NotADirectory(fs::cache::DirPathBufError),
     Lookback(fs::tail::ParseLookbackError),
+    WatchBackend(fs::cache::ParseWatchBackendError),
+    Multiline(&'static str),
+    Encoding(String),
+    LineFilter(&'static str),
+    Redaction(middleware::redact::ParseRedactionError),
+    TimestampFormat(middleware::timestamp::ParseFormatError),
+    Level(middleware::level::ParseLevelError),
+    Source(String),
+    Syslog(String),
 }
 
 impl Display for ConfigError {
@@ -33,6 +42,15 @@ impl Display for ConfigError {
             ConfigError::Regex(e) => write!(f, "{}", e),
             ConfigError::NotADirectory(e) => write!(f, "{}", e),
             ConfigError::Lookback(e) => write!(f, "{}", e),
+            ConfigError::WatchBackend(e) => write!(f, "{}", e),
+            ConfigError::Multiline(e) => write!(f, "invalid multiline rule: {}", e),
+            ConfigError::Encoding(e) => write!(f, "unknown encoding: {}", e),
+            ConfigError::LineFilter(e) => write!(f, "invalid line filter: {}", e),
+            ConfigError::Redaction(e) => write!(f, "{}", e),
+            ConfigError::TimestampFormat(e) => write!(f, "{}", e),
+            ConfigError::Level(e) => write!(f, "{}", e),
+            ConfigError::Source(e) => write!(f, "unknown source: {}", e),
+            ConfigError::Syslog(e) => write!(f, "invalid syslog listener: {}", e),
         }
     }
 }
@@ -55,8 +73,8 @@ impl From<http::types::error::TemplateError> for ConfigError {
     }
 }
 
-impl From<globber::Error> for ConfigError {
-    fn from(e: globber::Error) -> Self {
+impl From<fs::glob::Error> for ConfigError {
+    fn from(e: fs::glob::Error) -> Self {
         ConfigError::Glob(e)
     }
 }
@@ -78,3 +96,27 @@ impl From<fs::tail::ParseLookbackError> for ConfigError {
         ConfigError::Lookback(e)
     }
 }
+
+impl From<fs::cache::ParseWatchBackendError> for ConfigError {
+    fn from(e: fs::cache::ParseWatchBackendError) -> Self {
+        ConfigError::WatchBackend(e)
+    }
+}
+
+impl From<middleware::redact::ParseRedactionError> for ConfigError {
+    fn from(e: middleware::redact::ParseRedactionError) -> Self {
+        ConfigError::Redaction(e)
+    }
+}
+
+impl From<middleware::timestamp::ParseFormatError> for ConfigError {
+    fn from(e: middleware::timestamp::ParseFormatError) -> Self {
+        ConfigError::TimestampFormat(e)
+    }
+}
+
+impl From<middleware::level::ParseLevelError> for ConfigError {
+    fn from(e: middleware::level::ParseLevelError) -> Self {
+        ConfigError::Level(e)
+    }
+}
@@ -4,25 +4,43 @@ extern crate log;
 use std::convert::{TryFrom, TryInto};
 use std::ffi::CString;
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read};
 use std::path::PathBuf;
 use std::time::Duration;
 
 use flate2::Compression;
 
-use fs::rule::{GlobRule, RegexRule, Rules};
-use fs::tail::{DirPathBuf, Lookback};
+use fs::cache::{DirPathBufError, WatchBackend, DEFAULT_POLL_INTERVAL};
+use fs::encoding::{Encoding as Charset, EncodingRule};
+use fs::multiline::{Matcher, MultilineRule};
+use fs::rule::{GlobRule, OrderedRule, RegexRule, Rules};
+use fs::tail::{DirPathBuf, Lookback, ReadBudget, DEFAULT_READ_BUDGET_BYTES};
+use http::source::SOURCES;
 use http::types::request::{Encoding, RequestTemplate, Schema};
 use k8s::K8sEventLogConf;
+use middleware::filter::LineFilterRule;
+use middleware::json::JsonOptions;
+use middleware::level::{Level, MinLevelRule};
+use middleware::metadata::MetadataRule;
+use middleware::redact::{Detector, Mode, ParseRedactionError, RedactionRule};
+use middleware::timestamp::{Format as TimestampFormat, DEFAULT_MAX_SKEW};
+use syslog::source::{load_identity, Options as SyslogOptions};
 
 use crate::env::Config as EnvConfig;
 use crate::error::ConfigError;
-use crate::raw::Config as RawConfig;
+use crate::raw::{
+    Config as RawConfig, EncodingConfig, JsonConfig, LineFilterConfig, LogConfig as RawLogConfig,
+    MetadataConfig, MinLevelRuleConfig, MultilineConfig, RedactionRuleConfig, Rules as RawRules,
+    SyslogConfig, TimestampConfig,
+};
 
 pub mod env;
 pub mod error;
 pub mod raw;
 
+const DEFAULT_DB_PATH: &str = "/var/lib/logdna";
+const DEFAULT_TIMESTAMP_FORMATS: &[&str] = &["rfc3339", "syslog", "clf", "epoch_ms"];
+
 // Symbols that will be populated in the main.rs file
 extern "Rust" {
     static PKG_NAME: &'static str;
@@ -34,6 +52,7 @@ pub struct Config {
     pub http: HttpConfig,
     pub log: LogConfig,
     pub journald: JournaldConfig,
+    pub syslog: SyslogOptions,
 }
 
 #[derive(Debug)]
@@ -46,9 +65,28 @@ pub struct HttpConfig {
 #[derive(Debug)]
 pub struct LogConfig {
     pub dirs: Vec<DirPathBuf>,
+    pub pending_dirs: Vec<PathBuf>,
     pub rules: Rules,
     pub lookback: Lookback,
     pub log_k8s_events: K8sEventLogConf,
+    pub db_path: PathBuf,
+    pub multiline: Vec<MultilineRule>,
+    pub encodings: Vec<EncodingRule>,
+    pub metadata: Vec<MetadataRule>,
+    pub line_filters: Vec<LineFilterRule>,
+    pub redaction_rules: Vec<RedactionRule>,
+    pub redaction_salt: String,
+    pub json: Option<JsonOptions>,
+    pub timestamp_formats: Vec<TimestampFormat>,
+    pub timestamp_max_skew: Duration,
+    pub detect_levels: bool,
+    pub min_level: Option<Level>,
+    pub min_level_rules: Vec<MinLevelRule>,
+    pub partial_line_timeout: Option<Duration>,
+    pub watch_backend: WatchBackend,
+    pub poll_interval: Duration,
+    pub max_open_files: Option<usize>,
+    pub read_budget: ReadBudget,
 }
 
 #[derive(Debug)]
@@ -58,6 +96,27 @@ pub struct JournaldConfig {
 
 impl Config {
     pub fn new() -> Result<Self, ConfigError> {
+        let raw_config = Config::raw();
+
+        let mut tmp_config = raw_config.clone();
+        if let Some(ref mut key) = tmp_config.http.ingestion_key {
+            *key = "REDACTED".to_string();
+        }
+        if let Some(ref mut salt) = tmp_config.log.redact.as_mut().and_then(|r| r.salt.as_mut()) {
+            **salt = "REDACTED".to_string();
+        }
+        if let Some(ref mut password) = tmp_config.syslog.tls_password {
+            *password = "REDACTED".to_string();
+        }
+        if let Ok(yaml) = serde_yaml::to_string(&tmp_config) {
+            info!("current config: \n{}", yaml)
+        }
+
+        Config::try_from(raw_config)
+    }
+
+    /// Loads the config file and merges the env vars into it, without validating the result
+    pub fn raw() -> RawConfig {
         let env_config: EnvConfig = EnvConfig::parse();
         let raw_config = match RawConfig::parse(&env_config.config_file) {
             Ok(v) => v,
@@ -71,17 +130,7 @@ impl Config {
             }
         };
 
-        let raw_config = env_config.merge(raw_config);
-
-        let mut tmp_config = raw_config.clone();
-        if let Some(ref mut key) = tmp_config.http.ingestion_key {
-            *key = "REDACTED".to_string();
-        }
-        if let Ok(yaml) = serde_yaml::to_string(&tmp_config) {
-            info!("current config: \n{}", yaml)
-        }
-
-        Config::try_from(raw_config)
+        env_config.merge(raw_config)
     }
 }
 
@@ -176,27 +225,65 @@ impl TryFrom<RawConfig> for Config {
                 .ok_or(ConfigError::MissingField("http.body_size"))?,
         };
 
+        let log = LogConfig::try_from(raw.log)?;
+
+        let journald = JournaldConfig {
+            paths: raw.journald.paths.unwrap_or_default().into_iter().collect(),
+        };
+
+        let syslog = into_syslog_options(raw.syslog)?;
+
+        Ok(Config {
+            http,
+            log,
+            journald,
+            syslog,
+        })
+    }
+}
+
+impl TryFrom<RawLogConfig> for LogConfig {
+    type Error = ConfigError;
+
+    fn try_from(raw: RawLogConfig) -> Result<Self, Self::Error> {
+        let redact = raw.redact.unwrap_or_default();
+        // levels are detected once the section is set, unless it's disabled
+        let detect_levels = raw
+            .levels
+            .as_ref()
+            .is_some_and(|levels| levels.detect != Some(false));
+        let levels = raw.levels.unwrap_or_default();
+        // dirs that don't exist yet are watched until they are created
+        let mut pending_dirs = Vec::new();
+        let dirs = raw
+            .dirs
+            .into_iter()
+            // Filter off paths that are not directories and warn about them
+            .filter_map(|d| match d.clone().try_into() {
+                Ok(dir) => Some(dir),
+                Err(DirPathBufError::Io(ref e))
+                    if e.kind() == io::ErrorKind::NotFound && d.is_absolute() =>
+                {
+                    info!("{:?} doesn't exist yet, it's tailed once it's created", d);
+                    pending_dirs.push(d);
+                    None
+                }
+                Err(e) => {
+                    warn!("{}", e);
+                    None
+                }
+            })
+            .collect();
+
         let mut log = LogConfig {
-            dirs: raw
-                .log
-                .dirs
-                .into_iter()
-                // Filter off paths that are not directories and warn about them
-                .filter_map(|d| {
-                    d.try_into()
-                        .map_err(|e| {
-                            warn!("{}", e);
-                        })
-                        .ok()
-                })
-                .collect(),
+            dirs,
+            pending_dirs,
             rules: Rules::new(),
             lookback: raw
-                .log
                 .lookback
                 .map(|s| s.parse::<Lookback>())
                 .unwrap_or_else(|| Ok(Lookback::default()))?,
-            log_k8s_events: if let Some(s) = raw.log.log_k8s_events {
+            log_k8s_events: if let Some(s) = raw.log_k8s_events {
                 match s.parse::<K8sEventLogConf>() {
                     Ok(s) => s,
                     Err(e) => {
@@ -210,9 +297,90 @@ impl TryFrom<RawConfig> for Config {
             } else {
                 K8sEventLogConf::Never
             },
+            db_path: raw
+                .db_path
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_DB_PATH)),
+            multiline: raw
+                .multiline
+                .unwrap_or_default()
+                .into_iter()
+                .map(into_multiline_rule)
+                .collect::<Result<_, _>>()?,
+            encodings: raw
+                .encodings
+                .unwrap_or_default()
+                .into_iter()
+                .map(into_encoding_rule)
+                .collect::<Result<_, _>>()?,
+            metadata: raw
+                .metadata
+                .unwrap_or_default()
+                .into_iter()
+                .map(into_metadata_rule)
+                .collect::<Result<_, _>>()?,
+            line_filters: raw
+                .line_filters
+                .unwrap_or_default()
+                .into_iter()
+                .enumerate()
+                .map(into_line_filter_rule)
+                .collect::<Result<_, _>>()?,
+            redaction_rules: redact
+                .rules
+                .into_iter()
+                .enumerate()
+                .map(into_redaction_rule)
+                .collect::<Result<_, _>>()?,
+            redaction_salt: redact.salt.unwrap_or_default(),
+            json: raw.json.and_then(into_json_options),
+            timestamp_formats: raw
+                .timestamps
+                .as_ref()
+                .map(into_timestamp_formats)
+                .transpose()?
+                .unwrap_or_default(),
+            timestamp_max_skew: raw
+                .timestamps
+                .and_then(|timestamps| timestamps.max_skew)
+                .map(Duration::from_millis)
+                .unwrap_or(DEFAULT_MAX_SKEW),
+            detect_levels,
+            min_level: levels
+                .min_level
+                .map(|level| level.trim().parse::<Level>())
+                .transpose()?,
+            min_level_rules: levels
+                .rules
+                .unwrap_or_default()
+                .into_iter()
+                .map(into_min_level_rule)
+                .collect::<Result<_, _>>()?,
+            // partial lines are only sent once a timeout is set, a timeout of 0 disables it too
+            partial_line_timeout: raw
+                .partial_line_timeout
+                .filter(|timeout| *timeout > 0)
+                .map(Duration::from_millis),
+            watch_backend: raw
+                .watch_backend
+                .map(|s| s.parse::<WatchBackend>())
+                .unwrap_or_else(|| Ok(WatchBackend::default()))?,
+            poll_interval: raw
+                .poll_interval
+                .filter(|interval| *interval > 0)
+                .map(Duration::from_millis)
+                .unwrap_or(DEFAULT_POLL_INTERVAL),
+            max_open_files: raw.max_open_files.filter(|max| *max > 0),
+            read_budget: ReadBudget {
+                bytes: match raw.read_budget_bytes {
+                    Some(0) => None,
+                    Some(bytes) => Some(bytes),
+                    None => Some(DEFAULT_READ_BUDGET_BYTES),
+                },
+                lines: raw.read_budget_lines.filter(|lines| *lines > 0),
+            },
         };
 
-        if let Some(rules) = raw.log.include {
+        if let Some(rules) = raw.include {
             for glob in rules.glob {
                 log.rules.add_inclusion(GlobRule::new(&*glob)?)
             }
@@ -222,7 +390,7 @@ impl TryFrom<RawConfig> for Config {
             }
         }
 
-        if let Some(rules) = raw.log.exclude {
+        if let Some(rules) = raw.exclude {
             for glob in rules.glob {
                 log.rules.add_exclusion(GlobRule::new(&*glob)?)
             }
@@ -232,16 +400,226 @@ impl TryFrom<RawConfig> for Config {
             }
         }
 
-        let journald = JournaldConfig {
-            paths: raw.journald.paths.unwrap_or_default().into_iter().collect(),
-        };
+        for rule in raw.rules.unwrap_or_default() {
+            log.rules.add_ordered(rule.parse::<OrderedRule>()?)
+        }
 
-        Ok(Config {
-            http,
-            log,
-            journald,
-        })
+        // hashes without a secret salt can be reversed by hashing guesses
+        if log.redaction_salt.is_empty()
+            && log
+                .redaction_rules
+                .iter()
+                .any(|rule| rule.mode == Mode::Hash)
+        {
+            return Err(ParseRedactionError::Invalid("the hash mode requires a salt").into());
+        }
+
+        Ok(log)
+    }
+}
+
+fn into_syslog_options(raw: SyslogConfig) -> Result<SyslogOptions, ConfigError> {
+    let addrs = |addrs: Option<Vec<String>>| {
+        addrs
+            .unwrap_or_default()
+            .iter()
+            .map(|addr| {
+                addr.trim()
+                    .parse()
+                    .map_err(|_| ConfigError::Syslog(format!("{} is not an address", addr)))
+            })
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let mut options = SyslogOptions {
+        udp: addrs(raw.udp)?,
+        tcp: addrs(raw.tcp)?,
+        tls: addrs(raw.tls)?,
+        unix: raw.unix.unwrap_or_default(),
+        unix_stream: raw.unix_stream.unwrap_or_default(),
+        kmsg: raw.kmsg,
+        ..SyslogOptions::default()
+    };
+    if let Some(max) = raw.max_message_bytes.filter(|max| *max > 0) {
+        options.max_message_bytes = max;
+    }
+    if !options.tls.is_empty() {
+        let path = raw
+            .tls_identity
+            .ok_or(ConfigError::MissingField("syslog.tls_identity"))?;
+        options.identity = Some(
+            load_identity(&path, raw.tls_password.as_deref().unwrap_or_default())
+                .map_err(|e| ConfigError::Syslog(format!("{:?}: {}", path, e)))?,
+        );
+    }
+    Ok(options)
+}
+
+// Builds inclusion rules out of the globs and regexes of a config section
+fn into_inclusion_rules(raw: RawRules) -> Result<Rules, ConfigError> {
+    let mut rules = Rules::new();
+    for glob in raw.glob {
+        rules.add_inclusion(GlobRule::new(&*glob)?)
+    }
+
+    for regex in raw.regex {
+        rules.add_inclusion(RegexRule::new(&*regex)?)
+    }
+    Ok(rules)
+}
+
+fn into_multiline_rule(raw: MultilineConfig) -> Result<MultilineRule, ConfigError> {
+    let matcher = match (raw.start, raw.continuation, raw.indent.unwrap_or(false)) {
+        (Some(start), None, false) => Matcher::Start(pcre2::bytes::Regex::new(&start)?),
+        (None, Some(continuation), false) => {
+            Matcher::Continuation(pcre2::bytes::Regex::new(&continuation)?)
+        }
+        (None, None, true) => Matcher::Indent,
+        _ => {
+            return Err(ConfigError::Multiline(
+                "exactly one of start, continuation or indent has to be set",
+            ))
+        }
+    };
+
+    let mut rule = MultilineRule::new(into_inclusion_rules(raw.include)?, matcher);
+    if let Some(max_lines) = raw.max_lines {
+        rule.max_lines = max_lines;
+    }
+    if let Some(max_bytes) = raw.max_bytes {
+        rule.max_bytes = max_bytes;
+    }
+    if let Some(flush_timeout) = raw.flush_timeout {
+        rule.flush_timeout = Duration::from_millis(flush_timeout);
+    }
+    Ok(rule)
+}
+
+fn into_encoding_rule(raw: EncodingConfig) -> Result<EncodingRule, ConfigError> {
+    let encoding = Charset::for_label(raw.encoding.trim().as_bytes())
+        .ok_or(ConfigError::Encoding(raw.encoding))?;
+    Ok(EncodingRule {
+        rules: into_inclusion_rules(raw.include)?,
+        encoding,
+    })
+}
+
+fn into_metadata_rule(raw: MetadataConfig) -> Result<MetadataRule, ConfigError> {
+    let mut rule = MetadataRule::new(into_inclusion_rules(raw.include)?);
+    rule.app = raw.app;
+    rule.tags = raw.tags.unwrap_or_default();
+    rule.level = raw.level;
+    rule.env = raw.env;
+    rule.meta = raw.meta.unwrap_or_default().into_iter().collect();
+    Ok(rule)
+}
+
+fn into_line_filter_rule(
+    (idx, raw): (usize, LineFilterConfig),
+) -> Result<LineFilterRule, ConfigError> {
+    let regexes = |patterns: Option<Vec<String>>| {
+        patterns
+            .unwrap_or_default()
+            .iter()
+            .map(|pattern| pcre2::bytes::Regex::new(pattern))
+            .collect::<Result<Vec<_>, _>>()
+    };
+
+    let mut rule =
+        LineFilterRule::new(raw.name.unwrap_or_else(|| format!("line_filters[{}]", idx)));
+    rule.include = regexes(raw.include)?;
+    rule.exclude = regexes(raw.exclude)?;
+    if rule.include.is_empty() && rule.exclude.is_empty() {
+        return Err(ConfigError::LineFilter(
+            "at least one of include or exclude has to be set",
+        ));
+    }
+    rule.scope.files = raw.files.map(into_inclusion_rules).transpose()?;
+    rule.scope.apps = regexes(raw.apps)?;
+    rule.scope.sources = into_sources(raw.sources)?;
+    Ok(rule)
+}
+
+// returns the sources a rule is limited to, which have to be known
+fn into_sources(raw: Option<Vec<String>>) -> Result<Vec<String>, ConfigError> {
+    let sources = raw.unwrap_or_default();
+    match sources
+        .iter()
+        .find(|source| !SOURCES.contains(&source.as_str()))
+    {
+        Some(unknown) => Err(ConfigError::Source(unknown.clone())),
+        None => Ok(sources),
+    }
+}
+
+// Returns the options of the JSON parser, unless it's disabled. A section without `enabled`
+// enables the parser.
+fn into_json_options(raw: JsonConfig) -> Option<JsonOptions> {
+    if raw.enabled == Some(false) {
+        return None;
     }
+    let default = JsonOptions::default();
+    Some(JsonOptions {
+        max_bytes: raw.max_bytes.unwrap_or(default.max_bytes),
+        max_depth: raw.max_depth.unwrap_or(default.max_depth),
+        level_keys: raw.level_keys.unwrap_or(default.level_keys),
+        message_keys: raw.message_keys.unwrap_or(default.message_keys),
+        timestamp_keys: raw.timestamp_keys.unwrap_or(default.timestamp_keys),
+        app_keys: raw.app_keys.unwrap_or(default.app_keys),
+    })
+}
+
+// Returns the formats timestamps are extracted in, a section without formats extracts the
+// built-in ones
+fn into_timestamp_formats(raw: &TimestampConfig) -> Result<Vec<TimestampFormat>, ConfigError> {
+    match raw.formats {
+        Some(ref formats) => formats
+            .iter()
+            .map(|format| format.trim().parse::<TimestampFormat>())
+            .collect::<Result<_, _>>(),
+        None => DEFAULT_TIMESTAMP_FORMATS
+            .iter()
+            .map(|format| format.parse::<TimestampFormat>())
+            .collect(),
+    }
+    .map_err(Into::into)
+}
+
+fn into_min_level_rule(raw: MinLevelRuleConfig) -> Result<MinLevelRule, ConfigError> {
+    let mut rule = MinLevelRule::new(raw.min_level.trim().parse()?);
+    rule.scope.files = raw.files.map(into_inclusion_rules).transpose()?;
+    rule.scope.apps = raw
+        .apps
+        .unwrap_or_default()
+        .iter()
+        .map(|pattern| pcre2::bytes::Regex::new(pattern))
+        .collect::<Result<_, _>>()?;
+    rule.scope.sources = into_sources(raw.sources)?;
+    Ok(rule)
+}
+
+fn into_redaction_rule(
+    (idx, raw): (usize, RedactionRuleConfig),
+) -> Result<RedactionRule, ConfigError> {
+    let detector = match (raw.detector, raw.regex) {
+        (Some(detector), None) => detector.trim().parse::<Detector>()?,
+        (None, Some(regex)) => Detector::new(
+            raw.name.unwrap_or_else(|| format!("redact.rules[{}]", idx)),
+            pcre2::bytes::Regex::new(&regex)?,
+        ),
+        _ => {
+            return Err(ParseRedactionError::Invalid(
+                "exactly one of detector or regex has to be set",
+            )
+            .into())
+        }
+    };
+    Ok(RedactionRule {
+        detector,
+        mode: raw
+            .mode
+            .map(|mode| mode.parse::<Mode>())
+            .unwrap_or_else(|| Ok(Mode::default()))?,
+    })
 }
 
 pub fn get_hostname() -> Option<String> {
@@ -289,6 +667,7 @@ mod tests {
     use scopeguard::guard;
 
     use super::*;
+    use crate::env::{EnvList, GlobList};
 
     #[test]
     fn test_hostname() {
@@ -304,6 +683,325 @@ mod tests {
         assert!(Config::try_from(raw).is_ok());
     }
 
+    #[test]
+    fn test_multiline_rules() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("emptyingestionkey".to_string());
+        raw.log.multiline = Some(
+            serde_yaml::from_str(
+                r#"
+- include:
+    glob: ["/var/log/app/*.log"]
+  start: '^\d{4}-\d{2}-\d{2}'
+  max_lines: 100
+- include:
+    regex: ["python"]
+  indent: true
+  flush_timeout: 500
+"#,
+            )
+            .unwrap(),
+        );
+        let multiline = Config::try_from(raw.clone()).unwrap().log.multiline;
+        assert_eq!(multiline.len(), 2);
+        assert_eq!(multiline[0].max_lines, 100);
+        assert_eq!(multiline[1].flush_timeout, Duration::from_millis(500));
+
+        raw.log.multiline.as_mut().unwrap()[1].continuation = Some("^\\s".to_string());
+        assert!(matches!(
+            Config::try_from(raw),
+            Err(ConfigError::Multiline(_))
+        ));
+    }
+
+    #[test]
+    fn test_metadata_rules() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("emptyingestionkey".to_string());
+        raw.log.metadata = Some(
+            serde_yaml::from_str(
+                r#"
+- include:
+    regex: ['^/var/log/(?P<app>[^/]+)/']
+  app: '${app}'
+  tags: [web]
+  meta:
+    team: '${app}-team'
+- include:
+    glob: ["*/error.log"]
+  level: ERROR
+"#,
+            )
+            .unwrap(),
+        );
+        let metadata = Config::try_from(raw.clone()).unwrap().log.metadata;
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata[0].app, Some("${app}".to_string()));
+        assert_eq!(metadata[0].tags, vec!["web".to_string()]);
+        assert_eq!(
+            metadata[0].meta,
+            vec![("team".to_string(), "${app}-team".to_string())]
+        );
+        assert_eq!(metadata[1].level, Some("ERROR".to_string()));
+
+        raw.log.metadata.as_mut().unwrap()[0].include.regex = vec!["(?P<app".to_string()];
+        assert!(matches!(Config::try_from(raw), Err(ConfigError::Regex(_))));
+    }
+
+    #[test]
+    fn test_line_filters() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("emptyingestionkey".to_string());
+        raw.log.line_filters = Some(
+            serde_yaml::from_str(
+                r#"
+- name: health_checks
+  exclude: ['GET /healthz']
+  files:
+    glob: ["/var/log/nginx/*.log"]
+- include: ['ERROR|WARN']
+  apps: ['^api$']
+  sources: [journald]
+"#,
+            )
+            .unwrap(),
+        );
+        let line_filters = Config::try_from(raw.clone()).unwrap().log.line_filters;
+        assert_eq!(line_filters.len(), 2);
+        assert_eq!(line_filters[0].name, "health_checks");
+        assert!(line_filters[0].scope.files.is_some());
+        assert_eq!(line_filters[1].name, "line_filters[1]");
+        assert_eq!(line_filters[1].scope.apps.len(), 1);
+        assert_eq!(line_filters[1].scope.sources, vec!["journald"]);
+
+        raw.log.line_filters.as_mut().unwrap()[1].sources = Some(vec!["files".to_string()]);
+        assert!(matches!(
+            Config::try_from(raw.clone()),
+            Err(ConfigError::Source(_))
+        ));
+
+        raw.log.line_filters.as_mut().unwrap()[1].sources = None;
+        raw.log.line_filters.as_mut().unwrap()[1].include = None;
+        assert!(matches!(
+            Config::try_from(raw),
+            Err(ConfigError::LineFilter(_))
+        ));
+    }
+
+    #[test]
+    fn test_redaction_rules() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("emptyingestionkey".to_string());
+        raw.log.redact = Some(
+            serde_yaml::from_str(
+                r#"
+rules:
+  - detector: pan
+    mode: drop_line
+  - detector: email
+  - name: employee_id
+    regex: 'EMP-\d{6}'
+    mode: hash
+"#,
+            )
+            .unwrap(),
+        );
+        assert!(matches!(
+            Config::try_from(raw.clone()),
+            Err(ConfigError::Redaction(ParseRedactionError::Invalid(_)))
+        ));
+
+        raw.log.redact.as_mut().unwrap().salt = Some("salt".to_string());
+        let log = Config::try_from(raw.clone()).unwrap().log;
+        assert_eq!(log.redaction_salt, "salt");
+        assert_eq!(log.redaction_rules.len(), 3);
+        assert_eq!(log.redaction_rules[0].mode, Mode::DropLine);
+        assert_eq!(log.redaction_rules[1].detector.name(), "email");
+        assert_eq!(log.redaction_rules[1].mode, Mode::Mask);
+        assert_eq!(log.redaction_rules[2].detector.name(), "employee_id");
+
+        raw.log.redact.as_mut().unwrap().rules[1].detector = Some("ssn".to_string());
+        assert!(matches!(
+            Config::try_from(raw),
+            Err(ConfigError::Redaction(ParseRedactionError::Detector(_)))
+        ));
+    }
+
+    #[test]
+    fn test_json_options() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("emptyingestionkey".to_string());
+        assert_eq!(Config::try_from(raw.clone()).unwrap().log.json, None);
+
+        raw.log.json = Some(
+            serde_yaml::from_str(
+                r#"
+max_depth: 4
+message_keys: [text]
+"#,
+            )
+            .unwrap(),
+        );
+        let json = Config::try_from(raw.clone()).unwrap().log.json.unwrap();
+        assert_eq!(json.max_depth, 4);
+        assert_eq!(json.message_keys, vec!["text".to_string()]);
+        assert_eq!(json.level_keys, JsonOptions::default().level_keys);
+
+        raw.log.json.as_mut().unwrap().enabled = Some(false);
+        assert_eq!(Config::try_from(raw).unwrap().log.json, None);
+    }
+
+    #[test]
+    fn test_timestamp_formats() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("emptyingestionkey".to_string());
+        assert!(Config::try_from(raw.clone())
+            .unwrap()
+            .log
+            .timestamp_formats
+            .is_empty());
+
+        raw.log.timestamps = Some(TimestampConfig::default());
+        let log = Config::try_from(raw.clone()).unwrap().log;
+        assert_eq!(log.timestamp_formats.len(), 4);
+        assert_eq!(log.timestamp_max_skew, DEFAULT_MAX_SKEW);
+
+        raw.log.timestamps = Some(
+            serde_yaml::from_str(
+                r#"
+formats: [clf, "%d.%m.%Y %H:%M:%S"]
+max_skew: 1000
+"#,
+            )
+            .unwrap(),
+        );
+        let log = Config::try_from(raw.clone()).unwrap().log;
+        assert!(matches!(log.timestamp_formats[0], TimestampFormat::Clf(_)));
+        assert!(matches!(
+            log.timestamp_formats[1],
+            TimestampFormat::Strptime(_)
+        ));
+        assert_eq!(log.timestamp_max_skew, Duration::from_millis(1000));
+
+        raw.log.timestamps.as_mut().unwrap().formats = Some(vec!["iso".to_string()]);
+        assert!(matches!(
+            Config::try_from(raw),
+            Err(ConfigError::TimestampFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_levels() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("emptyingestionkey".to_string());
+        let log = Config::try_from(raw.clone()).unwrap().log;
+        assert!(!log.detect_levels);
+        assert_eq!(log.min_level, None);
+
+        raw.log.levels = Some(
+            serde_yaml::from_str(
+                r#"
+min_level: info
+rules:
+  - min_level: error
+    files:
+      glob: ["/var/log/nginx/*.log"]
+  - min_level: warning
+    apps: ['^kubelet$']
+  - min_level: notice
+    sources: [journald, syslog]
+"#,
+            )
+            .unwrap(),
+        );
+        let log = Config::try_from(raw.clone()).unwrap().log;
+        assert!(log.detect_levels);
+        assert_eq!(log.min_level, Some(Level::Info));
+        assert_eq!(log.min_level_rules.len(), 3);
+        assert!(log.min_level_rules[0].scope.files.is_some());
+        assert_eq!(log.min_level_rules[1].min_level, Level::Warn);
+        assert_eq!(
+            log.min_level_rules[2].scope.sources,
+            vec!["journald", "syslog"]
+        );
+
+        raw.log.levels.as_mut().unwrap().detect = Some(false);
+        assert!(!Config::try_from(raw.clone()).unwrap().log.detect_levels);
+
+        raw.log.levels.as_mut().unwrap().min_level = Some("loud".to_string());
+        assert!(matches!(Config::try_from(raw), Err(ConfigError::Level(_))));
+    }
+
+    #[test]
+    fn test_syslog_options() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("emptyingestionkey".to_string());
+        assert!(Config::try_from(raw.clone()).unwrap().syslog.is_empty());
+
+        raw.syslog.udp = Some(vec!["0.0.0.0:514".to_string()]);
+        raw.syslog.tcp = Some(vec!["127.0.0.1:601".to_string(), "[::1]:601".to_string()]);
+        let syslog = Config::try_from(raw.clone()).unwrap().syslog;
+        assert_eq!(syslog.udp.len(), 1);
+        assert_eq!(syslog.tcp[1].port(), 601);
+
+        raw.syslog.tls = Some(vec!["0.0.0.0:6514".to_string()]);
+        assert!(matches!(
+            Config::try_from(raw.clone()),
+            Err(ConfigError::MissingField(_))
+        ));
+
+        raw.syslog.tls = None;
+        raw.syslog.udp = None;
+        raw.syslog.tcp = None;
+        raw.syslog.unix = Some(vec![PathBuf::from("/dev/log")]);
+        let syslog = Config::try_from(raw.clone()).unwrap().syslog;
+        assert!(!syslog.is_empty());
+        assert_eq!(syslog.unix, vec![PathBuf::from("/dev/log")]);
+
+        raw.syslog.unix = None;
+        raw.syslog.kmsg = Some(PathBuf::from("/dev/kmsg"));
+        let syslog = Config::try_from(raw.clone()).unwrap().syslog;
+        assert!(!syslog.is_empty());
+        assert_eq!(syslog.kmsg, Some(PathBuf::from("/dev/kmsg")));
+
+        raw.syslog.udp = Some(vec!["localhost".to_string()]);
+        assert!(matches!(Config::try_from(raw), Err(ConfigError::Syslog(_))));
+    }
+
+    #[test]
+    fn test_ordered_rules() {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("emptyingestionkey".to_string());
+        raw.log.rules = Some(
+            "/var/log/app/**,!*.{debug,trace}.log,*/critical.debug.log"
+                .parse::<GlobList>()
+                .unwrap()
+                .0,
+        );
+        let rules = Config::try_from(raw.clone()).unwrap().log.rules;
+        assert_eq!(rules.ordered_list().len(), 3);
+        assert!(rules.ordered_list()[1].is_negated());
+        assert!(!rules
+            .passes(&PathBuf::from("/var/log/app/app.trace.log"))
+            .is_ok());
+        assert!(rules
+            .passes(&PathBuf::from("/var/log/app/critical.debug.log"))
+            .is_ok());
+
+        raw.log.rules = Some(vec!["!/var/log/@(app".to_string()]);
+        assert!(matches!(Config::try_from(raw), Err(ConfigError::Glob(_))));
+
+        // only glob lists keep the commas of braces, escaped braces don't count
+        assert_eq!(
+            r"/a/\{*,/b/{c,d}.log,/e".parse::<GlobList>().unwrap().0,
+            vec![r"/a/\{*", "/b/{c,d}.log", "/e"]
+        );
+        assert_eq!(
+            r"/a/\{.*,/b/.*".parse::<EnvList<String>>().unwrap().0,
+            vec![r"/a/\{.*", "/b/.*"]
+        );
+    }
+
     #[test]
     fn e2e() {
         let _ = remove_file("test.yaml");
@@ -320,16 +1018,21 @@ mod tests {
 
             EnvConfig::ingestion_key_vars()
                 .iter()
-                .for_each(env::remove_var);
+                .for_each(|var| env::remove_var(var));
             env::set_var(&EnvConfig::config_file_vars()[0], "test.yaml");
             assert!(Config::new().is_err());
 
+            // the example identity of the env config tests doesn't exist
+            EnvConfig::syslog_tls_vars()
+                .iter()
+                .for_each(|var| env::remove_var(var));
+
             env::set_var(&EnvConfig::ingestion_key_vars()[0], "ingestion_key_test");
             assert!(Config::new().is_ok());
 
             EnvConfig::inclusion_rules_vars()
                 .iter()
-                .for_each(env::remove_var);
+                .for_each(|var| env::remove_var(var));
             let old_len = Config::new().unwrap().log.rules.inclusion_list().len();
             env::set_var(&EnvConfig::inclusion_rules_vars()[0], "test.log,test2.log");
             assert_eq!(
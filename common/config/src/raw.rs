@@ -4,6 +4,7 @@ use http::types::params::Params;
 
 use crate::error::ConfigError;
 use crate::get_hostname;
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 
@@ -12,6 +13,8 @@ pub struct Config {
     pub http: HttpConfig,
     pub log: LogConfig,
     pub journald: JournaldConfig,
+    #[serde(default)]
+    pub syslog: SyslogConfig,
 }
 
 impl Config {
@@ -50,8 +53,161 @@ pub struct LogConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub exclude: Option<Rules>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub rules: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub lookback: Option<String>,
     pub log_k8s_events: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub db_path: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multiline: Option<Vec<MultilineConfig>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encodings: Option<Vec<EncodingConfig>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Vec<MetadataConfig>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_filters: Option<Vec<LineFilterConfig>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redact: Option<RedactConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub json: Option<JsonConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamps: Option<TimestampConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub levels: Option<LevelConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partial_line_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub watch_backend: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poll_interval: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_open_files: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_budget_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_budget_lines: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct MultilineConfig {
+    pub include: Rules,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub continuation: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub indent: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_lines: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flush_timeout: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct EncodingConfig {
+    pub include: Rules,
+    pub encoding: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct MetadataConfig {
+    pub include: Rules,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub env: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct LineFilterConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<Rules>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub apps: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sources: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Eq, PartialEq, Clone)]
+pub struct RedactConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub salt: Option<String>,
+    #[serde(default)]
+    pub rules: Vec<RedactionRuleConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct RedactionRuleConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detector: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Eq, PartialEq, Clone)]
+pub struct JsonConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level_keys: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_keys: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp_keys: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_keys: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Eq, PartialEq, Clone)]
+pub struct TimestampConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub formats: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_skew: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Eq, PartialEq, Clone)]
+pub struct LevelConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detect: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_level: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rules: Option<Vec<MinLevelRuleConfig>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct MinLevelRuleConfig {
+    pub min_level: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<Rules>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub apps: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sources: Option<Vec<String>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
@@ -60,9 +216,33 @@ pub struct JournaldConfig {
     pub paths: Option<Vec<PathBuf>>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, Default, Eq, PartialEq, Clone)]
+pub struct SyslogConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub udp: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_identity: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unix: Option<Vec<PathBuf>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unix_stream: Option<Vec<PathBuf>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kmsg: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_message_bytes: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Eq, PartialEq, Clone)]
 pub struct Rules {
+    #[serde(default)]
     pub glob: Vec<String>,
+    #[serde(default)]
     pub regex: Vec<String>,
 }
 
@@ -72,6 +252,7 @@ impl Default for Config {
             http: HttpConfig::default(),
             log: LogConfig::default(),
             journald: JournaldConfig::default(),
+            syslog: SyslogConfig::default(),
         }
     }
 }
@@ -120,8 +301,24 @@ impl Default for LogConfig {
                 ],
                 regex: Vec::new(),
             }),
+            rules: None,
             lookback: None,
             log_k8s_events: None,
+            db_path: None,
+            multiline: None,
+            encodings: None,
+            metadata: None,
+            line_filters: None,
+            redact: None,
+            json: None,
+            timestamps: None,
+            levels: None,
+            partial_line_timeout: None,
+            watch_backend: None,
+            poll_interval: None,
+            max_open_files: None,
+            read_budget_bytes: None,
+            read_budget_lines: None,
         }
     }
 }
@@ -1,6 +1,7 @@
+use crate::cache::watch::WatchDescriptor;
 use crate::cache::Children;
+use crate::identity::FileId;
 use crate::rule::Rules;
-use inotify::WatchDescriptor;
 use std::ffi::OsString;
 use std::fs::File;
 use std::path::PathBuf;
@@ -14,8 +15,10 @@ pub enum Entry<T> {
         name: OsString,
         parent: EntryPtr<T>,
         wd: WatchDescriptor,
+        id: FileId,
         data: T,
-        file_handle: File,
+        // closed while the file isn't used if the number of open files is limited
+        file_handle: Option<File>,
     },
     Dir {
         name: OsString,
@@ -93,7 +96,14 @@ impl<T> Entry<T> {
     pub fn file_handle(&self) -> Option<&File> {
         match self {
             Entry::Dir { .. } | Entry::Symlink { .. } => None,
-            Entry::File { file_handle, .. } => Some(file_handle),
+            Entry::File { file_handle, .. } => file_handle.as_ref(),
+        }
+    }
+
+    pub fn file_id(&self) -> Option<FileId> {
+        match self {
+            Entry::Dir { .. } | Entry::Symlink { .. } => None,
+            Entry::File { id, .. } => Some(*id),
         }
     }
 }
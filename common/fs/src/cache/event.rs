@@ -14,4 +14,9 @@ where
     Write(EntryPtr<T>),
     /// A file was deleted
     Delete(EntryPtr<T>),
+    /// A file was renamed to another tracked path, e.g. by a log rotation
+    Rotate(EntryPtr<T>),
+    /// A path now refers to a different file than the one that was tracked,
+    /// the old file is reported by a preceding Delete
+    Replace(EntryPtr<T>),
 }
@@ -0,0 +1,89 @@
+use crate::cache::entry::EntryPtr;
+use hashbrown::HashMap;
+use std::collections::BTreeMap;
+
+/// Tracks which file entries have an open handle, ordered from least to most recently used
+#[derive(Debug)]
+pub struct OpenFiles<T> {
+    limit: Option<usize>,
+    tick: u64,
+    ticks: HashMap<EntryPtr<T>, u64>,
+    order: BTreeMap<u64, EntryPtr<T>>,
+}
+
+impl<T> OpenFiles<T> {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            tick: 0,
+            ticks: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    /// Marks the handle of an entry as the most recently used one
+    pub fn touch(&mut self, entry: EntryPtr<T>) {
+        self.tick += 1;
+        if let Some(tick) = self.ticks.insert(entry, self.tick) {
+            self.order.remove(&tick);
+        }
+        self.order.insert(self.tick, entry);
+    }
+
+    /// Forgets about the handle of an entry, e.g. once it's closed
+    pub fn remove(&mut self, entry: EntryPtr<T>) {
+        if let Some(tick) = self.ticks.remove(&entry) {
+            self.order.remove(&tick);
+        }
+    }
+
+    /// Returns the number of handles above the limit
+    pub fn excess(&self) -> usize {
+        match self.limit {
+            Some(limit) => self.ticks.len().saturating_sub(limit),
+            None => 0,
+        }
+    }
+
+    /// Returns the entries with an open handle, least recently used first
+    pub fn lru(&self) -> Vec<EntryPtr<T>> {
+        self.order.values().copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::entry::Entry;
+    use std::ptr::NonNull;
+
+    #[test]
+    fn lru_order() {
+        let entries = (0..3)
+            .map(|i| {
+                Box::new(Entry::<()>::Dir {
+                    name: i.to_string().into(),
+                    parent: None,
+                    children: HashMap::new(),
+                    wd: crate::cache::watch::WatchDescriptor::Poll(i),
+                })
+            })
+            .collect::<Vec<_>>();
+        let ptrs = entries
+            .iter()
+            .map(|entry| NonNull::from(entry.as_ref()))
+            .collect::<Vec<_>>();
+
+        let mut open_files = OpenFiles::new(Some(2));
+        ptrs.iter().for_each(|ptr| open_files.touch(*ptr));
+        assert_eq!(open_files.excess(), 1);
+        assert_eq!(open_files.lru(), ptrs);
+
+        open_files.touch(ptrs[0]);
+        assert_eq!(open_files.lru(), vec![ptrs[1], ptrs[2], ptrs[0]]);
+
+        open_files.remove(ptrs[1]);
+        assert_eq!(open_files.excess(), 0);
+        assert_eq!(open_files.lru(), vec![ptrs[2], ptrs[0]]);
+    }
+}
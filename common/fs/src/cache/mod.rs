@@ -1,33 +1,82 @@
 use crate::cache::entry::{Entry, EntryPtr};
 use crate::cache::event::Event;
-use crate::cache::watch::{WatchEvent, Watcher};
-use crate::rule::{GlobRule, Rules, Status};
+use crate::cache::handles::OpenFiles;
+use crate::cache::watch::{WatchDescriptor, WatchEvent, Watcher};
+use crate::identity::FileId;
+use crate::rule::{Explanation, GlobRule, Rules, Status};
 use futures::{Stream, StreamExt};
 use hashbrown::hash_map::Entry as HashMapEntry;
 use hashbrown::HashMap;
-use inotify::WatchDescriptor;
 use metrics::Metrics;
 use std::ffi::OsString;
 use std::fmt;
 use std::fs::read_dir;
-use std::fs::OpenOptions;
+use std::fs::{File, OpenOptions};
 use std::iter::FromIterator;
 use std::ops::{Deref, DerefMut};
+use std::os::unix::fs::MetadataExt;
 use std::path::{Component, PathBuf};
 use std::ptr::NonNull;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 pub mod dir_path;
 pub mod entry;
 pub mod event;
 pub use dir_path::{DirPathBuf, DirPathBufError};
+pub use watch::{ParseWatchBackendError, WatchBackend, DEFAULT_POLL_INTERVAL};
 
+mod handles;
 mod watch;
 
+// the delays before reading watch events again after the first and after repeated errors
+const WATCH_ERROR_DELAY: Duration = Duration::from_millis(100);
+const MAX_WATCH_ERROR_DELAY: Duration = Duration::from_secs(30);
+
 type Children<T> = HashMap<OsString, Box<Entry<T>>>;
 type Symlinks<T> = HashMap<PathBuf, Vec<EntryPtr<T>>>;
 type WatchDescriptors<T> = HashMap<WatchDescriptor, Vec<EntryPtr<T>>>;
 
+/// Settings of the cache that can't change once it's created
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// How paths are watched for changes
+    pub backend: WatchBackend,
+    /// The time between two scans of the paths that are watched by polling
+    pub poll_interval: Duration,
+    /// The maximum number of file handles kept open, unlimited if None
+    pub max_open_files: Option<usize>,
+    /// Directories that don't exist yet, they are watched through their nearest existing
+    /// ancestor and tailed once they are created
+    pub pending_dirs: Vec<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            backend: WatchBackend::default(),
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            max_open_files: None,
+            pending_dirs: Vec::new(),
+        }
+    }
+}
+
+/// Why a path is or isn't tracked, see FileSystem::explain
+#[derive(Debug)]
+pub struct PathExplanation<'a> {
+    /// The inclusion, exclusion and ordered rules matching the path
+    pub rules: Explanation<'a>,
+    /// True if the path is in one of the initial dirs or is one of their ancestors
+    pub initial_dir: bool,
+    /// The symlinks pointing to the path or to a dir containing it
+    pub symlinks: Vec<PathBuf>,
+    /// The dir containing the path that isn't watched since it's excluded as a whole
+    pub pruned: Option<PathBuf>,
+    /// True if the path is tracked once it exists
+    pub tracked: bool,
+}
+
 pub struct FileSystem<T>
 where
     T: Clone + std::fmt::Debug,
@@ -39,9 +88,15 @@ where
     watch_descriptors: WatchDescriptors<T>,
 
     master_rules: Rules,
+    initial_dirs: Vec<PathBuf>,
     initial_dir_rules: Rules,
 
     initial_events: Vec<Event<T>>,
+
+    open_files: OpenFiles<T>,
+
+    // entries removed from the tree are kept alive until the events referencing them are handled
+    removed: Vec<Box<Entry<T>>>,
 }
 
 impl<'a, T: 'a + Default> FileSystem<T>
@@ -49,12 +104,17 @@ where
     T: Clone + std::fmt::Debug,
 {
     pub fn new(initial_dirs: Vec<DirPathBuf>, rules: Rules) -> Self {
+        Self::with_options(initial_dirs, rules, Options::default())
+    }
+
+    pub fn with_options(initial_dirs: Vec<DirPathBuf>, rules: Rules, options: Options) -> Self {
         initial_dirs.iter().for_each(|path| {
             if !path.is_dir() {
                 panic!("initial dirs must be dirs")
             }
         });
-        let mut watcher = Watcher::new().expect("unable to initialize inotify");
+        let mut watcher = Watcher::new(options.backend, options.poll_interval)
+            .expect("unable to initialize inotify");
 
         let root = Box::new(Entry::Dir {
             name: "/".into(),
@@ -63,9 +123,15 @@ where
             wd: watcher.watch("/").expect("unable to watch /"),
         });
 
+        let initial_dirs = initial_dirs
+            .into_iter()
+            .map(|path| -> PathBuf { path.into() })
+            .chain(options.pending_dirs)
+            .collect::<Vec<_>>();
+
         let mut initial_dir_rules = Rules::new();
         for path in initial_dirs.iter() {
-            append_rules(&mut initial_dir_rules, path.as_ref().into());
+            append_rules(&mut initial_dir_rules, path.clone());
         }
 
         let mut fs = Self {
@@ -73,18 +139,19 @@ where
             symlinks: Symlinks::new(),
             watch_descriptors: WatchDescriptors::new(),
             master_rules: rules,
+            initial_dirs: initial_dirs.clone(),
             initial_dir_rules,
             watcher,
             initial_events: Vec::new(),
+            open_files: OpenFiles::new(options.max_open_files),
+            removed: Vec::new(),
         };
 
         let root = EntryPtr::from(fs.root.deref_mut());
         fs.register(root);
 
-        for dir in initial_dirs
-            .into_iter()
-            .map(|path| -> PathBuf { path.into() })
-        {
+        for dir in initial_dirs {
+            // a dir that doesn't exist yet is picked up by the watch of it's nearest ancestor
             let mut path_cpy: PathBuf = dir.clone();
             loop {
                 if !path_cpy.exists() {
@@ -95,7 +162,7 @@ where
                 }
             }
 
-            for path in recursive_scan(&dir) {
+            for path in recursive_scan(&dir, &|dir| fs.is_pruned(dir)) {
                 let mut events = Vec::new();
                 fs.insert(&path, &mut events);
                 for event in events {
@@ -141,7 +208,34 @@ where
             acc
         };
 
-        let events = events_stream.into_stream().map(move |event| {
+        // an error that persists would be returned again right away, so reading is delayed longer
+        // after every error in a row which also limits how often it's logged
+        let mut error_delay = None;
+        let events_stream = events_stream.into_stream().then(move |event| {
+            let delay = match event {
+                Ok(_) => {
+                    error_delay = None;
+                    None
+                }
+                Err(ref e) => {
+                    let delay = error_delay
+                        .map_or(WATCH_ERROR_DELAY, |delay: Duration| delay * 2)
+                        .min(MAX_WATCH_ERROR_DELAY);
+                    error!("error reading watch events, retrying in {:?}: {}", delay, e);
+                    error_delay = Some(delay);
+                    Some(delay)
+                }
+            };
+            async move {
+                if let Some(delay) = delay {
+                    tokio::time::delay_for(delay).await;
+                }
+                event
+            }
+        });
+
+        let opener = fs.clone();
+        let events = events_stream.map(move |event| {
             let fs = fs.clone();
             {
                 let mut acc = Vec::new();
@@ -153,17 +247,30 @@ where
                             .process(event, &mut acc);
                         futures::stream::iter(acc)
                     }
-                    _ => panic!("Inotify error"),
+                    // the error was logged before the delay
+                    Err(_) => futures::stream::iter(acc),
                 }
             }
         });
 
-        Ok(futures::stream::iter(initial_events).chain(events.flatten()))
+        // files are handed out with an open handle even if their handle was closed in between,
+        // consumers like the tailer lock the cache too so this has to wait for them
+        Ok(futures::stream::iter(initial_events)
+            .chain(events.flatten())
+            .map(move |event| {
+                opener
+                    .lock()
+                    .expect("couldn't lock filesystem cache")
+                    .open(&event);
+                event
+            }))
     }
 
     // handles inotify events and may produce Event(s) that are return upstream through sender
     fn process(&mut self, watch_event: WatchEvent, events: &mut Vec<Event<T>>) {
         Metrics::fs().increment_events();
+        // the events of the last inotify event have been consumed by now
+        self.removed.clear();
 
         debug!("handling inotify event {:#?}", watch_event);
 
@@ -237,10 +344,171 @@ where
                     self.process_delete(&from_wd, from_name, events);
                 }
             }
-            WatchEvent::Overflow => panic!("overflowed kernel queue!"),
+            WatchEvent::Overflow => {
+                Metrics::fs().increment_overflows();
+                warn!("inotify queue overflowed, rescanning watched directories");
+                self.rescan(events);
+            }
         };
     }
 
+    // makes sure the file an event refers to has an open handle
+    fn open(&mut self, event: &Event<T>) {
+        match event {
+            Event::Initialize(entry_ptr)
+            | Event::New(entry_ptr)
+            | Event::Replace(entry_ptr)
+            | Event::Write(entry_ptr)
+            | Event::Rotate(entry_ptr) => self.open_file(*entry_ptr),
+            Event::Delete(_) => {}
+        }
+    }
+
+    /// Reopens the handle of a file entry if it was closed and closes the least recently used
+    /// handles above the limit
+    pub fn open_file(&mut self, entry_ptr: EntryPtr<T>) {
+        // entries dropped later in the same batch have their handle closed with the graveyard
+        if self
+            .removed
+            .iter()
+            .any(|entry| EntryPtr::from(entry.deref()) == entry_ptr)
+        {
+            return;
+        }
+
+        let path = self.resolve_direct_path(unsafe { entry_ptr.as_ref() });
+        if let Entry::File {
+            id, file_handle, ..
+        } = unsafe { &mut *entry_ptr.as_ptr() }
+        {
+            if file_handle.is_none() {
+                // the path is only trusted if it still leads to the same file
+                match File::open(&path).and_then(|file| Ok((FileId::from(&file.metadata()?), file)))
+                {
+                    Ok((reopened_id, file)) if reopened_id == *id => *file_handle = Some(file),
+                    // the events of the file are skipped until it's handle is open again
+                    Ok(_) => {
+                        Metrics::fs().increment_failed_reopens();
+                        warn!("{:?} was replaced while it's handle was closed", path);
+                        return;
+                    }
+                    Err(e) => {
+                        Metrics::fs().increment_failed_reopens();
+                        warn!("unable to reopen {:?}: {}", path, e);
+                        return;
+                    }
+                }
+            }
+            self.open_files.touch(entry_ptr);
+            self.evict(entry_ptr);
+        }
+    }
+
+    // closes the least recently used handles above the limit, files that were deleted while open
+    // keep their handle until their delete event is handled since they can't be reopened
+    fn evict(&mut self, keep: EntryPtr<T>) {
+        let mut excess = self.open_files.excess();
+        for entry_ptr in self.open_files.lru() {
+            if excess == 0 {
+                break;
+            }
+            if entry_ptr == keep {
+                continue;
+            }
+
+            if let Entry::File { file_handle, .. } = unsafe { &mut *entry_ptr.as_ptr() } {
+                let deleted = file_handle
+                    .as_ref()
+                    .and_then(|file| file.metadata().ok())
+                    .is_some_and(|metadata| metadata.nlink() == 0);
+                if deleted {
+                    continue;
+                }
+                *file_handle = None;
+            }
+            self.open_files.remove(entry_ptr);
+            excess -= 1;
+        }
+    }
+
+    // reconciles the cache with the filesystem after events were lost, entries of files that
+    // still exist are kept so the state attached to them survives
+    fn rescan(&mut self, events: &mut Vec<Event<T>>) {
+        let scanned = self
+            .initial_dirs
+            .iter()
+            .flat_map(|dir| recursive_scan(dir, &|dir| self.is_pruned(dir)))
+            .filter(|path| self.passes(path))
+            .collect::<Vec<_>>();
+
+        let mut vanished = Vec::new();
+        let mut ids = HashMap::new();
+        for entry_ptr in self.entries() {
+            let entry = unsafe { entry_ptr.as_ref() };
+            let path = self.resolve_direct_path(entry);
+            if path.symlink_metadata().is_err() {
+                vanished.push(path);
+            } else if let Some(id) = entry.file_id() {
+                ids.insert(id, path);
+            }
+        }
+
+        // a file that vanished from the cache but exists at an untracked path was moved
+        let mut moved = HashMap::new();
+        for path in scanned.iter() {
+            if let Ok(metadata) = path.symlink_metadata() {
+                let id = FileId::from(&metadata);
+                if ids.get(&id) != Some(path) {
+                    moved.insert(id, path.clone());
+                }
+            }
+        }
+        // children go first so files in a moved dir are found before the dir is removed
+        for from in vanished.into_iter().rev() {
+            let to = match self
+                .lookup(&from)
+                .and_then(|entry_ptr| moved.remove(&unsafe { entry_ptr.as_ref() }.file_id()?))
+            {
+                Some(to) => to,
+                None => {
+                    self.remove(&from, events);
+                    continue;
+                }
+            };
+            self.rename(&from, &to, events);
+        }
+
+        for path in scanned.iter() {
+            self.insert(path, events);
+        }
+
+        // files that were already tracked may have been written to while events were lost
+        for entry_ptr in self.entries() {
+            let is_new = events.iter().any(|event| match event {
+                Event::New(ptr) | Event::Replace(ptr) | Event::Rotate(ptr) => *ptr == entry_ptr,
+                _ => false,
+            });
+            if let Entry::File { .. } = unsafe { entry_ptr.as_ref() } {
+                if !is_new {
+                    events.push(Event::Write(entry_ptr));
+                }
+            }
+        }
+    }
+
+    // returns all entries of the cache, parents before their children
+    fn entries(&self) -> Vec<EntryPtr<T>> {
+        let mut entries = Vec::new();
+        let mut stack = vec![EntryPtr::from(self.root.deref())];
+        while let Some(entry_ptr) = stack.pop() {
+            entries.push(entry_ptr);
+            if let Entry::Dir { children, .. } = unsafe { entry_ptr.as_ref() } {
+                stack.extend(children.values().map(|child| EntryPtr::from(child.deref())));
+            }
+        }
+        entries
+    }
+
     fn process_create(
         &mut self,
         watch_descriptor: &WatchDescriptor,
@@ -266,7 +534,7 @@ where
 
         if let Some(new_entry) = self.insert(&path, events) {
             if let Entry::Dir { .. } = unsafe { new_entry.as_ref() } {
-                for new_path in recursive_scan(&path) {
+                for new_path in recursive_scan(&path, &|dir| self.is_pruned(dir)) {
                     self.insert(&new_path, events);
                 }
             }
@@ -418,6 +686,11 @@ where
             return None;
         }
 
+        if path.is_dir() && self.is_pruned(path) {
+            info!("ignoring excluded directory {:?}", path);
+            return None;
+        }
+
         if !(path.exists() || path.read_link().is_ok()) {
             warn!("attempted to insert non existant path {:?}", path);
             return None;
@@ -436,6 +709,20 @@ where
 
         let children = unsafe { (&mut *parent.as_ptr()).children_mut().unwrap() };
 
+        // A tracked file whose path now points to a different file, e.g. another file was moved
+        // over it, is dropped so the new file is tracked from the start
+        let replaced = match children.get(&component) {
+            Some(entry) => match (entry.file_id(), path.symlink_metadata()) {
+                (Some(id), Ok(metadata)) => id != FileId::from(&metadata),
+                _ => false,
+            },
+            None => false,
+        };
+        if replaced {
+            info!("{:?} was replaced by a different file", path);
+            self.remove(path, events);
+        }
+
         // Ok = symlink, Err = real path
 
         Some(match children.entry(component.clone()) {
@@ -471,6 +758,18 @@ where
                     EntryPtr::from((*v.insert(symlink)).deref())
                 }
                 Err(_) => {
+                    let (file_handle, id) =
+                        match OpenOptions::new().read(true).open(path).and_then(|file| {
+                            let id = FileId::from(&file.metadata()?);
+                            Ok((file, id))
+                        }) {
+                            Ok(v) => v,
+                            Err(e) => {
+                                error!("error opening {:?}: {}", path, e);
+                                return None;
+                            }
+                        };
+
                     let wd = match self.watcher.watch(path) {
                         Ok(wd) => wd,
                         Err(e) => {
@@ -483,14 +782,23 @@ where
                         name: component,
                         parent,
                         wd,
+                        id,
                         data: T::default(),
-                        file_handle: OpenOptions::new().read(true).open(path).unwrap(),
+                        file_handle: Some(file_handle),
                     });
 
                     self.register(EntryPtr::from(file.deref()));
 
-                    events.push(Event::New(EntryPtr::from(file.deref())));
-                    EntryPtr::from((*v.insert(file)).deref())
+                    let file_ptr = EntryPtr::from(file.deref());
+                    events.push(if replaced {
+                        Event::Replace(file_ptr)
+                    } else {
+                        Event::New(file_ptr)
+                    });
+                    let file_ptr = EntryPtr::from((*v.insert(file)).deref());
+                    self.open_files.touch(file_ptr);
+                    self.evict(file_ptr);
+                    file_ptr
                 }
             },
         })
@@ -552,7 +860,7 @@ where
         info!("unwatching {:?}", path);
     }
 
-    fn remove(&mut self, path: &PathBuf, events: &mut Vec<Event<T>>) -> Option<Box<Entry<T>>> {
+    fn remove(&mut self, path: &PathBuf, events: &mut Vec<Event<T>>) -> Option<EntryPtr<T>> {
         let mut parent = self.lookup(&path.parent()?.into())?;
         let component = into_components(path).pop()?;
 
@@ -563,8 +871,10 @@ where
                 .unwrap() // parents are always dirs
                 .remove(&component)
                 .map(|entry| {
-                    self.drop_entry(EntryPtr::from(entry.deref()), events);
-                    entry
+                    let entry_ptr = EntryPtr::from(entry.deref());
+                    self.drop_entry(entry_ptr, events);
+                    self.removed.push(entry);
+                    entry_ptr
                 })
         }
     }
@@ -588,6 +898,7 @@ where
                 events.push(Event::Delete(entry_ptr));
             }
             Entry::File { .. } => {
+                self.open_files.remove(entry_ptr);
                 events.push(Event::Delete(entry_ptr));
             }
         };
@@ -624,6 +935,12 @@ where
                 .unwrap()
         };
 
+        // the entry at the destination is overwritten by the move so it's dropped first
+        if self.lookup(to).is_some() {
+            info!("{:?} was replaced by {:?}", to, from);
+            self.remove(to, events);
+        }
+
         entry.set_parent(new_parent);
         entry.set_name(new_name.clone());
         let entry_ptr = EntryPtr::from(entry.deref());
@@ -636,6 +953,10 @@ where
                 .insert(new_name, entry);
         }
 
+        if let Entry::File { .. } = unsafe { entry_ptr.as_ref() } {
+            events.push(Event::Rotate(entry_ptr));
+        }
+
         Some(entry_ptr)
     }
 
@@ -811,6 +1132,53 @@ where
     fn passes(&self, path: &PathBuf) -> bool {
         self.is_initial_dir_target(path) || self.is_symlink_target(path)
     }
+
+    /// Explains which rules decide whether a path is tracked
+    pub fn explain(&self, path: &PathBuf) -> PathExplanation<'_> {
+        let mut symlinks = Vec::new();
+        for (_, symlink_ptrs) in self.symlinks.iter() {
+            for symlink_ptr in symlink_ptrs.iter() {
+                let symlink = unsafe { symlink_ptr.as_ref() };
+                if let Entry::Symlink { rules, .. } = symlink {
+                    if rules.passes(path).is_ok() {
+                        symlinks.push(self.resolve_direct_path(symlink));
+                    }
+                }
+            }
+        }
+        // the outermost of the dirs, which is the one that isn't watched
+        let pruned = path
+            .ancestors()
+            .skip(1)
+            .map(PathBuf::from)
+            .filter(|dir| self.is_pruned(dir))
+            .last();
+
+        PathExplanation {
+            rules: self.master_rules.explain(path),
+            initial_dir: self.initial_dir_rules.passes(path).is_ok(),
+            symlinks,
+            tracked: pruned.is_none() && self.passes(path),
+            pruned,
+        }
+    }
+
+    // a directory is pruned when no file below it can pass the exclusion rules, unless it leads
+    // to the target of a symlink which ignores exclusions
+    fn is_pruned(&self, dir: &PathBuf) -> bool {
+        if !self.master_rules.excludes_subtree(dir) {
+            return false;
+        }
+
+        !self.symlinks.iter().any(|(_, symlink_ptrs)| {
+            symlink_ptrs
+                .iter()
+                .any(|symlink_ptr| match unsafe { (*symlink_ptr).as_ref() } {
+                    Entry::Symlink { rules, .. } => rules.passes(dir).is_ok(),
+                    _ => false,
+                })
+        })
+    }
 }
 
 // conditionally implement std::fmt::Debug if the underlying type T implements it
@@ -823,13 +1191,14 @@ impl<T: fmt::Debug + Clone> fmt::Debug for FileSystem<T> {
         builder.field("master_rules", &&self.master_rules);
         builder.field("initial_dir_rules", &&self.initial_dir_rules);
         builder.field("initial_events", &&self.initial_events);
+        builder.field("removed", &&self.removed);
         builder.finish()
     }
 }
 
-// recursively scans a directory for unlimited depth
-fn recursive_scan(path: &PathBuf) -> Vec<PathBuf> {
-    if !path.is_dir() {
+// recursively scans a directory for unlimited depth, skipping the directories that are pruned
+fn recursive_scan(path: &PathBuf, is_pruned: &dyn Fn(&PathBuf) -> bool) -> Vec<PathBuf> {
+    if !path.is_dir() || is_pruned(path) {
         return vec![];
     }
 
@@ -855,7 +1224,7 @@ fn recursive_scan(path: &PathBuf) -> Vec<PathBuf> {
         // if the path is a dir then recursively scan it also
         // so that we have an unlimited depth scan
         if path.is_dir() {
-            paths.append(&mut recursive_scan(&path))
+            paths.append(&mut recursive_scan(&path, is_pruned))
         } else {
             paths.push(path)
         }
@@ -907,6 +1276,7 @@ mod tests {
     use crate::test::LOGGER;
     use std::convert::TryInto;
     use std::fs::{copy, create_dir, hard_link, remove_dir_all, remove_file, rename, File};
+    use std::io::Write;
     use std::os::unix::fs::symlink;
     use std::panic;
     use tempfile::TempDir;
@@ -938,39 +1308,260 @@ mod tests {
     fn new_fs<T: Default + Clone + std::fmt::Debug>(
         path: PathBuf,
         rules: Option<Rules>,
+        backend: WatchBackend,
     ) -> FileSystem<T> {
         let rules = rules.unwrap_or_else(|| {
             let mut rules = Rules::new();
             rules.add_inclusion(GlobRule::new(r"**").unwrap());
             rules
         });
-        FileSystem::new(
+        FileSystem::with_options(
             vec![path
                 .as_path()
                 .try_into()
                 .unwrap_or_else(|_| panic!("{:?} is not a directory!", path))],
             rules,
+            Options {
+                backend,
+                poll_interval: Duration::from_millis(50),
+                ..Default::default()
+            },
         )
     }
 
-    fn run_test<T: FnOnce() + panic::UnwindSafe>(test: T) {
+    // runs a test once with every backend
+    fn run_test<T: Fn(WatchBackend) + panic::RefUnwindSafe>(test: T) {
         #![allow(unused_must_use, clippy::clone_on_copy)]
         LOGGER.clone();
-        let result = panic::catch_unwind(|| {
-            test();
+        for backend in [WatchBackend::Inotify, WatchBackend::Poll].iter() {
+            let result = panic::catch_unwind(|| {
+                test(*backend);
+            });
+
+            assert!(result.is_ok(), "failed with the {:?} backend", backend)
+        }
+    }
+
+    // Simulates a rescan after the events of a move, delete and create were lost
+    #[test]
+    fn filesystem_rescan_after_overflow() {
+        run_test(|backend| {
+            let tempdir = TempDir::new().unwrap();
+            let path = tempdir.path().to_path_buf();
+
+            let a = path.join("a");
+            let b = path.join("b");
+            File::create(&a).unwrap();
+            File::create(&b).unwrap();
+
+            let fs = Arc::new(Mutex::new(new_fs::<()>(path.clone(), None, backend)));
+            let a_entry = lookup_entry!(fs, a).unwrap();
+
+            let old = path.join("a.old");
+            let c = path.join("c");
+            rename(&a, &old).unwrap();
+            remove_file(&b).unwrap();
+            File::create(&c).unwrap();
+
+            let mut events = Vec::new();
+            fs.lock()
+                .expect("failed to lock fs")
+                .process(WatchEvent::Overflow, &mut events);
+
+            assert!(lookup_entry!(fs, a).is_none());
+            assert!(lookup_entry!(fs, b).is_none());
+            assert!(lookup_entry!(fs, c).is_some());
+            // the moved file keeps it's entry so it's state isn't lost
+            assert_eq!(lookup_entry!(fs, old), Some(a_entry));
+            assert_eq!(events.len(), 3);
+            assert!(events.iter().any(|e| matches!(e, Event::Rotate(_))));
+            assert!(events.iter().any(|e| matches!(e, Event::Delete(_))));
+            assert!(events.iter().any(|e| matches!(e, Event::New(_))));
+        });
+    }
+
+    // Closes the least recently used handles and reopens them once the file is written to
+    #[test]
+    fn filesystem_max_open_files() {
+        run_test(|backend| {
+            let tempdir = TempDir::new().unwrap();
+            let path = tempdir.path().to_path_buf();
+
+            let a = path.join("a");
+            let b = path.join("b");
+            let c = path.join("c");
+            for file in &[&a, &b, &c] {
+                File::create(file).unwrap();
+            }
+
+            let mut rules = Rules::new();
+            rules.add_inclusion(GlobRule::new(r"**").unwrap());
+            let fs = Arc::new(Mutex::new(FileSystem::<()>::with_options(
+                vec![path.as_path().try_into().unwrap()],
+                rules,
+                Options {
+                    backend,
+                    poll_interval: Duration::from_millis(50),
+                    max_open_files: Some(1),
+                    ..Default::default()
+                },
+            )));
+            let is_open = |file: &PathBuf| {
+                let entry = lookup_entry!(fs, file).unwrap();
+                unsafe { entry.as_ref() }.file_handle().is_some()
+            };
+            let open_count = || [&a, &b, &c].iter().filter(|file| is_open(file)).count();
+            assert_eq!(open_count(), 1);
+
+            take_events!(fs, 3);
+            OpenOptions::new()
+                .append(true)
+                .open(&a)
+                .unwrap()
+                .write_all(b"line\n")
+                .unwrap();
+            let events = take_events!(fs, 1);
+            assert!(matches!(events[0], Ok(Event::Write(_))));
+            assert!(is_open(&a));
+            assert_eq!(open_count(), 1);
+
+            // a deleted file can't be reopened so it's kept open until it's delete event
+            remove_file(&a).unwrap();
+            let c_entry = lookup_entry!(fs, c).unwrap();
+            fs.lock().unwrap().open(&Event::Write(c_entry));
+            assert!(is_open(&c));
+            assert_eq!(open_count(), 2);
+        });
+    }
+
+    // Watches a dir that doesn't exist yet through it's nearest ancestor
+    #[test]
+    fn filesystem_pending_dir() {
+        run_test(|backend| {
+            let tempdir = TempDir::new().unwrap();
+            let path = tempdir.path().to_path_buf();
+            let app = path.join("app");
+            let logs = app.join("logs");
+
+            let mut rules = Rules::new();
+            rules.add_inclusion(GlobRule::new(r"**").unwrap());
+            let fs = Arc::new(Mutex::new(FileSystem::<()>::with_options(
+                Vec::new(),
+                rules,
+                Options {
+                    backend,
+                    poll_interval: Duration::from_millis(50),
+                    pending_dirs: vec![logs.clone()],
+                    ..Default::default()
+                },
+            )));
+
+            // paths next to the pending dir aren't tracked
+            File::create(path.join("other.log")).unwrap();
+            create_dir(&app).unwrap();
+            take_events!(fs, 1);
+            create_dir(&logs).unwrap();
+            take_events!(fs, 1);
+            File::create(logs.join("a.log")).unwrap();
+            let events = take_events!(fs, 2);
+            assert!(matches!(events[0], Ok(Event::New(_))));
+            assert!(lookup_entry!(fs, path.join("other.log")).is_none());
+            assert!(lookup_entry!(fs, logs.join("a.log")).is_some());
+
+            remove_dir_all(&app).unwrap();
+            let events = take_events!(fs, 2);
+            assert!(matches!(events[0], Ok(Event::Delete(_))));
+            assert!(lookup_entry!(fs, logs).is_none());
+
+            create_dir(&app).unwrap();
+            create_dir(&logs).unwrap();
+            File::create(logs.join("b.log")).unwrap();
+            // the dir is picked up again once it comes back
+            let events = take_events!(fs, 2);
+            assert!(matches!(events[0], Ok(Event::New(_))));
+            assert!(lookup_entry!(fs, logs.join("b.log")).is_some());
+        });
+    }
+
+    // Explains the rules of a file in an initial dir and the target of a symlink outside of it
+    #[test]
+    fn filesystem_explain() {
+        run_test(|backend| {
+            let tempdir = TempDir::new().unwrap();
+            let path = tempdir.path().to_path_buf();
+            let target_dir = TempDir::new().unwrap();
+            let target = target_dir.path().join("target.debug.log");
+            File::create(&target).unwrap();
+            symlink(&target, path.join("link.log")).unwrap();
+
+            let mut rules = Rules::new();
+            rules.add_inclusion(GlobRule::new(r"**").unwrap());
+            rules.add_exclusion(GlobRule::new(r"*.debug.log").unwrap());
+            let fs = new_fs::<()>(path.clone(), Some(rules), backend);
+
+            let explanation = fs.explain(&path.join("a.log"));
+            assert!(explanation.initial_dir);
+            assert!(explanation.symlinks.is_empty());
+            assert_eq!(explanation.rules.inclusions[0].describe(), "glob **");
+            assert_eq!(explanation.rules.status, Status::Ok);
+            assert!(explanation.tracked);
+
+            let explanation = fs.explain(&path.join("a.debug.log"));
+            assert_eq!(explanation.rules.status, Status::Excluded);
+            assert!(!explanation.tracked);
+
+            // symlink targets are tracked regardless of the exclusion rules
+            let explanation = fs.explain(&target);
+            assert!(!explanation.initial_dir);
+            assert_eq!(explanation.symlinks, vec![path.join("link.log")]);
+            assert_eq!(explanation.rules.status, Status::Excluded);
+            assert!(explanation.tracked);
         });
+    }
+
+    // Excluded subtrees aren't scanned or watched
+    #[test]
+    fn filesystem_prune_excluded_dir() {
+        run_test(|backend| {
+            let tempdir = TempDir::new().unwrap();
+            let path = tempdir.path().to_path_buf();
+            let pods = path.join("pods");
+            create_dir(&pods).unwrap();
+            create_dir(pods.join("pod")).unwrap();
+            File::create(pods.join("pod").join("0.log")).unwrap();
 
-        assert!(result.is_ok())
+            let mut rules = Rules::new();
+            rules.add_inclusion(GlobRule::new(r"**").unwrap());
+            rules.add_exclusion(GlobRule::new(&*format!("{}/**/*", pods.display())).unwrap());
+            rules.add_exclusion(GlobRule::new(&*format!("{}/*/cache/**", path.display())).unwrap());
+            let fs = Arc::new(Mutex::new(new_fs::<()>(path.clone(), Some(rules), backend)));
+            assert!(lookup_entry!(fs, pods).is_none());
+
+            File::create(pods.join("1.log")).unwrap();
+            create_dir(path.join("app")).unwrap();
+            File::create(path.join("app").join("a.log")).unwrap();
+            let events = take_events!(fs, 2);
+            assert!(matches!(events[0], Ok(Event::New(_))));
+            assert!(lookup_entry!(fs, path.join("app").join("a.log")).is_some());
+            assert!(lookup_entry!(fs, pods.join("1.log")).is_none());
+
+            // a dir that is created later is skipped as well
+            create_dir(path.join("app").join("cache")).unwrap();
+            take_events!(fs, 1);
+            File::create(path.join("app").join("cache").join("b.log")).unwrap();
+            take_events!(fs, 1);
+            assert!(lookup_entry!(fs, path.join("app").join("cache")).is_none());
+        });
     }
 
     // Simulates the `create_move` log rotation strategy
     #[test]
     fn filesystem_rotate_create_move() {
-        run_test(|| {
+        run_test(|backend| {
             let tempdir = TempDir::new().unwrap();
             let path = tempdir.path().to_path_buf();
 
-            let fs = Arc::new(Mutex::new(new_fs::<()>(path.clone(), None)));
+            let fs = Arc::new(Mutex::new(new_fs::<()>(path.clone(), None, backend)));
 
             let a = path.join("a");
             File::create(&a).unwrap();
@@ -1013,11 +1604,11 @@ mod tests {
     // Simulates the `create_copy` log rotation strategy
     #[test]
     fn filesystem_rotate_create_copy() {
-        run_test(|| {
+        run_test(|backend| {
             let tempdir = TempDir::new().unwrap();
             let path = tempdir.path().to_path_buf();
 
-            let fs = Arc::new(Mutex::new(new_fs::<()>(path.clone(), None)));
+            let fs = Arc::new(Mutex::new(new_fs::<()>(path.clone(), None, backend)));
 
             let a = path.join("a");
             File::create(&a).unwrap();
@@ -1065,11 +1656,11 @@ mod tests {
     // Creates a plain old dir
     #[test]
     fn filesystem_create_dir() {
-        run_test(|| {
+        run_test(|backend| {
             let tempdir = TempDir::new().unwrap();
             let path = tempdir.path().to_path_buf();
 
-            let fs = Arc::new(Mutex::new(new_fs::<()>(path.clone(), None)));
+            let fs = Arc::new(Mutex::new(new_fs::<()>(path.clone(), None, backend)));
 
             take_events!(fs, 1);
 
@@ -1080,11 +1671,11 @@ mod tests {
     // Creates a plain old file
     #[test]
     fn filesystem_create_file() {
-        run_test(|| {
+        run_test(|backend| {
             let tempdir = TempDir::new().unwrap();
             let path = tempdir.path().to_path_buf();
 
-            let fs = Arc::new(Mutex::new(new_fs::<()>(path.clone(), None)));
+            let fs = Arc::new(Mutex::new(new_fs::<()>(path.clone(), None, backend)));
 
             File::create(path.join("insert.log")).unwrap();
             take_events!(fs, 1);
@@ -1096,11 +1687,11 @@ mod tests {
     // Creates a symlink
     #[test]
     fn filesystem_create_symlink() {
-        run_test(|| {
+        run_test(|backend| {
             let tempdir = TempDir::new().unwrap();
             let path = tempdir.path().to_path_buf();
 
-            let fs = Arc::new(Mutex::new(new_fs::<()>(path.clone(), None)));
+            let fs = Arc::new(Mutex::new(new_fs::<()>(path.clone(), None, backend)));
 
             let a = path.join("a");
             let b = path.join("b");
@@ -1130,11 +1721,11 @@ mod tests {
     // Creates a hardlink
     #[test]
     fn filesystem_create_hardlink() {
-        run_test(|| {
+        run_test(|backend| {
             let tempdir = TempDir::new().unwrap();
             let path = tempdir.path().to_path_buf();
 
-            let fs = Arc::new(Mutex::new(new_fs::<()>(path.clone(), None)));
+            let fs = Arc::new(Mutex::new(new_fs::<()>(path.clone(), None, backend)));
 
             let file_path = path.join("insert.log");
             let hard_path = path.join("hard.log");
@@ -1164,7 +1755,7 @@ mod tests {
     // Deletes a directory
     #[test]
     fn filesystem_delete_filled_dir() {
-        run_test(|| {
+        run_test(|backend| {
             let tempdir = TempDir::new().unwrap();
             let path = tempdir.path().to_path_buf();
 
@@ -1175,7 +1766,7 @@ mod tests {
             symlink(&file_path, &sym_path).unwrap();
             hard_link(&file_path, &hard_path).unwrap();
 
-            let fs = Arc::new(Mutex::new(new_fs::<()>(path.clone(), None)));
+            let fs = Arc::new(Mutex::new(new_fs::<()>(path.clone(), None, backend)));
 
             assert!(lookup_entry!(fs, path).is_some());
             assert!(lookup_entry!(fs, file_path).is_some());
@@ -1195,14 +1786,14 @@ mod tests {
     // Deletes a file
     #[test]
     fn filesystem_delete_file() {
-        run_test(|| {
+        run_test(|backend| {
             let tempdir = TempDir::new().unwrap();
             let path = tempdir.path().to_path_buf();
 
             let file_path = path.join("file");
             File::create(file_path.clone()).unwrap();
 
-            let fs = Arc::new(Mutex::new(new_fs::<()>(path, None)));
+            let fs = Arc::new(Mutex::new(new_fs::<()>(path, None, backend)));
 
             assert!(lookup_entry!(fs, file_path).is_some());
 
@@ -1216,7 +1807,7 @@ mod tests {
     // Deletes a symlink
     #[test]
     fn filesystem_delete_symlink() {
-        run_test(|| {
+        run_test(|backend| {
             let tempdir = TempDir::new().unwrap();
             let path = tempdir.path().to_path_buf();
 
@@ -1225,7 +1816,7 @@ mod tests {
             create_dir(&a).unwrap();
             symlink(&a, &b).unwrap();
 
-            let fs = Arc::new(Mutex::new(new_fs::<()>(path, None)));
+            let fs = Arc::new(Mutex::new(new_fs::<()>(path, None, backend)));
 
             remove_dir_all(&b).unwrap();
             take_events!(fs, 1);
@@ -1238,7 +1829,7 @@ mod tests {
     // Deletes the pointee of a symlink
     #[test]
     fn filesystem_delete_symlink_pointee() {
-        run_test(|| {
+        run_test(|backend| {
             let tempdir = TempDir::new().unwrap();
             let path = tempdir.path().to_path_buf();
 
@@ -1247,7 +1838,7 @@ mod tests {
             create_dir(&a).unwrap();
             symlink(&a, &b).unwrap();
 
-            let fs = Arc::new(Mutex::new(new_fs::<()>(path, None)));
+            let fs = Arc::new(Mutex::new(new_fs::<()>(path, None, backend)));
 
             remove_dir_all(&a).unwrap();
             take_events!(fs, 1);
@@ -1260,7 +1851,7 @@ mod tests {
     // Deletes a hardlink
     #[test]
     fn filesystem_delete_hardlink() {
-        run_test(|| {
+        run_test(|backend| {
             let tempdir = TempDir::new().unwrap();
             let path = tempdir.path().to_path_buf();
 
@@ -1269,7 +1860,7 @@ mod tests {
             File::create(a.clone()).unwrap();
             hard_link(&a, &b).unwrap();
 
-            let fs = Arc::new(Mutex::new(new_fs::<()>(path, None)));
+            let fs = Arc::new(Mutex::new(new_fs::<()>(path, None, backend)));
 
             assert!(lookup_entry!(fs, a).is_some());
             assert!(lookup_entry!(fs, b).is_some());
@@ -1286,7 +1877,7 @@ mod tests {
     // entry, but what evs)
     #[test]
     fn filesystem_delete_hardlink_pointee() {
-        run_test(|| {
+        run_test(|backend| {
             let tempdir = TempDir::new().unwrap();
             let path = tempdir.path().to_path_buf();
 
@@ -1295,7 +1886,7 @@ mod tests {
             File::create(a.clone()).unwrap();
             hard_link(&a, &b).unwrap();
 
-            let fs = Arc::new(Mutex::new(new_fs::<()>(path, None)));
+            let fs = Arc::new(Mutex::new(new_fs::<()>(path, None, backend)));
 
             remove_file(&a).unwrap();
             take_events!(fs, 3);
@@ -1308,7 +1899,7 @@ mod tests {
     // Moves a directory within the watched directory
     #[test]
     fn filesystem_move_dir_internal() {
-        run_test(|| {
+        run_test(|backend| {
             let tempdir = TempDir::new().unwrap();
             let path = tempdir.path().to_path_buf();
 
@@ -1322,7 +1913,7 @@ mod tests {
             symlink(&file_path, &sym_path).unwrap();
             hard_link(&file_path, &hard_path).unwrap();
 
-            let fs = Arc::new(Mutex::new(new_fs::<()>(path, None)));
+            let fs = Arc::new(Mutex::new(new_fs::<()>(path, None, backend)));
 
             rename(&old_dir_path, &new_dir_path).unwrap();
             take_events!(fs, 4);
@@ -1368,7 +1959,7 @@ mod tests {
     // Moves a directory out
     #[test]
     fn filesystem_move_dir_out() {
-        run_test(|| {
+        run_test(|backend| {
             let tempdir = TempDir::new().unwrap();
             let path = tempdir.path().to_path_buf();
 
@@ -1382,7 +1973,11 @@ mod tests {
             symlink(&file_path, &sym_path).unwrap();
             hard_link(&file_path, &hard_path).unwrap();
 
-            let fs = Arc::new(Mutex::new(new_fs::<()>(old_dir_path.clone(), None)));
+            let fs = Arc::new(Mutex::new(new_fs::<()>(
+                old_dir_path.clone(),
+                None,
+                backend,
+            )));
 
             rename(&old_dir_path, &new_dir_path).unwrap();
             take_events!(fs, 1);
@@ -1397,7 +1992,7 @@ mod tests {
     // Moves a directory in
     #[test]
     fn filesystem_move_dir_in() {
-        run_test(|| {
+        run_test(|backend| {
             let old_tempdir = TempDir::new().unwrap();
             let old_path = old_tempdir.path().to_path_buf();
 
@@ -1414,7 +2009,7 @@ mod tests {
             symlink(&file_path, &sym_path).unwrap();
             hard_link(&file_path, &hard_path).unwrap();
 
-            let fs = Arc::new(Mutex::new(new_fs::<()>(new_path, None)));
+            let fs = Arc::new(Mutex::new(new_fs::<()>(new_path, None, backend)));
 
             assert!(lookup_entry!(fs, old_dir_path).is_none());
             assert!(lookup_entry!(fs, new_dir_path).is_none());
@@ -1461,11 +2056,11 @@ mod tests {
     // Moves a file within the watched directory
     #[test]
     fn filesystem_move_file_internal() {
-        run_test(|| {
+        run_test(|backend| {
             let tempdir = TempDir::new().unwrap();
             let path = tempdir.path().to_path_buf();
 
-            let fs = Arc::new(Mutex::new(new_fs::<()>(path.clone(), None)));
+            let fs = Arc::new(Mutex::new(new_fs::<()>(path.clone(), None, backend)));
 
             let file_path = path.join("insert.log");
             let new_path = path.join("new.log");
@@ -1489,7 +2084,7 @@ mod tests {
     // Moves a file out of the watched directory
     #[test]
     fn filesystem_move_file_out() {
-        run_test(|| {
+        run_test(|backend| {
             let tempdir = TempDir::new().unwrap();
             let path = tempdir.path().to_path_buf();
 
@@ -1502,7 +2097,7 @@ mod tests {
             let move_path = other_path.join("outside.log");
             File::create(file_path.clone()).unwrap();
 
-            let fs = Arc::new(Mutex::new(new_fs::<()>(watch_path, None)));
+            let fs = Arc::new(Mutex::new(new_fs::<()>(watch_path, None, backend)));
 
             rename(&file_path, &move_path).unwrap();
 
@@ -1519,7 +2114,7 @@ mod tests {
     // Moves a file into the watched directory
     #[test]
     fn filesystem_move_file_in() {
-        run_test(|| {
+        run_test(|backend| {
             let tempdir = TempDir::new().unwrap();
             let path = tempdir.path().to_path_buf();
 
@@ -1532,7 +2127,7 @@ mod tests {
             let move_path = watch_path.join("outside.log");
             File::create(file_path.clone()).unwrap();
 
-            let fs = Arc::new(Mutex::new(new_fs::<()>(watch_path, None)));
+            let fs = Arc::new(Mutex::new(new_fs::<()>(watch_path, None, backend)));
 
             rename(&file_path, &move_path).unwrap();
             File::create(file_path.clone()).unwrap();
@@ -1551,10 +2146,88 @@ mod tests {
         });
     }
 
+    // Moves a file from outside the watched directory over a tracked file
+    #[test]
+    fn filesystem_move_file_in_replace() {
+        run_test(|backend| {
+            let tempdir = TempDir::new().unwrap();
+            let path = tempdir.path().to_path_buf();
+
+            let watch_path = path.join("watch");
+            let other_path = path.join("other");
+            create_dir(&watch_path).unwrap();
+            create_dir(&other_path).unwrap();
+
+            let file_path = watch_path.join("a.log");
+            let new_path = other_path.join("a.log");
+            File::create(&file_path).unwrap();
+            File::create(&new_path).unwrap();
+            let new_inode = new_path.metadata().unwrap().ino();
+
+            let fs = Arc::new(Mutex::new(new_fs::<()>(watch_path, None, backend)));
+            take_events!(fs, 1);
+
+            rename(&new_path, &file_path).unwrap();
+
+            let events = take_events!(fs, 2);
+            assert!(matches!(events[0], Ok(Event::Delete(_))));
+            assert!(matches!(events[1], Ok(Event::Replace(_))));
+
+            let entry = lookup_entry!(fs, file_path);
+            match unsafe { entry.unwrap().as_ref() } {
+                Entry::File { file_handle, .. } => {
+                    assert_eq!(
+                        file_handle.as_ref().unwrap().metadata().unwrap().ino(),
+                        new_inode
+                    )
+                }
+                _ => panic!("wrong entry type"),
+            }
+        });
+    }
+
+    // Moves a tracked file over another tracked file
+    #[test]
+    fn filesystem_move_file_internal_replace() {
+        run_test(|backend| {
+            let tempdir = TempDir::new().unwrap();
+            let path = tempdir.path().to_path_buf();
+
+            let old_path = path.join("a.log");
+            let new_path = path.join("b.log");
+            File::create(&old_path).unwrap();
+            File::create(&new_path).unwrap();
+            let old_inode = old_path.metadata().unwrap().ino();
+
+            let fs = Arc::new(Mutex::new(new_fs::<()>(path, None, backend)));
+            take_events!(fs, 2);
+
+            rename(&old_path, &new_path).unwrap();
+
+            let events = take_events!(fs, 2);
+            assert!(matches!(events[0], Ok(Event::Delete(_))));
+            assert!(matches!(events[1], Ok(Event::Rotate(_))));
+
+            let entry = lookup_entry!(fs, old_path);
+            assert!(entry.is_none());
+
+            let entry = lookup_entry!(fs, new_path);
+            match unsafe { entry.unwrap().as_ref() } {
+                Entry::File { file_handle, .. } => {
+                    assert_eq!(
+                        file_handle.as_ref().unwrap().metadata().unwrap().ino(),
+                        old_inode
+                    )
+                }
+                _ => panic!("wrong entry type"),
+            }
+        });
+    }
+
     // Moves a file out of the watched directory
     #[test]
     fn filesystem_move_symlink_file_out() {
-        run_test(|| {
+        run_test(|backend| {
             let tempdir = TempDir::new().unwrap();
             let path = tempdir.path().to_path_buf();
 
@@ -1569,7 +2242,7 @@ mod tests {
             File::create(file_path.clone()).unwrap();
             symlink(&file_path, &sym_path).unwrap();
 
-            let fs = Arc::new(Mutex::new(new_fs::<()>(watch_path, None)));
+            let fs = Arc::new(Mutex::new(new_fs::<()>(watch_path, None, backend)));
 
             rename(&file_path, &move_path).unwrap();
 
@@ -1589,7 +2262,7 @@ mod tests {
     // Watch symlink target that is excluded
     #[test]
     fn filesystem_watch_symlink_w_excluded_target() {
-        run_test(|| {
+        run_test(|backend| {
             let tempdir = TempDir::new().unwrap();
             let path = tempdir.path().to_path_buf();
 
@@ -1604,7 +2277,7 @@ mod tests {
             let sym_path = path.join("test.log");
             File::create(file_path.clone()).unwrap();
 
-            let fs = Arc::new(Mutex::new(new_fs::<()>(path, Some(rules))));
+            let fs = Arc::new(Mutex::new(new_fs::<()>(path, Some(rules), backend)));
 
             let entry = lookup_entry!(fs, file_path);
             assert!(entry.is_none());
@@ -1623,7 +2296,7 @@ mod tests {
 
     #[test]
     fn filesystem_resolve_valid_paths() {
-        run_test(|| {
+        run_test(|backend| {
             let tempdir = TempDir::new().unwrap();
             let path = tempdir.path().to_path_buf();
 
@@ -1643,7 +2316,7 @@ mod tests {
             symlink(&remote_symlink_path, &nested_symlink_path).unwrap();
             symlink(&nested_symlink_path, &double_nested_symlink_path).unwrap();
 
-            let fs = Arc::new(Mutex::new(new_fs::<()>(path, None)));
+            let fs = Arc::new(Mutex::new(new_fs::<()>(path, None, backend)));
             let entry = unsafe { &*(lookup_entry!(fs, file_path).unwrap()).as_ptr() };
 
             let fs = fs.lock().expect("Failed to lock fs");
@@ -1,17 +1,84 @@
-use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
-use std::ffi::OsString;
+use crate::identity::FileId;
+use hashbrown::HashMap;
+use inotify::{EventMask, Inotify, WatchMask};
+use metrics::Metrics;
+use std::ffi::{CString, OsString};
+use std::fs::read_dir;
 use std::io;
-use std::path::Path;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirEntryExt, MetadataExt};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use futures::future::Either;
 use futures::{Stream, StreamExt};
+use thiserror::Error;
 use tokio::time::Instant;
 
 use tokio::sync::Mutex;
 
 const INOTIFY_EVENT_GRACE_PERIOD_MS: u64 = 10;
 
+/// The default time between two scans of the paths watched by polling
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(1000);
+
+// statfs magic numbers of filesystems that don't report changes through inotify
+const NFS_SUPER_MAGIC: u32 = 0x6969;
+const SMB_SUPER_MAGIC: u32 = 0x517b;
+const CIFS_MAGIC_NUMBER: u32 = 0xff53_4d42;
+const SMB2_MAGIC_NUMBER: u32 = 0xfe53_4d42;
+const FUSE_SUPER_MAGIC: u32 = 0x6573_5546;
+const V9FS_MAGIC: u32 = 0x0102_1997;
+const CEPH_SUPER_MAGIC: u32 = 0x00c3_6400;
+const POLLED_FILESYSTEMS: &[u32] = &[
+    NFS_SUPER_MAGIC,
+    SMB_SUPER_MAGIC,
+    CIFS_MAGIC_NUMBER,
+    SMB2_MAGIC_NUMBER,
+    FUSE_SUPER_MAGIC,
+    V9FS_MAGIC,
+    CEPH_SUPER_MAGIC,
+];
+
+/// The mechanism used to watch paths for changes
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum WatchBackend {
+    /// Polls paths on network and FUSE filesystems and uses inotify for everything else
+    #[default]
+    Auto,
+    /// Always uses inotify
+    Inotify,
+    /// Always polls, e.g. for overlay setups where inotify doesn't report changes
+    Poll,
+}
+
+#[derive(Error, Debug)]
+pub enum ParseWatchBackendError {
+    #[error("Unknown watch backend: {0}")]
+    Unknown(String),
+}
+
+impl std::str::FromStr for WatchBackend {
+    type Err = ParseWatchBackendError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "auto" => Ok(WatchBackend::Auto),
+            "inotify" => Ok(WatchBackend::Inotify),
+            "poll" => Ok(WatchBackend::Poll),
+            _ => Err(ParseWatchBackendError::Unknown(s.into())),
+        }
+    }
+}
+
+/// Identifies a watch independently of the backend it was created with
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum WatchDescriptor {
+    Inotify(inotify::WatchDescriptor),
+    Poll(u64),
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum WatchEvent {
     Create {
@@ -55,22 +122,65 @@ where
 
 pub struct Watcher {
     inotify: Inotify,
+    poller: Arc<std::sync::Mutex<Poller>>,
+    backend: WatchBackend,
+    poll_interval: Duration,
+    // dirs that are polled because the inotify watch limit was reached while watching them
+    exhausted: Vec<PathBuf>,
 }
 
 impl Watcher {
-    pub fn new() -> io::Result<Self> {
+    pub fn new(backend: WatchBackend, poll_interval: Duration) -> io::Result<Self> {
         Ok(Self {
             inotify: Inotify::init()?,
+            poller: Arc::new(std::sync::Mutex::new(Poller::default())),
+            backend,
+            poll_interval,
+            exhausted: Vec::new(),
         })
     }
 
     pub fn watch<P: AsRef<Path>>(&mut self, path: P) -> io::Result<WatchDescriptor> {
-        self.inotify
-            .add_watch(path.as_ref(), watch_mask(path.as_ref()))
+        let path = path.as_ref();
+        let poll = match self.backend {
+            WatchBackend::Auto => needs_polling(path),
+            WatchBackend::Inotify => false,
+            WatchBackend::Poll => true,
+        } || self.exhausted.iter().any(|dir| path.starts_with(dir));
+
+        if !poll {
+            match self.inotify.add_watch(path, watch_mask(path)) {
+                Ok(wd) => return Ok(WatchDescriptor::Inotify(wd)),
+                // once max_user_watches is reached the rest of the subtree is polled
+                Err(e) if e.raw_os_error() == Some(libc::ENOSPC) => {
+                    Metrics::fs().increment_exhausted_watches();
+                    warn!("inotify watch limit reached, polling {:?} instead", path);
+                    if path.is_dir() {
+                        self.exhausted.push(path.to_path_buf());
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.poller
+            .lock()
+            .expect("Couldn't lock poller")
+            .watch(path)
+            .map(WatchDescriptor::Poll)
     }
 
     pub fn unwatch(&mut self, wd: WatchDescriptor) -> io::Result<()> {
-        self.inotify.rm_watch(wd)
+        match wd {
+            WatchDescriptor::Inotify(wd) => self.inotify.rm_watch(wd),
+            WatchDescriptor::Poll(wd) => {
+                self.poller
+                    .lock()
+                    .expect("Couldn't lock poller")
+                    .unwatch(wd);
+                Ok(())
+            }
+        }
     }
 
     pub fn event_stream<'a>(
@@ -79,16 +189,41 @@ impl Watcher {
     ) -> std::io::Result<WatchEventStream<'a>> {
         Ok(WatchEventStream {
             event_stream: self.inotify.event_stream(buffer)?,
+            poller: self.poller.clone(),
+            poll_interval: self.poll_interval,
         })
     }
 }
 
 pub struct WatchEventStream<'a> {
     event_stream: inotify::EventStream<&'a mut [u8]>,
+    poller: Arc<std::sync::Mutex<Poller>>,
+    poll_interval: Duration,
 }
 
 impl<'a> WatchEventStream<'a> {
     pub fn into_stream(self) -> impl Stream<Item = Result<WatchEvent, std::io::Error>> + 'a {
+        // the changes found by polling are merged with the events of inotify
+        let poller = self.poller;
+        let polled = tokio::time::interval(self.poll_interval)
+            .map(move |_| {
+                futures::stream::iter(
+                    poller
+                        .lock()
+                        .expect("Couldn't lock poller")
+                        .poll()
+                        .into_iter()
+                        .map(Ok),
+                )
+            })
+            .flatten();
+
+        futures::stream::select(WatchEventStream::inotify_stream(self.event_stream), polled)
+    }
+
+    fn inotify_stream(
+        event_stream: inotify::EventStream<&'a mut [u8]>,
+    ) -> impl Stream<Item = Result<WatchEvent, std::io::Error>> + 'a {
         let unmatched_move_to: Arc<Mutex<Vec<(Instant, WatchEvent)>>> =
             Arc::new(Mutex::new(Vec::new()));
         let unmatched_move_from: Arc<Mutex<Vec<(Instant, WatchEvent)>>> =
@@ -98,7 +233,7 @@ impl<'a> WatchEventStream<'a> {
         // correctly generate events.
 
         let events = futures::stream::select(
-            self.event_stream.map(EventOrInterval::Event),
+            event_stream.map(EventOrInterval::Event),
             tokio::time::interval(tokio::time::Duration::from_millis(
                 INOTIFY_EVENT_GRACE_PERIOD_MS,
             ))
@@ -136,7 +271,9 @@ impl<'a> WatchEventStream<'a> {
                                                     unmatched_move_to.swap_remove(idx)
                                                 {
                                                     Some(WatchEvent::Move {
-                                                        from_wd: raw_event.wd.clone(),
+                                                        from_wd: WatchDescriptor::Inotify(
+                                                            raw_event.wd.clone(),
+                                                        ),
                                                         from_name: raw_event.name.unwrap(),
                                                         to_wd: wd,
                                                         to_name: name,
@@ -150,7 +287,9 @@ impl<'a> WatchEventStream<'a> {
                                                 unmatched_move_from.lock().await.push((
                                                     Instant::now(),
                                                     WatchEvent::MovedFrom {
-                                                        wd: raw_event.wd.clone(),
+                                                        wd: WatchDescriptor::Inotify(
+                                                            raw_event.wd.clone(),
+                                                        ),
                                                         name: raw_event.name.unwrap(),
                                                         cookie: raw_event.cookie,
                                                     },
@@ -180,7 +319,9 @@ impl<'a> WatchEventStream<'a> {
                                                     Some(WatchEvent::Move {
                                                         from_wd: wd,
                                                         from_name: name,
-                                                        to_wd: raw_event.wd.clone(),
+                                                        to_wd: WatchDescriptor::Inotify(
+                                                            raw_event.wd.clone(),
+                                                        ),
                                                         to_name: raw_event.name.unwrap(),
                                                     })
                                                 } else {
@@ -192,7 +333,9 @@ impl<'a> WatchEventStream<'a> {
                                                 unmatched_move_to.lock().await.push((
                                                     Instant::now(),
                                                     WatchEvent::MovedTo {
-                                                        wd: raw_event.wd.clone(),
+                                                        wd: WatchDescriptor::Inotify(
+                                                            raw_event.wd.clone(),
+                                                        ),
                                                         name: raw_event.name.unwrap(),
                                                         cookie: raw_event.cookie,
                                                     },
@@ -201,17 +344,17 @@ impl<'a> WatchEventStream<'a> {
                                             }
                                         } else if raw_event.mask.contains(EventMask::CREATE) {
                                             Some(WatchEvent::Create {
-                                                wd: raw_event.wd.clone(),
+                                                wd: WatchDescriptor::Inotify(raw_event.wd.clone()),
                                                 name: raw_event.name.unwrap(),
                                             })
                                         } else if raw_event.mask.contains(EventMask::DELETE) {
                                             Some(WatchEvent::Delete {
-                                                wd: raw_event.wd.clone(),
+                                                wd: WatchDescriptor::Inotify(raw_event.wd.clone()),
                                                 name: raw_event.name.unwrap(),
                                             })
                                         } else if raw_event.mask.contains(EventMask::MODIFY) {
                                             Some(WatchEvent::Modify {
-                                                wd: raw_event.wd.clone(),
+                                                wd: WatchDescriptor::Inotify(raw_event.wd.clone()),
                                             })
                                         } else if raw_event.mask.contains(EventMask::Q_OVERFLOW) {
                                             Some(WatchEvent::Overflow)
@@ -293,3 +436,218 @@ fn watch_mask<P: AsRef<Path>>(path: P) -> WatchMask {
             | WatchMask::MOVED_FROM
     }
 }
+
+// checks if a path lies on a filesystem where changes have to be found by polling
+fn needs_polling(path: &Path) -> bool {
+    let path = match CString::new(path.as_os_str().as_bytes()) {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return false;
+    }
+    POLLED_FILESYSTEMS.contains(&(stat.f_type as u32))
+}
+
+// the state of a watched path as of the last poll
+#[derive(Debug)]
+enum PollState {
+    // files are checked through their path so polling doesn't count towards the open files
+    File {
+        len: u64,
+        modified: Option<SystemTime>,
+    },
+    Dir {
+        children: HashMap<OsString, FileId>,
+    },
+    Symlink,
+}
+
+#[derive(Debug)]
+struct PollWatch {
+    path: PathBuf,
+    id: FileId,
+    state: PollState,
+}
+
+// Finds changes to watched paths by comparing them to the state they had on the last poll
+#[derive(Debug, Default)]
+struct Poller {
+    next_wd: u64,
+    watches: HashMap<u64, PollWatch>,
+    // hard links share a watch like they do with inotify
+    ids: HashMap<FileId, u64>,
+}
+
+impl Poller {
+    fn watch(&mut self, path: &Path) -> io::Result<u64> {
+        let metadata = path.symlink_metadata()?;
+        let id = FileId::from(&metadata);
+        if let Some(wd) = self.ids.get(&id) {
+            return Ok(*wd);
+        }
+
+        let state = if metadata.file_type().is_symlink() {
+            PollState::Symlink
+        } else if metadata.is_dir() {
+            PollState::Dir {
+                children: read_children(path, metadata.dev())?,
+            }
+        } else {
+            PollState::File {
+                len: metadata.len(),
+                modified: metadata.modified().ok(),
+            }
+        };
+
+        let wd = self.next_wd;
+        self.next_wd += 1;
+        self.watches.insert(
+            wd,
+            PollWatch {
+                path: path.to_path_buf(),
+                id,
+                state,
+            },
+        );
+        self.ids.insert(id, wd);
+        Ok(wd)
+    }
+
+    fn unwatch(&mut self, wd: u64) {
+        if let Some(watch) = self.watches.remove(&wd) {
+            self.ids.remove(&watch.id);
+        }
+    }
+
+    // returns the changes since the last poll in the order inotify would report them
+    fn poll(&mut self) -> Vec<WatchEvent> {
+        let mut modified = Vec::new();
+        let mut deleted = Vec::new();
+        let mut created = Vec::new();
+
+        for (wd, watch) in self.watches.iter_mut() {
+            match &mut watch.state {
+                PollState::File {
+                    len,
+                    modified: last_modified,
+                } => {
+                    // a path that is gone or leads to another file is reported by the parent dir
+                    let metadata = match watch.path.metadata() {
+                        Ok(metadata) if FileId::from(&metadata) == watch.id => metadata,
+                        _ => continue,
+                    };
+                    if metadata.len() != *len || metadata.modified().ok() != *last_modified {
+                        *len = metadata.len();
+                        *last_modified = metadata.modified().ok();
+                        modified.push(WatchEvent::Modify {
+                            wd: WatchDescriptor::Poll(*wd),
+                        });
+                    }
+                }
+                PollState::Dir { children } => {
+                    // a dir that can't be read anymore was removed, which it's parent reports
+                    let current = match read_children(&watch.path, watch.id.dev) {
+                        Ok(current) => current,
+                        Err(_) => continue,
+                    };
+                    for (name, id) in children.iter() {
+                        if current.get(name) != Some(id) {
+                            deleted.push((*wd, name.clone(), *id));
+                        }
+                    }
+                    for (name, id) in current.iter() {
+                        if children.get(name) != Some(id) {
+                            created.push((*wd, name.clone(), *id));
+                        }
+                    }
+                    *children = current;
+                }
+                PollState::Symlink => {}
+            }
+        }
+
+        // a file that disappeared from one place and appeared in another was moved
+        let mut moved = Vec::new();
+        let mut creates = Vec::new();
+        for (to_wd, to_name, id) in created {
+            match deleted.iter().position(|(_, _, other)| *other == id) {
+                Some(idx) => {
+                    let (from_wd, from_name, _) = deleted.swap_remove(idx);
+                    moved.push((from_wd, from_name, to_wd, to_name));
+                }
+                None => creates.push((to_wd, to_name)),
+            }
+        }
+        // a file that was replaced is handled by the create or move that replaced it
+        deleted.retain(|(wd, name, _)| {
+            !creates
+                .iter()
+                .any(|(to_wd, to_name)| to_wd == wd && to_name == name)
+                && !moved
+                    .iter()
+                    .any(|(_, _, to_wd, to_name)| to_wd == wd && to_name == name)
+        });
+
+        for (from_wd, from_name, to_wd, to_name) in moved.iter() {
+            self.rebase(*from_wd, from_name, *to_wd, to_name);
+        }
+
+        // moves come first so a file created in place of a moved one isn't mistaken for it
+        let mut events = modified;
+        events.extend(
+            moved
+                .into_iter()
+                .map(|(from_wd, from_name, to_wd, to_name)| WatchEvent::Move {
+                    from_wd: WatchDescriptor::Poll(from_wd),
+                    from_name,
+                    to_wd: WatchDescriptor::Poll(to_wd),
+                    to_name,
+                }),
+        );
+        events.extend(deleted.into_iter().map(|(wd, name, _)| WatchEvent::Delete {
+            wd: WatchDescriptor::Poll(wd),
+            name,
+        }));
+        events.extend(creates.into_iter().map(|(wd, name)| WatchEvent::Create {
+            wd: WatchDescriptor::Poll(wd),
+            name,
+        }));
+        events
+    }
+
+    // watched dirs are read through their path so it's updated when they or their parents move
+    fn rebase(&mut self, from_wd: u64, from_name: &OsString, to_wd: u64, to_name: &OsString) {
+        let (from, to) = match (self.watches.get(&from_wd), self.watches.get(&to_wd)) {
+            (Some(from), Some(to)) => (from.path.join(from_name), to.path.join(to_name)),
+            _ => return,
+        };
+        for watch in self.watches.values_mut() {
+            if let Ok(suffix) = watch.path.strip_prefix(&from) {
+                watch.path = if suffix.as_os_str().is_empty() {
+                    to.clone()
+                } else {
+                    to.join(suffix)
+                };
+            }
+        }
+    }
+}
+
+// returns the identities of the entries of a dir by their names
+fn read_children(path: &Path, dev: u64) -> io::Result<HashMap<OsString, FileId>> {
+    read_dir(path)?
+        .map(|entry| {
+            entry.map(|entry| {
+                (
+                    entry.file_name(),
+                    FileId {
+                        dev,
+                        inode: entry.ino(),
+                    },
+                )
+            })
+        })
+        .collect()
+}
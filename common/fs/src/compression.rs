@@ -0,0 +1,300 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::mem;
+use std::os::unix::fs::FileExt;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// The compression formats of rotated files that can be read
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Detects the compression of a file from it's magic bytes
+    pub fn detect(file: &File) -> io::Result<Option<Self>> {
+        let mut magic = [0; 4];
+        let mut read = 0;
+        while read < magic.len() {
+            match file.read_at(&mut magic[read..], read as u64) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let magic = &magic[..read];
+        Ok(if magic.starts_with(GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if magic.starts_with(ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        })
+    }
+}
+
+/// Decompresses a file in turns, each turn reads what was written to it since the last one, so
+/// a file that is read while it's still being written is only decompressed once
+pub struct Decoder {
+    reader: BufReader<Members>,
+    // the decompressed bytes that were read before, e.g. by the agent before a restart
+    skip: u64,
+    // the start of a line whose end wasn't written yet
+    line: Vec<u8>,
+}
+
+impl Decoder {
+    /// Creates a decoder of a file in the compression format that skips the first skip bytes
+    /// of the decompressed content
+    pub fn new(compression: Compression, skip: u64) -> io::Result<Self> {
+        let written = BufReader::new(Written {
+            file: None,
+            position: 0,
+        });
+        Ok(Self {
+            reader: BufReader::new(Members {
+                compression,
+                member: Some(Member::new(compression, written)?),
+            }),
+            skip,
+            line: Vec::new(),
+        })
+    }
+
+    /// Reads the file in the next turn, it's handle is only kept for the turn
+    pub fn attach(&mut self, file: &File) -> io::Result<()> {
+        if let Some(written) = self.reader.get_mut().written() {
+            written.file = Some(file.try_clone()?);
+        }
+        Ok(())
+    }
+
+    /// Ends the turn, closing the handle of the file
+    pub fn detach(&mut self) {
+        if let Some(written) = self.reader.get_mut().written() {
+            written.file = None;
+        }
+    }
+
+    /// Reads the next line including it's new line, None is returned at the end of the
+    /// compressed content and a `WouldBlock` error once the rest of it isn't written yet
+    pub fn read_line(&mut self) -> io::Result<Option<Vec<u8>>> {
+        while self.skip > 0 {
+            let available = self.reader.fill_buf()?;
+            if available.is_empty() {
+                return Ok(None);
+            }
+            let len = available.len().min(self.skip as usize);
+            self.reader.consume(len);
+            self.skip -= len as u64;
+        }
+
+        // the bytes read before the rest of a line is missing are kept until it's written
+        match self.reader.read_until(b'\n', &mut self.line)? {
+            0 if self.line.is_empty() => Ok(None),
+            _ => Ok(Some(mem::take(&mut self.line))),
+        }
+    }
+}
+
+// the part of a file that was written so far, reading past it fails with `WouldBlock` so the
+// decoders reading it keep their state until more of it is written
+struct Written {
+    file: Option<File>,
+    position: u64,
+}
+
+impl Read for Written {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = match self.file {
+            Some(ref file) => file.read_at(buf, self.position)?,
+            None => 0,
+        };
+        if len == 0 && !buf.is_empty() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+// a gzip member or zstd frame, compressed files may consist of several of them
+enum Member {
+    Gzip(flate2::bufread::GzDecoder<BufReader<Written>>),
+    Zstd(zstd::stream::read::Decoder<BufReader<Written>>),
+}
+
+impl Member {
+    fn new(compression: Compression, written: BufReader<Written>) -> io::Result<Self> {
+        Ok(match compression {
+            Compression::Gzip => Member::Gzip(flate2::bufread::GzDecoder::new(written)),
+            Compression::Zstd => {
+                Member::Zstd(zstd::stream::read::Decoder::with_buffer(written)?.single_frame())
+            }
+        })
+    }
+
+    fn written(&mut self) -> &mut BufReader<Written> {
+        match self {
+            Member::Gzip(decoder) => decoder.get_mut(),
+            Member::Zstd(decoder) => decoder.get_mut(),
+        }
+    }
+
+    fn into_written(self) -> BufReader<Written> {
+        match self {
+            Member::Gzip(decoder) => decoder.into_inner(),
+            Member::Zstd(decoder) => decoder.finish(),
+        }
+    }
+}
+
+impl Read for Member {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Member::Gzip(decoder) => decoder.read(buf),
+            Member::Zstd(decoder) => decoder.read(buf),
+        }
+    }
+}
+
+// the members of a file one after the other, the content ends with the member that ends where
+// the file does
+struct Members {
+    compression: Compression,
+    member: Option<Member>,
+}
+
+impl Members {
+    fn written(&mut self) -> Option<&mut Written> {
+        self.member
+            .as_mut()
+            .map(|member| member.written().get_mut())
+    }
+}
+
+impl Read for Members {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let len = match self.member {
+                Some(ref mut member) => member.read(buf)?,
+                None => return Ok(0),
+            };
+            if len > 0 || buf.is_empty() {
+                return Ok(len);
+            }
+
+            let mut written = match self.member.take() {
+                Some(member) => member.into_written(),
+                None => return Ok(0),
+            };
+            match written.fill_buf() {
+                Ok(rest) if !rest.is_empty() => {
+                    self.member = Some(Member::new(self.compression, written)?)
+                }
+                Ok(_) => return Ok(0),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(0),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+    use tempfile::tempfile;
+
+    fn decompress(file: &File) -> String {
+        let compression = Compression::detect(file).unwrap().unwrap();
+        let mut decoder = Decoder::new(compression, 0).unwrap();
+        read_lines(&mut decoder, file).concat()
+    }
+
+    #[test]
+    fn detect_gzip() {
+        let file = tempfile().unwrap();
+        let mut encoder = GzEncoder::new(&file, flate2::Compression::default());
+        encoder.write_all(b"line 1\nline 2\n").unwrap();
+        encoder.finish().unwrap();
+
+        assert_eq!(Compression::detect(&file).unwrap(), Some(Compression::Gzip));
+        assert_eq!(decompress(&file), "line 1\nline 2\n");
+    }
+
+    #[test]
+    fn detect_zstd() {
+        let file = tempfile().unwrap();
+        let mut encoder = zstd::stream::write::Encoder::new(&file, 0).unwrap();
+        encoder.write_all(b"line 1\nline 2\n").unwrap();
+        encoder.finish().unwrap();
+
+        assert_eq!(Compression::detect(&file).unwrap(), Some(Compression::Zstd));
+        assert_eq!(decompress(&file), "line 1\nline 2\n");
+    }
+
+    fn read_lines(decoder: &mut Decoder, file: &File) -> Vec<String> {
+        decoder.attach(file).unwrap();
+        let mut lines = Vec::new();
+        loop {
+            match decoder.read_line() {
+                Ok(Some(line)) => lines.push(String::from_utf8(line).unwrap()),
+                Ok(None) => break,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => panic!("{}", e),
+            }
+        }
+        decoder.detach();
+        lines
+    }
+
+    #[test]
+    fn decoder_resumes() {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"line 1\nline 2\nline 3").unwrap();
+        let gzip = encoder.finish().unwrap();
+        let mut file = tempfile().unwrap();
+
+        // the lines are read as the file is written, without starting over
+        let mut decoder = Decoder::new(Compression::Gzip, 0).unwrap();
+        let mut lines = Vec::new();
+        for part in gzip.chunks(gzip.len() / 3 + 1) {
+            file.write_all(part).unwrap();
+            lines.append(&mut read_lines(&mut decoder, &file));
+        }
+        assert_eq!(lines, vec!["line 1\n", "line 2\n", "line 3"]);
+        assert!(decoder.read_line().unwrap().is_none());
+
+        // a file of two members is read to the end of the second one
+        let mut file = tempfile().unwrap();
+        file.write_all(&gzip).unwrap();
+        file.write_all(&gzip).unwrap();
+        let mut decoder = Decoder::new(Compression::Gzip, 14).unwrap();
+        assert_eq!(
+            read_lines(&mut decoder, &file),
+            vec!["line 3line 1\n", "line 2\n", "line 3"]
+        );
+
+        let file = tempfile().unwrap();
+        let mut encoder = zstd::stream::write::Encoder::new(&file, 0).unwrap();
+        encoder.write_all(b"line 1\nline 2\n").unwrap();
+        encoder.finish().unwrap();
+        let mut decoder = Decoder::new(Compression::Zstd, 7).unwrap();
+        assert_eq!(read_lines(&mut decoder, &file), vec!["line 2\n"]);
+    }
+
+    #[test]
+    fn detect_plain() {
+        let mut file = tempfile().unwrap();
+        assert_eq!(Compression::detect(&file).unwrap(), None);
+        file.write_all(b"line 1\n").unwrap();
+        assert_eq!(Compression::detect(&file).unwrap(), None);
+    }
+}
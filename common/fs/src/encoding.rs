@@ -0,0 +1,234 @@
+use crate::identity::FileId;
+use crate::rule::Rules;
+pub use encoding_rs::Encoding;
+use encoding_rs::{UTF_16BE, UTF_16LE, UTF_8};
+use hashbrown::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead};
+use std::os::unix::fs::FileExt;
+use std::path::PathBuf;
+
+// the longest byte order mark, the one of UTF-8
+const MAX_BOM_LEN: usize = 3;
+
+/// Decodes the files matching rules with an encoding other than UTF-8
+#[derive(Debug)]
+pub struct EncodingRule {
+    pub rules: Rules,
+    pub encoding: &'static Encoding,
+}
+
+/// The encoding a file is read with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Charset {
+    pub encoding: &'static Encoding,
+    /// The length of the byte order mark at the start of the file, 0 if there's none
+    pub bom_len: u64,
+}
+
+impl Charset {
+    /// Reads up to and including the next new line, returning the number of bytes read
+    pub fn read_line<R: BufRead>(&self, reader: &mut R, buf: &mut Vec<u8>) -> io::Result<usize> {
+        if self.encoding != UTF_16LE && self.encoding != UTF_16BE {
+            // a new line byte is always a new line in ASCII compatible encodings
+            return reader.read_until(b'\n', buf);
+        }
+
+        // a new line is a two byte unit in UTF-16, a new line byte in any other position is
+        // part of a different character
+        let start = buf.len();
+        loop {
+            if reader.read_until(b'\n', buf)? == 0 || buf.last() != Some(&b'\n') {
+                return Ok(buf.len() - start);
+            }
+            let len = buf.len() - start;
+            let odd = len % 2 == 1;
+            if self.encoding == UTF_16BE {
+                if !odd && buf[buf.len() - 2] == 0 {
+                    return Ok(len);
+                }
+            } else if odd {
+                let high = match reader.fill_buf()?.first() {
+                    Some(byte) => *byte,
+                    None => return Ok(len),
+                };
+                reader.consume(1);
+                buf.push(high);
+                if high == 0 {
+                    return Ok(len + 1);
+                }
+            }
+        }
+    }
+
+    /// The length in bytes of the code units lines are made of
+    pub fn unit_len(&self) -> u64 {
+        if self.encoding == UTF_16LE || self.encoding == UTF_16BE {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Decodes a line, invalid sequences are replaced with the replacement character
+    pub fn decode(&self, raw: Vec<u8>) -> String {
+        if self.encoding == UTF_8 {
+            return String::from_utf8(raw)
+                .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).to_string());
+        }
+        self.encoding
+            .decode_without_bom_handling(&raw)
+            .0
+            .into_owned()
+    }
+}
+
+/// Picks the charset of files from their byte order mark or the first rule matching them
+#[derive(Debug, Default)]
+pub struct Encodings {
+    rules: Vec<EncodingRule>,
+    assigned: HashMap<FileId, Charset>,
+}
+
+impl Encodings {
+    pub fn new(rules: Vec<EncodingRule>) -> Self {
+        Self {
+            rules,
+            assigned: HashMap::new(),
+        }
+    }
+
+    /// Returns the charset of a file, the result is cached until the file is removed
+    pub fn charset(&mut self, id: FileId, paths: &[PathBuf], file: &File) -> Charset {
+        if let Some(charset) = self.assigned.get(&id) {
+            return *charset;
+        }
+
+        let mut bom = [0; MAX_BOM_LEN];
+        let mut read = 0;
+        while read < bom.len() {
+            match file.read_at(&mut bom[read..], read as u64) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+
+        let charset = match Encoding::for_bom(&bom[..read]) {
+            Some((encoding, bom_len)) => Charset {
+                encoding,
+                bom_len: bom_len as u64,
+            },
+            None => Charset {
+                encoding: self
+                    .rules
+                    .iter()
+                    .find(|rule| paths.iter().any(|path| rule.rules.passes(path).is_ok()))
+                    .map_or(UTF_8, |rule| rule.encoding),
+                bom_len: 0,
+            },
+        };
+        // a file that is too short may still get a byte order mark
+        if read == bom.len() || charset.bom_len > 0 {
+            self.assigned.insert(id, charset);
+        }
+        charset
+    }
+
+    /// Forgets about the charset of a file, e.g. once it's deleted or truncated
+    pub fn remove(&mut self, id: FileId) {
+        self.assigned.remove(&id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rule::GlobRule;
+    use encoding_rs::{SHIFT_JIS, WINDOWS_1252};
+    use std::io::{BufReader, Write};
+    use tempfile::tempfile;
+
+    const ID: FileId = FileId { dev: 1, inode: 1 };
+
+    fn read_lines(charset: Charset, content: &[u8]) -> Vec<String> {
+        let mut reader = BufReader::new(content);
+        let mut lines = Vec::new();
+        loop {
+            let mut raw = Vec::new();
+            if charset.read_line(&mut reader, &mut raw).unwrap() == 0 {
+                return lines;
+            }
+            lines.push(charset.decode(raw));
+        }
+    }
+
+    fn utf16(line: &str, little_endian: bool) -> Vec<u8> {
+        line.encode_utf16()
+            .flat_map(|unit| {
+                if little_endian {
+                    unit.to_le_bytes()
+                } else {
+                    unit.to_be_bytes()
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn utf16_new_lines() {
+        // U+0A41 and U+410A contain a new line byte that isn't a new line
+        let content = "a\u{0a41}\nb\u{410a}\n";
+        for (encoding, little_endian) in &[(UTF_16LE, true), (UTF_16BE, false)] {
+            let charset = Charset {
+                encoding,
+                bom_len: 2,
+            };
+            assert_eq!(
+                read_lines(charset, &utf16(content, *little_endian)),
+                vec!["a\u{0a41}\n", "b\u{410a}\n"]
+            );
+        }
+    }
+
+    #[test]
+    fn legacy_encodings() {
+        let latin1 = Charset {
+            encoding: WINDOWS_1252,
+            bom_len: 0,
+        };
+        assert_eq!(read_lines(latin1, b"caf\xe9\n"), vec!["café\n"]);
+
+        let shift_jis = Charset {
+            encoding: SHIFT_JIS,
+            bom_len: 0,
+        };
+        assert_eq!(read_lines(shift_jis, b"\x83\x8d\x83\x4f\n"), vec!["ログ\n"]);
+    }
+
+    #[test]
+    fn bom_overrides_rules() {
+        let mut rules = Rules::new();
+        rules.add_inclusion(GlobRule::new("*.log").unwrap());
+        let mut encodings = Encodings::new(vec![EncodingRule {
+            rules,
+            encoding: WINDOWS_1252,
+        }]);
+        let paths = vec![PathBuf::from("/var/log/app.log")];
+
+        let mut file = tempfile().unwrap();
+        file.write_all(b"\xff\xfea\x00").unwrap();
+        let charset = encodings.charset(ID, &paths, &file);
+        assert_eq!(charset.encoding, UTF_16LE);
+        assert_eq!(charset.bom_len, 2);
+
+        encodings.remove(ID);
+        let file = tempfile().unwrap();
+        // a file without content isn't assigned a charset yet
+        assert_eq!(encodings.charset(ID, &paths, &file).encoding, WINDOWS_1252);
+        (&file).write_all(b"\xef\xbb\xbfline\n").unwrap();
+        assert_eq!(encodings.charset(ID, &paths, &file).encoding, UTF_8);
+        assert_eq!(encodings.charset(ID, &paths, &file).bom_len, 3);
+    }
+}
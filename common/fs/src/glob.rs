@@ -0,0 +1,393 @@
+use std::str::FromStr;
+use thiserror::Error;
+
+/// An error in the syntax of a glob pattern
+#[derive(Error, Clone, Debug, PartialEq)]
+#[error("Invalid glob {pattern} at {position}: {reason}")]
+pub struct Error {
+    pattern: String,
+    position: usize,
+    reason: &'static str,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    // a
+    Char(char),
+    // ?
+    AnyChar,
+    // *
+    AnySequence,
+    // **
+    AnyRecursive,
+    // [abc-z] or [!abc-z]
+    Range {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+    // ?(a|b)
+    ZeroOrOne(Vec<Vec<Token>>),
+    // *(a|b)
+    ZeroOrMore(Vec<Vec<Token>>),
+    // +(a|b)
+    OneOrMore(Vec<Vec<Token>>),
+    // @(a|b)
+    ExactlyOne(Vec<Vec<Token>>),
+    // !(a|b)
+    NoneOf(Vec<Vec<Token>>),
+}
+
+/// A glob pattern with the extended glob syntax
+///
+/// ```text
+/// ?           any character
+/// *           any sequence of characters, including /
+/// **          any sequence of path components, e.g. /var/**/*.log matches /var/a.log
+/// [abc]       one of the characters, [a-z] is a range of characters
+/// [!abc]      none of the characters
+/// ?(a|b)      zero or one of the patterns
+/// *(a|b)      zero or more of the patterns
+/// +(a|b)      one or more of the patterns
+/// @(a|b)      exactly one of the patterns
+/// !(a|b)      anything that doesn't match one of the patterns
+/// \*          escapes a character so it's matched literally
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pattern {
+    tokens: Vec<Token>,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Result<Self, Error> {
+        pattern.parse()
+    }
+
+    /// Returns true if the whole input matches the pattern
+    pub fn matches(&self, input: &str) -> bool {
+        let input = input.chars().collect::<Vec<_>>();
+        let mut starts = vec![false; input.len() + 1];
+        starts[0] = true;
+        match_tokens(&self.tokens, &input, starts)[input.len()]
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            pattern: s,
+            chars: s.chars().collect(),
+            i: 0,
+        };
+        let tokens = parser.parse(false)?.pop().unwrap_or_default();
+        Ok(Self { tokens })
+    }
+}
+
+struct Parser<'a> {
+    pattern: &'a str,
+    chars: Vec<char>,
+    i: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, position: usize, reason: &'static str) -> Error {
+        Error {
+            pattern: self.pattern.to_string(),
+            position,
+            reason,
+        }
+    }
+
+    // parses the alternatives of a pattern up to the ) ending it, or the end of the input if
+    // it's not nested
+    fn parse(&mut self, nested: bool) -> Result<Vec<Vec<Token>>, Error> {
+        let start = self.i;
+        let mut alternatives = vec![Vec::new()];
+        while self.i < self.chars.len() {
+            let c = self.chars[self.i];
+            let next = self.chars.get(self.i + 1).copied();
+            let token = match (c, next) {
+                ('?', Some('('))
+                | ('*', Some('('))
+                | ('+', Some('('))
+                | ('@', Some('('))
+                | ('!', Some('(')) => {
+                    let open = self.i + 1;
+                    self.i += 2;
+                    let patterns = self.parse(true)?;
+                    if patterns.iter().any(Vec::is_empty) {
+                        return Err(self.error(open, "patterns must not be empty"));
+                    }
+                    match c {
+                        '?' => Token::ZeroOrOne(patterns),
+                        '*' => Token::ZeroOrMore(patterns),
+                        '+' => Token::OneOrMore(patterns),
+                        '@' => Token::ExactlyOne(patterns),
+                        _ => Token::NoneOf(patterns),
+                    }
+                }
+                ('|', _) if nested => {
+                    self.i += 1;
+                    alternatives.push(Vec::new());
+                    continue;
+                }
+                (')', _) if nested => {
+                    self.i += 1;
+                    return Ok(alternatives);
+                }
+                ('(', _) | (')', _) | ('|', _) | (']', _) => {
+                    return Err(
+                        self.error(self.i, "illegal character, consider escaping it with \\")
+                    )
+                }
+                ('*', Some('*')) => self.parse_recursive()?,
+                ('*', _) => {
+                    self.i += 1;
+                    Token::AnySequence
+                }
+                ('?', _) => {
+                    self.i += 1;
+                    Token::AnyChar
+                }
+                ('\\', Some(escaped)) => {
+                    self.i += 2;
+                    Token::Char(escaped)
+                }
+                ('\\', None) => {
+                    return Err(self.error(self.i, "\\ must be followed by a character"))
+                }
+                ('[', _) => self.parse_range()?,
+                (c, _) => {
+                    self.i += 1;
+                    Token::Char(c)
+                }
+            };
+            alternatives.last_mut().unwrap().push(token);
+        }
+
+        if nested {
+            return Err(self.error(start - 1, "patterns must be ended with a )"));
+        }
+        Ok(alternatives)
+    }
+
+    fn parse_recursive(&mut self) -> Result<Token, Error> {
+        let start = self.i;
+        self.i += 2;
+        // ** has to be a whole path component
+        if start > 0 && !matches!(self.chars[start - 1], '/' | '(' | '|') {
+            return Err(self.error(start, "** must be a single path component"));
+        }
+        match self.chars.get(self.i) {
+            None | Some('/') | Some(')') | Some('|') => Ok(Token::AnyRecursive),
+            Some('*') => Err(self.error(self.i, "only * and ** are allowed")),
+            Some(_) => Err(self.error(start, "** must be a single path component")),
+        }
+    }
+
+    fn parse_range(&mut self) -> Result<Token, Error> {
+        let start = self.i;
+        self.i += 1;
+        let negated = matches!(self.chars.get(self.i), Some('!') | Some('^'));
+        if negated {
+            self.i += 1;
+        }
+
+        let mut ranges = Vec::new();
+        loop {
+            let c = match self.chars.get(self.i) {
+                None => return Err(self.error(start, "ranges must be ended with a ]")),
+                // a ] right at the start is part of the range
+                Some(']') if !ranges.is_empty() => {
+                    self.i += 1;
+                    return Ok(Token::Range { negated, ranges });
+                }
+                Some(c) => *c,
+            };
+            match (self.chars.get(self.i + 1), self.chars.get(self.i + 2)) {
+                (Some('-'), Some(end)) if *end != ']' => {
+                    ranges.push((c, *end));
+                    self.i += 3;
+                }
+                _ => {
+                    ranges.push((c, c));
+                    self.i += 1;
+                }
+            }
+        }
+    }
+}
+
+// the positions in the input that the tokens can match up to from any of the start positions.
+// Matching tracks the set of positions instead of backtracking, so patterns with many wildcards
+// take polynomial rather than exponential time.
+fn match_tokens(tokens: &[Token], input: &[char], starts: Positions) -> Positions {
+    let mut positions = starts;
+    let mut i = 0;
+    while i < tokens.len() && positions.contains(&true) {
+        positions = match &tokens[i] {
+            Token::Char(c) => step(input, &positions, |next| next == *c),
+            Token::AnyChar => step(input, &positions, |_| true),
+            Token::Range { negated, ranges } => step(input, &positions, |next| {
+                ranges
+                    .iter()
+                    .any(|(start, end)| *start <= next && next <= *end)
+                    != *negated
+            }),
+            Token::AnySequence => any_sequence(&positions),
+            // **/ also matches no path components at all
+            Token::AnyRecursive if tokens.get(i + 1) == Some(&Token::Char('/')) => {
+                i += 1;
+                let mut after = step(input, &any_sequence(&positions), |next| next == '/');
+                union(&mut after, &positions);
+                after
+            }
+            Token::AnyRecursive => any_sequence(&positions),
+            Token::ZeroOrOne(patterns) => {
+                let mut after = match_any(patterns, input, &positions);
+                union(&mut after, &positions);
+                after
+            }
+            Token::ZeroOrMore(patterns) => match_repeated(patterns, input, positions),
+            Token::OneOrMore(patterns) => {
+                match_repeated(patterns, input, match_any(patterns, input, &positions))
+            }
+            Token::ExactlyOne(patterns) => match_any(patterns, input, &positions),
+            Token::NoneOf(patterns) => {
+                let mut after = vec![false; positions.len()];
+                for start in (0..positions.len()).filter(|start| positions[*start]) {
+                    let mut only = vec![false; positions.len()];
+                    only[start] = true;
+                    let matched = match_any(patterns, input, &only);
+                    for (end, after) in after.iter_mut().enumerate().skip(start) {
+                        *after |= !matched[end];
+                    }
+                }
+                after
+            }
+        };
+        i += 1;
+    }
+    positions
+}
+
+// a set of positions in the input, from before the first character to after the last
+type Positions = Vec<bool>;
+
+// the positions one character further, where the character is accepted
+fn step(input: &[char], positions: &[bool], accept: impl Fn(char) -> bool) -> Positions {
+    let mut after = vec![false; positions.len()];
+    for (position, c) in input.iter().enumerate() {
+        after[position + 1] = positions[position] && accept(*c);
+    }
+    after
+}
+
+// every position at or after one of the positions
+fn any_sequence(positions: &[bool]) -> Positions {
+    let mut reached = false;
+    positions
+        .iter()
+        .map(|position| {
+            reached |= *position;
+            reached
+        })
+        .collect()
+}
+
+fn union(positions: &mut [bool], other: &[bool]) {
+    for (position, other) in positions.iter_mut().zip(other) {
+        *position |= *other;
+    }
+}
+
+fn match_any(patterns: &[Vec<Token>], input: &[char], positions: &[bool]) -> Positions {
+    let mut after = vec![false; positions.len()];
+    for pattern in patterns {
+        union(
+            &mut after,
+            &match_tokens(pattern, input, positions.to_vec()),
+        );
+    }
+    after
+}
+
+// matches the patterns zero or more times, until the repetitions reach no new positions
+fn match_repeated(patterns: &[Vec<Token>], input: &[char], positions: Positions) -> Positions {
+    let mut reached = positions.clone();
+    let mut frontier = positions;
+    while frontier.contains(&true) {
+        frontier = match_any(patterns, input, &frontier);
+        for (position, reached) in frontier.iter_mut().zip(reached.iter_mut()) {
+            *position &= !*reached;
+            *reached |= *position;
+        }
+    }
+    reached
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn matches(pattern: &str, input: &str) -> bool {
+        Pattern::new(pattern).unwrap().matches(input)
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*.log", "/var/log/syslog.log"));
+        assert!(!matches("*.log", "/var/log/syslog.log.1"));
+        assert!(matches("/var/log/sar*", "/var/log/sar01"));
+        assert!(matches("/var/log/?.log", "/var/log/a.log"));
+        assert!(!matches("/var/log/?.log", "/var/log/ab.log"));
+        assert!(matches("/var/log/[a-c]x[!0-9].log", "/var/log/bxz.log"));
+        assert!(!matches("/var/log/[a-c]x[!0-9].log", "/var/log/bx1.log"));
+        assert!(matches(r"/var/log/\*.log", "/var/log/*.log"));
+        assert!(!matches(r"/var/log/\*.log", "/var/log/a.log"));
+    }
+
+    #[test]
+    fn recursive_wildcards() {
+        assert!(matches("/var/log/**", "/var/log/a/b"));
+        assert!(matches("/var/log/pods/**/*", "/var/log/pods/a.log"));
+        assert!(matches("/var/log/pods/**/*", "/var/log/pods/a/b/c.log"));
+        assert!(matches("some/**/**/needle.txt", "some/needle.txt"));
+        assert!(matches("**/test", "test"));
+        assert!(!matches("/**/test", "/one/notthis"));
+        assert!(Pattern::new("/var/log**").is_err());
+        assert!(Pattern::new("/var/***").is_err());
+    }
+
+    #[test]
+    fn extended() {
+        assert!(matches("!(*.*)", "/var/log/syslog"));
+        assert!(!matches("!(*.*)", "/var/log/syslog.log"));
+        assert!(matches("/var/log/+(a|b).log", "/var/log/abba.log"));
+        assert!(!matches("/var/log/+(a|b).log", "/var/log/.log"));
+        assert!(matches("/var/log/*(ab|cd).log", "/var/log/.log"));
+        assert!(matches("/var/log/*(ab|cd).log", "/var/log/abcdab.log"));
+        assert!(matches("/var/log/@(app|web)/*.log", "/var/log/web/a.log"));
+        assert!(!matches("/var/log/@(app|web)/*.log", "/var/log/db/a.log"));
+        assert!(matches("/var/log/app?(.1).log", "/var/log/app.1.log"));
+        assert!(matches("/var/log/app?(.1).log", "/var/log/app.log"));
+        assert!(matches("/var/log/!(app).log", "/var/log/web.log"));
+        assert!(!matches("/var/log/!(app).log", "/var/log/app.log"));
+        assert!(matches("/var/log/@(a|+(b|c)d).log", "/var/log/bcbd.log"));
+        assert!(Pattern::new("/var/log/@(a|b").is_err());
+        assert!(Pattern::new("/var/log/@(a|)").is_err());
+        assert!(Pattern::new("/var/log/(a)").is_err());
+    }
+
+    #[test]
+    fn many_wildcards() {
+        let path = format!("/var/log/{}", "a".repeat(1024));
+        let start = std::time::Instant::now();
+        assert!(!matches("*a*a*a*a*a*a*a*a*a*a*b", &path));
+        assert!(!matches("/**/*a*(a|aa)*a!(*a)b", &path));
+        assert!(matches("*a*a*a*a*a*a*a*a*a*a", &path));
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{File, Metadata};
+use std::io;
+use std::os::unix::fs::{FileExt, MetadataExt};
+
+/// The number of leading bytes of a file that make up it's fingerprint
+pub const FINGERPRINT_LEN: u64 = 1024;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Identifies a file independently of the path(s) it is reachable through
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FileId {
+    pub dev: u64,
+    pub inode: u64,
+}
+
+impl From<&Metadata> for FileId {
+    fn from(metadata: &Metadata) -> Self {
+        Self {
+            dev: metadata.dev(),
+            inode: metadata.ino(),
+        }
+    }
+}
+
+/// A hash of the first bytes of a file, used to tell if the content of a file was replaced
+/// while it's dev and inode stayed the same, e.g. by a copytruncate rotation or inode reuse
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Fingerprint {
+    /// The number of bytes that were hashed, at most FINGERPRINT_LEN
+    pub len: u64,
+    pub hash: u64,
+}
+
+impl Default for Fingerprint {
+    fn default() -> Self {
+        Self {
+            len: 0,
+            hash: FNV_OFFSET_BASIS,
+        }
+    }
+}
+
+impl Fingerprint {
+    /// Fingerprints the first FINGERPRINT_LEN bytes of a file
+    pub fn of(file: &File) -> io::Result<Self> {
+        Fingerprint::of_prefix(file, FINGERPRINT_LEN)
+    }
+
+    /// Checks if the file still starts with the bytes this fingerprint was taken from
+    pub fn matches(&self, file: &File) -> io::Result<bool> {
+        if self.len == 0 {
+            return Ok(true);
+        }
+        Ok(Fingerprint::of_prefix(file, self.len)? == *self)
+    }
+
+    fn of_prefix(file: &File, len: u64) -> io::Result<Self> {
+        let mut buf = vec![0; len as usize];
+        let mut read = 0;
+        // read_at doesn't move the cursor of the file so this can't interfere with tailing
+        while read < buf.len() {
+            match file.read_at(&mut buf[read..], read as u64) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        // FNV-1a, stable across restarts unlike the std hasher
+        let hash = buf[..read].iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME)
+        });
+        Ok(Self {
+            len: read as u64,
+            hash,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempfile;
+
+    #[test]
+    fn fingerprint_matches_appended_file() {
+        let mut file = tempfile().unwrap();
+        writeln!(file, "first line").unwrap();
+        let fingerprint = Fingerprint::of(&file).unwrap();
+        assert_eq!(fingerprint.len, 11);
+
+        writeln!(file, "second line").unwrap();
+        assert!(fingerprint.matches(&file).unwrap());
+        assert_ne!(Fingerprint::of(&file).unwrap(), fingerprint);
+    }
+
+    #[test]
+    fn fingerprint_detects_rewritten_file() {
+        let mut file = tempfile().unwrap();
+        writeln!(file, "first line").unwrap();
+        let fingerprint = Fingerprint::of(&file).unwrap();
+
+        file.set_len(0).unwrap();
+        file.write_all_at(b"other line\nmore data\n", 0).unwrap();
+        assert!(!fingerprint.matches(&file).unwrap());
+    }
+
+    #[test]
+    fn fingerprint_is_bounded() {
+        let mut file = tempfile().unwrap();
+        file.write_all(&vec![b'a'; FINGERPRINT_LEN as usize * 2])
+            .unwrap();
+        assert_eq!(Fingerprint::of(&file).unwrap().len, FINGERPRINT_LEN);
+    }
+}
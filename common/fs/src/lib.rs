@@ -6,8 +6,20 @@ extern crate lazy_static;
 
 /// Prototype
 pub mod cache;
+/// Detects and decompresses compressed rotated files
+pub mod compression;
+/// Detects and decodes the character encoding of files
+pub mod encoding;
 /// Contains the error type(s) for this crate
 pub mod error;
+/// Matches paths against extended glob patterns
+pub mod glob;
+/// Stable identities for tailed files
+pub mod identity;
+/// Decides where tailing files that exist on startup begins
+pub mod lookback;
+/// Merges multiple lines of a file into single logical lines
+pub mod multiline;
 /// Traits and types for defining exclusion and inclusion rules
 pub mod rule;
 /// Defines the source implementation for fs
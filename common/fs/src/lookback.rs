@@ -0,0 +1,399 @@
+use crate::encoding::Charset;
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, TimeZone, Utc};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, Seek, SeekFrom};
+use std::os::unix::fs::FileExt;
+use std::time::Duration;
+use thiserror::Error;
+
+/// The size in bytes up to which a file is read from the start by the `smallfiles` lookback
+pub const DEFAULT_SMALL_FILES_BYTES: u64 = 8192;
+
+// the size of the chunks a file is read backwards in when looking for it's last lines
+const CHUNK_LEN: u64 = 64 * 1024;
+// how far past an offset a line with a timestamp is looked for
+const MAX_TIMESTAMP_SCAN: u64 = 64 * 1024;
+
+/// Decides where tailing a file that already exists on startup begins
+#[derive(Clone, Debug, PartialEq)]
+pub enum Lookback {
+    /// Starts at the beginning of the file
+    Start,
+    /// Starts files smaller than the threshold in bytes at the beginning and others at the end
+    SmallFiles(u64),
+    /// Starts at the end of the file
+    None,
+    /// Starts at the last lines of the file
+    Lines(u64),
+    /// Starts at the first line that is newer than the duration
+    Time(Duration),
+}
+
+#[derive(Error, Debug)]
+pub enum ParseLookbackError {
+    #[error("Unknown lookback strategy: {0}")]
+    Unknown(String),
+    #[error("Invalid lookback {0}: {1}")]
+    Invalid(String, &'static str),
+}
+
+impl std::str::FromStr for Lookback {
+    type Err = ParseLookbackError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s.to_lowercase().split_whitespace().collect::<String>();
+        let (strategy, arg) = match normalized.find(':') {
+            Some(idx) => (&normalized[..idx], Some(&normalized[idx + 1..])),
+            None => (normalized.as_str(), None),
+        };
+        let invalid = |reason| ParseLookbackError::Invalid(s.into(), reason);
+
+        match (strategy, arg) {
+            ("start", None) => Ok(Lookback::Start),
+            ("smallfiles", None) => Ok(Lookback::SmallFiles(DEFAULT_SMALL_FILES_BYTES)),
+            ("smallfiles", Some(bytes)) => bytes
+                .parse()
+                .map(Lookback::SmallFiles)
+                .map_err(|_| invalid("the threshold has to be a number of bytes")),
+            ("none", None) => Ok(Lookback::None),
+            ("lines", Some(lines)) => lines
+                .parse()
+                .map(Lookback::Lines)
+                .map_err(|_| invalid("the lines have to be a number")),
+            ("time", Some(duration)) => {
+                parse_duration(duration).map(Lookback::Time).ok_or_else(|| {
+                    invalid("the duration has to be a number with a unit of s, m, h or d")
+                })
+            }
+            _ => Err(ParseLookbackError::Unknown(s.into())),
+        }
+    }
+}
+
+impl Default for Lookback {
+    fn default() -> Self {
+        Lookback::SmallFiles(DEFAULT_SMALL_FILES_BYTES)
+    }
+}
+
+impl Lookback {
+    /// Returns the offset at which tailing a file starts, lines are found with the file's charset
+    pub fn offset(&self, file: &File, charset: Charset) -> io::Result<u64> {
+        let metadata = file.metadata()?;
+        let len = metadata.len();
+        match self {
+            Lookback::Start => Ok(0),
+            Lookback::SmallFiles(threshold) if len < *threshold => Ok(0),
+            Lookback::SmallFiles(_) | Lookback::None => Ok(len),
+            Lookback::Lines(lines) => last_lines(file, len, *lines, charset),
+            Lookback::Time(window) => {
+                // a window too long to subtract from now reaches back to the epoch
+                let cutoff = match chrono::Duration::from_std(*window)
+                    .ok()
+                    .and_then(|window| Utc::now().checked_sub_signed(window))
+                    .or_else(|| Utc.timestamp_opt(0, 0).single())
+                {
+                    Some(cutoff) => cutoff,
+                    None => return Ok(0),
+                };
+                // a file that wasn't written to within the window has no new lines
+                if DateTime::<Utc>::from(metadata.modified()?) < cutoff {
+                    return Ok(len);
+                }
+                first_line_since(file, len, cutoff, charset)
+            }
+        }
+    }
+}
+
+// parses durations like 30s, 15m, 2h or 1d
+fn parse_duration(s: &str) -> Option<Duration> {
+    let idx = s.find(|c: char| !c.is_ascii_digit())?;
+    let value: u64 = s[..idx].parse().ok()?;
+    let unit = match &s[idx..] {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(Duration::from_secs(value.checked_mul(unit)?))
+}
+
+// returns the offset of the start of the last lines of a file by reading it backwards, files
+// in encodings where a new line byte may be part of another character are read from the start
+fn last_lines(file: &File, len: u64, lines: u64, charset: Charset) -> io::Result<u64> {
+    if lines == 0 {
+        return Ok(len);
+    }
+    if !charset.encoding.is_ascii_compatible() {
+        return last_lines_from_start(file, len, lines, charset);
+    }
+
+    let mut buf = vec![0; CHUNK_LEN as usize];
+    let mut end = len;
+    let mut found = 0;
+    while end > 0 {
+        let start = end.saturating_sub(CHUNK_LEN);
+        let chunk = &mut buf[..(end - start) as usize];
+        file.read_exact_at(chunk, start)?;
+        for (idx, byte) in chunk.iter().enumerate().rev() {
+            // the new line ending the last line doesn't start a line
+            if *byte != b'\n' || start + idx as u64 + 1 == len {
+                continue;
+            }
+            found += 1;
+            if found == lines {
+                return Ok(start + idx as u64 + 1);
+            }
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+fn last_lines_from_start(file: &File, len: u64, lines: u64, charset: Charset) -> io::Result<u64> {
+    let mut reader = BufReader::new(file);
+    reader.seek(SeekFrom::Start(charset.bom_len))?;
+    let mut starts = VecDeque::new();
+    let mut position = charset.bom_len;
+    let mut line = Vec::new();
+    while position < len {
+        line.clear();
+        let read = charset.read_line(&mut reader, &mut line)? as u64;
+        if read == 0 {
+            break;
+        }
+        if starts.len() as u64 == lines {
+            starts.pop_front();
+        }
+        starts.push_back(position);
+        position += read;
+    }
+    Ok(starts.front().copied().unwrap_or(len))
+}
+
+// returns the offset of the first line with a timestamp at or after the cutoff, timestamps of
+// a log only go up so the line is found by bisecting the file
+fn first_line_since(
+    file: &File,
+    len: u64,
+    cutoff: DateTime<Utc>,
+    charset: Charset,
+) -> io::Result<u64> {
+    let mut low = 0;
+    let mut high = len;
+    while low < high {
+        let mid = low + (high - low) / 2;
+        match timestamp_after(file, mid, len, charset)? {
+            Some((_, timestamp)) if timestamp < cutoff => low = mid + 1,
+            _ => high = mid,
+        }
+    }
+
+    Ok(match timestamp_after(file, low, len, charset)? {
+        Some((offset, timestamp)) if timestamp >= cutoff => offset,
+        _ => len,
+    })
+}
+
+// returns the offset and timestamp of the first line starting at or after an offset that
+// starts with a timestamp
+fn timestamp_after(
+    file: &File,
+    offset: u64,
+    len: u64,
+    charset: Charset,
+) -> io::Result<Option<(u64, DateTime<Utc>)>> {
+    let mut reader = BufReader::new(file);
+    let mut line = Vec::new();
+    let mut position = charset.bom_len;
+    // the rest of the line the character before the offset belongs to is skipped, lines start
+    // at a whole code unit after the byte order mark
+    if offset > position {
+        let unit_len = charset.unit_len();
+        position += (offset - 1 - position) / unit_len * unit_len;
+        reader.seek(SeekFrom::Start(position))?;
+        position += charset.read_line(&mut reader, &mut line)? as u64;
+    } else {
+        reader.seek(SeekFrom::Start(position))?;
+    }
+
+    while position < len && position < offset + MAX_TIMESTAMP_SCAN {
+        line.clear();
+        let read = charset.read_line(&mut reader, &mut line)? as u64;
+        if read == 0 {
+            break;
+        }
+        if let Some(timestamp) = parse_timestamp(&charset.decode(std::mem::take(&mut line))) {
+            return Ok(Some((position, timestamp)));
+        }
+        position += read;
+    }
+    Ok(None)
+}
+
+// parses a timestamp at the start of a line, e.g. RFC 3339, `2006-01-02 15:04:05` in local
+// time or the syslog format `Jan  2 15:04:05`
+fn parse_timestamp(line: &str) -> Option<DateTime<Utc>> {
+    let line = line.trim_start_matches('[');
+    let token = line.split(|c: char| c.is_whitespace() || c == ']').next()?;
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(token) {
+        return Some(timestamp.with_timezone(&Utc));
+    }
+
+    if let Some(Ok(naive)) = line.get(..19).map(|prefix| {
+        NaiveDateTime::parse_from_str(&prefix.replacen('T', " ", 1), "%Y-%m-%d %H:%M:%S")
+    }) {
+        return Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .map(|timestamp| timestamp.with_timezone(&Utc));
+    }
+
+    // syslog timestamps don't have a year, a timestamp in the future is from last year
+    let prefix = line.get(..15)?;
+    let now = Local::now();
+    let parse = |year: i32| {
+        NaiveDateTime::parse_from_str(&format!("{} {}", year, prefix), "%Y %b %e %H:%M:%S")
+            .ok()
+            .and_then(|naive| Local.from_local_datetime(&naive).earliest())
+    };
+    let timestamp = parse(now.year())?;
+    let timestamp = if timestamp > now + chrono::Duration::days(1) {
+        parse(now.year() - 1)?
+    } else {
+        timestamp
+    };
+    Some(timestamp.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use encoding_rs::{UTF_16LE, UTF_8};
+    use std::io::Write;
+    use tempfile::tempfile;
+
+    const UTF8: Charset = Charset {
+        encoding: UTF_8,
+        bom_len: 0,
+    };
+
+    fn file_with(content: &str) -> File {
+        let mut file = tempfile().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        file
+    }
+
+    // writes the content as UTF-16LE after a byte order mark
+    fn utf16_file_with(content: &str) -> File {
+        let mut file = tempfile().unwrap();
+        file.write_all(&[0xFF, 0xFE]).unwrap();
+        for unit in content.encode_utf16() {
+            file.write_all(&unit.to_le_bytes()).unwrap();
+        }
+        file
+    }
+
+    #[test]
+    fn parse_lookback() {
+        assert_eq!("start".parse::<Lookback>().unwrap(), Lookback::Start);
+        assert_eq!(
+            "SmallFiles".parse::<Lookback>().unwrap(),
+            Lookback::SmallFiles(DEFAULT_SMALL_FILES_BYTES)
+        );
+        assert_eq!(
+            "smallfiles:1024".parse::<Lookback>().unwrap(),
+            Lookback::SmallFiles(1024)
+        );
+        assert_eq!(
+            "lines: 100".parse::<Lookback>().unwrap(),
+            Lookback::Lines(100)
+        );
+        assert_eq!(
+            "time:2h".parse::<Lookback>().unwrap(),
+            Lookback::Time(Duration::from_secs(7200))
+        );
+        assert!("time:2y".parse::<Lookback>().is_err());
+        assert!("lines".parse::<Lookback>().is_err());
+        assert!("end".parse::<Lookback>().is_err());
+    }
+
+    #[test]
+    fn last_lines_offset() {
+        let file = file_with("a\nb\nc\n");
+        assert_eq!(Lookback::Lines(2).offset(&file, UTF8).unwrap(), 2);
+        assert_eq!(Lookback::Lines(5).offset(&file, UTF8).unwrap(), 0);
+        assert_eq!(Lookback::Lines(0).offset(&file, UTF8).unwrap(), 6);
+
+        // a line that is still being written counts as the last line
+        let file = file_with("a\nb\nc");
+        assert_eq!(Lookback::Lines(1).offset(&file, UTF8).unwrap(), 4);
+
+        // the new line byte in \u{0a0a} isn't a new line in UTF-16
+        let utf16 = Charset {
+            encoding: UTF_16LE,
+            bom_len: 2,
+        };
+        let file = utf16_file_with("a\nb\u{0a0a}\nc\n");
+        assert_eq!(Lookback::Lines(2).offset(&file, utf16).unwrap(), 6);
+        assert_eq!(Lookback::Lines(3).offset(&file, utf16).unwrap(), 2);
+    }
+
+    #[test]
+    fn time_window_offset() {
+        let now = Utc::now();
+        let old = (now - chrono::Duration::hours(3)).to_rfc3339();
+        let new = (now - chrono::Duration::minutes(30)).to_rfc3339();
+        let content = format!(
+            "{} old\n  continued\n{} old\n{} new\nno timestamp\n{} new\n",
+            old, old, new, new
+        );
+        let file = file_with(&content);
+        let expected = content.find(&format!("{} new", new)).unwrap() as u64;
+        assert_eq!(
+            Lookback::Time(Duration::from_secs(3600))
+                .offset(&file, UTF8)
+                .unwrap(),
+            expected
+        );
+        // nothing is newer than a minute
+        assert_eq!(
+            Lookback::Time(Duration::from_secs(60))
+                .offset(&file, UTF8)
+                .unwrap(),
+            content.len() as u64
+        );
+    }
+
+    #[test]
+    fn utf16_time_window_offset() {
+        let now = Utc::now();
+        let old = (now - chrono::Duration::hours(3)).to_rfc3339();
+        let new = (now - chrono::Duration::minutes(30)).to_rfc3339();
+        let content = format!("{} old\n{} old\n{} new\n", old, old, new);
+        let file = utf16_file_with(&content);
+        let charset = Charset {
+            encoding: UTF_16LE,
+            bom_len: 2,
+        };
+        let expected = content.find(&format!("{} new", new)).unwrap() as u64 * 2 + 2;
+        assert_eq!(
+            Lookback::Time(Duration::from_secs(3600))
+                .offset(&file, charset)
+                .unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn timestamp_formats() {
+        assert!(parse_timestamp("2020-01-02T03:04:05.678Z message").is_some());
+        assert!(parse_timestamp("[2020-01-02T03:04:05+01:00] message").is_some());
+        assert!(parse_timestamp("2020-01-02 03:04:05,678 INFO message").is_some());
+        assert!(parse_timestamp("Jan  2 03:04:05 host app[1]: message").is_some());
+        assert!(parse_timestamp("message without a timestamp").is_none());
+    }
+}
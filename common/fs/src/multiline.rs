@@ -0,0 +1,301 @@
+use crate::identity::FileId;
+use crate::rule::Rules;
+use hashbrown::hash_map::Entry as HashMapEntry;
+use hashbrown::HashMap;
+use pcre2::bytes::Regex;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// The default maximum number of lines merged into one logical line
+pub const DEFAULT_MAX_LINES: usize = 500;
+/// The default maximum size in bytes of a logical line
+pub const DEFAULT_MAX_BYTES: usize = 64 * 1024;
+/// The default time a logical line waits for more lines before it's sent
+pub const DEFAULT_FLUSH_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Decides if a line continues the logical line before it
+#[derive(Debug)]
+pub enum Matcher {
+    /// Lines matching the pattern start a new logical line, all other lines continue it
+    Start(Regex),
+    /// Lines matching the pattern continue the logical line, all other lines start a new one
+    Continuation(Regex),
+    /// Lines starting with whitespace continue the logical line, e.g. stack traces
+    Indent,
+}
+
+impl Matcher {
+    fn continues(&self, line: &str) -> bool {
+        match self {
+            Matcher::Start(regex) => !regex.is_match(line.as_bytes()).unwrap_or(false),
+            Matcher::Continuation(regex) => regex.is_match(line.as_bytes()).unwrap_or(false),
+            Matcher::Indent => line.starts_with(char::is_whitespace),
+        }
+    }
+}
+
+/// Merges the lines of the files matching rules into logical lines
+#[derive(Debug)]
+pub struct MultilineRule {
+    pub rules: Rules,
+    pub matcher: Matcher,
+    pub max_lines: usize,
+    pub max_bytes: usize,
+    pub flush_timeout: Duration,
+}
+
+impl MultilineRule {
+    /// Creates a new rule for the files matching rules with the default limits
+    pub fn new(rules: Rules, matcher: Matcher) -> Self {
+        Self {
+            rules,
+            matcher,
+            max_lines: DEFAULT_MAX_LINES,
+            max_bytes: DEFAULT_MAX_BYTES,
+            flush_timeout: DEFAULT_FLUSH_TIMEOUT,
+        }
+    }
+}
+
+/// A logical line made up of one or more lines of a file
+#[derive(Debug)]
+pub struct Aggregate {
+    pub id: FileId,
+    pub paths: Vec<PathBuf>,
+    pub line: String,
+    /// The offset in the file right after the last line of the aggregate
+    pub end: u64,
+}
+
+#[derive(Debug)]
+struct Pending {
+    rule: usize,
+    paths: Vec<PathBuf>,
+    line: String,
+    lines: usize,
+    start: u64,
+    end: u64,
+    updated: Instant,
+}
+
+/// Holds the logical lines of files that may still be continued
+#[derive(Debug, Default)]
+pub struct Multiline {
+    rules: Vec<MultilineRule>,
+    assigned: HashMap<FileId, Option<usize>>,
+    pending: HashMap<FileId, Pending>,
+}
+
+impl Multiline {
+    pub fn new(rules: Vec<MultilineRule>) -> Self {
+        Self {
+            rules,
+            assigned: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Whether there are rules, files are only aggregated if there are
+    pub fn is_enabled(&self) -> bool {
+        !self.rules.is_empty()
+    }
+
+    /// Feeds a line of a file spanning start..end into the aggregation,
+    /// returning the logical lines that are complete
+    pub fn push(
+        &mut self,
+        id: FileId,
+        paths: &[PathBuf],
+        start: u64,
+        end: u64,
+        line: String,
+    ) -> Vec<String> {
+        let rule_idx = match self.rule(id, paths) {
+            Some(idx) => idx,
+            None => return vec![line],
+        };
+        let rule = &self.rules[rule_idx];
+
+        let mut complete = Vec::new();
+        if let HashMapEntry::Occupied(mut entry) = self.pending.entry(id) {
+            let pending = entry.get_mut();
+            if rule.matcher.continues(&line)
+                && pending.lines < rule.max_lines
+                && pending.line.len() + 1 + line.len() <= rule.max_bytes
+            {
+                pending.line.push('\n');
+                pending.line.push_str(&line);
+                pending.lines += 1;
+                pending.end = end;
+                pending.paths = paths.to_vec();
+                pending.updated = Instant::now();
+                return complete;
+            }
+            complete.push(entry.remove().line);
+        }
+
+        self.pending.insert(
+            id,
+            Pending {
+                rule: rule_idx,
+                paths: paths.to_vec(),
+                line,
+                lines: 1,
+                start,
+                end,
+                updated: Instant::now(),
+            },
+        );
+        complete
+    }
+
+    /// Takes the logical lines that weren't continued within their rule's flush timeout
+    pub fn flush_expired(&mut self) -> Vec<Aggregate> {
+        let rules = &self.rules;
+        let expired = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.updated.elapsed() >= rules[pending.rule].flush_timeout)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+
+        expired.into_iter().filter_map(|id| self.take(id)).collect()
+    }
+
+    /// Takes the pending logical line of a file and forgets about the file, e.g. once it's deleted
+    pub fn remove(&mut self, id: FileId) -> Option<Aggregate> {
+        self.assigned.remove(&id);
+        self.take(id)
+    }
+
+    /// Forgets the rule assigned to a file, so it's matched again against the paths the file is
+    /// reachable through once one of them is deleted
+    pub fn unassign(&mut self, id: FileId) {
+        self.assigned.remove(&id);
+    }
+
+    /// Returns the offset of the first line of a file that is waiting to be sent
+    pub fn pending_offset(&self, id: FileId) -> Option<u64> {
+        self.pending.get(&id).map(|pending| pending.start)
+    }
+
+    fn take(&mut self, id: FileId) -> Option<Aggregate> {
+        self.pending.remove(&id).map(|pending| Aggregate {
+            id,
+            paths: pending.paths,
+            line: pending.line,
+            end: pending.end,
+        })
+    }
+
+    // returns the index of the first rule matching the file, the result is cached per file
+    fn rule(&mut self, id: FileId, paths: &[PathBuf]) -> Option<usize> {
+        if self.rules.is_empty() {
+            return None;
+        }
+
+        let rules = &self.rules;
+        *self.assigned.entry(id).or_insert_with(|| {
+            rules
+                .iter()
+                .position(|rule| paths.iter().any(|path| rule.rules.passes(path).is_ok()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rule::GlobRule;
+
+    const ID: FileId = FileId { dev: 1, inode: 1 };
+
+    fn multiline(matcher: Matcher) -> Multiline {
+        let mut rules = Rules::new();
+        rules.add_inclusion(GlobRule::new("*.log").unwrap());
+        Multiline::new(vec![MultilineRule::new(rules, matcher)])
+    }
+
+    fn push_all(multiline: &mut Multiline, path: &str, lines: &[&str]) -> Vec<String> {
+        let paths = vec![PathBuf::from(path)];
+        let mut offset = 0;
+        let mut complete = Vec::new();
+        for line in lines {
+            let end = offset + line.len() as u64 + 1;
+            complete.append(&mut multiline.push(ID, &paths, offset, end, line.to_string()));
+            offset = end;
+        }
+        complete
+    }
+
+    #[test]
+    fn start_pattern() {
+        let mut multiline = multiline(Matcher::Start(Regex::new(r"^\d{4}-").unwrap()));
+        let complete = push_all(
+            &mut multiline,
+            "/var/log/app.log",
+            &[
+                "2020-01-01 Exception in thread \"main\"",
+                "\tat com.example.Main.main(Main.java:1)",
+                "2020-01-01 done",
+            ],
+        );
+        assert_eq!(
+            complete,
+            vec![
+                "2020-01-01 Exception in thread \"main\"\n\tat com.example.Main.main(Main.java:1)"
+            ]
+        );
+        assert_eq!(multiline.pending_offset(ID), Some(77));
+
+        let aggregate = multiline.remove(ID).unwrap();
+        assert_eq!(aggregate.line, "2020-01-01 done");
+        assert_eq!(aggregate.end, 93);
+        assert_eq!(multiline.pending_offset(ID), None);
+    }
+
+    #[test]
+    fn continuation_pattern() {
+        let mut multiline = multiline(Matcher::Continuation(Regex::new(r"^\.\.\.").unwrap()));
+        let complete = push_all(&mut multiline, "/var/log/app.log", &["a", "...b", "c", "d"]);
+        assert_eq!(complete, vec!["a\n...b", "c"]);
+    }
+
+    #[test]
+    fn indent_with_limits() {
+        let mut multiline = multiline(Matcher::Indent);
+        multiline.rules[0].max_lines = 2;
+        let complete = push_all(
+            &mut multiline,
+            "/var/log/app.log",
+            &["Traceback:", "  line 1", "  line 2", "next"],
+        );
+        assert_eq!(complete, vec!["Traceback:\n  line 1", "  line 2"]);
+    }
+
+    #[test]
+    fn unmatched_files_pass_through() {
+        let mut multiline = multiline(Matcher::Indent);
+        let complete = push_all(&mut multiline, "/var/log/app.txt", &["a", "  b"]);
+        assert_eq!(complete, vec!["a", "  b"]);
+        assert_eq!(multiline.pending_offset(ID), None);
+
+        // the rule is matched again once the path the file was assigned by is deleted
+        multiline.unassign(ID);
+        let complete = push_all(&mut multiline, "/var/log/app.log", &["a", "  b"]);
+        assert!(complete.is_empty());
+        assert_eq!(multiline.pending_offset(ID), Some(0));
+    }
+
+    #[test]
+    fn flush_timeout() {
+        let mut multiline = multiline(Matcher::Indent);
+        multiline.rules[0].flush_timeout = Duration::from_millis(0);
+        push_all(&mut multiline, "/var/log/app.log", &["a", "  b"]);
+
+        let aggregates = multiline.flush_expired();
+        assert_eq!(aggregates.len(), 1);
+        assert_eq!(aggregates[0].line, "a\n  b");
+        assert!(multiline.flush_expired().is_empty());
+    }
+}
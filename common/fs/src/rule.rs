@@ -2,21 +2,33 @@ use std::fmt::Debug;
 use std::path::PathBuf;
 use std::str::FromStr;
 
-use globber::{Error as PatternError, Pattern};
+use crate::glob::{Error as PatternError, Pattern};
 use pcre2::{bytes::Regex, Error as RegexError};
 use std::os::unix::ffi::OsStrExt;
 
 /// A list of rules
-pub type RuleList = Vec<Box<dyn Rule + Send>>;
+pub type RuleList = Vec<Box<dyn Rule + Send + Sync>>;
 
 /// A trait for implementing a rule, see GlobRule/RegexRule for an example
 pub trait Rule: Debug {
     /// Takes a value and returns true or false based on if it matches
     fn matches(&self, value: &PathBuf) -> bool;
+    /// Returns true if the rule matches every path below a directory, regardless of it's name
+    fn matches_subtree(&self, _dir: &PathBuf) -> bool {
+        false
+    }
+    /// Describes the rule to a human, e.g. by it's pattern
+    fn describe(&self) -> String {
+        format!("{:?}", self)
+    }
+    /// Returns the named groups the rule captures from a value it matches
+    fn captures(&self, _value: &PathBuf) -> Vec<(String, String)> {
+        Vec::new()
+    }
 }
 
 /// Used for representing matches on Rules
-#[derive(PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Status {
     /// Failed due to not being included
     NotIncluded,
@@ -33,11 +45,68 @@ impl Status {
     }
 }
 
+/// A rule of an ordered rule list, which excludes the values it matches if it's negated
+#[derive(Debug)]
+pub struct OrderedRule {
+    negated: bool,
+    rule: Box<dyn Rule + Send + Sync>,
+}
+
+impl OrderedRule {
+    /// Creates a new OrderedRule from a rule
+    pub fn new<T: Rule + Send + Sync + 'static>(rule: T, negated: bool) -> Self {
+        Self {
+            negated,
+            rule: Box::new(rule),
+        }
+    }
+    /// Returns true if the rule excludes the values it matches
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+    /// Describes the rule to a human, negated rules start with a `!`
+    pub fn describe(&self) -> String {
+        if self.negated {
+            format!("!{}", self.rule.describe())
+        } else {
+            self.rule.describe()
+        }
+    }
+}
+
+impl FromStr for OrderedRule {
+    type Err = PatternError;
+
+    /// Parses a glob rule, a leading `!` negates it unless it starts an extended glob like
+    /// `!(*.*)`, so `!!(*.*)` is the negation of that extended glob
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix('!') {
+            Some(glob) if !glob.starts_with('(') => {
+                Ok(OrderedRule::new(GlobRule::new(glob)?, true))
+            }
+            _ => Ok(OrderedRule::new(GlobRule::new(s)?, false)),
+        }
+    }
+}
+
+/// The rules matching a value and the status they result in, see Rules::explain
+#[derive(Debug)]
+pub struct Explanation<'a> {
+    /// The inclusion rules matching the value
+    pub inclusions: Vec<&'a dyn Rule>,
+    /// The exclusion rules matching the value
+    pub exclusions: Vec<&'a dyn Rule>,
+    /// The ordered rule deciding the status, it takes precedence over the other rules
+    pub ordered: Option<&'a OrderedRule>,
+    pub status: Status,
+}
+
 /// Holds both exclusion and inclusion rules
 #[derive(Default, Debug)]
 pub struct Rules {
     inclusion: RuleList,
     exclusion: RuleList,
+    ordered: Vec<OrderedRule>,
 }
 
 impl Rules {
@@ -46,9 +115,11 @@ impl Rules {
         Self {
             inclusion: Vec::new(),
             exclusion: Vec::new(),
+            ordered: Vec::new(),
         }
     }
-    /// Check if value is included (matches at least one inclusion rule)
+    /// Check if value is included (matches at least one inclusion rule or ordered rule that
+    /// isn't negated)
     pub fn included<'a, T: Into<&'a PathBuf>>(&self, value: T) -> Status {
         let value = value.into();
         for rule in &self.inclusion {
@@ -56,6 +127,11 @@ impl Rules {
                 return Status::Ok;
             }
         }
+        for rule in &self.ordered {
+            if !rule.negated && rule.rule.matches(value) {
+                return Status::Ok;
+            }
+        }
         Status::NotIncluded
     }
     /// Check if value is excluded (matches none of the exclusion rules)
@@ -68,29 +144,103 @@ impl Rules {
         }
         Status::Ok
     }
-    /// Returns true if the value is included but not excluded
+    /// Check the value against the ordered rules, the last one matching it decides whether
+    /// it passes, None is returned if none of them matches
+    pub fn ordered<'a, T: Into<&'a PathBuf>>(&self, value: T) -> Option<Status> {
+        let value = value.into();
+        self.ordered
+            .iter()
+            .rev()
+            .find(|rule| rule.rule.matches(value))
+            .map(|rule| {
+                if rule.negated {
+                    Status::Excluded
+                } else {
+                    Status::Ok
+                }
+            })
+    }
+    /// Returns true if the value is included but not excluded, a matching ordered rule takes
+    /// precedence over the inclusion and exclusion rules
     pub fn passes<'a, T: Into<&'a PathBuf>>(&self, value: T) -> Status {
         let value = value.into();
 
+        if let Some(status) = self.ordered(value) {
+            return status;
+        }
+
         if self.included(value) == Status::NotIncluded {
             return Status::NotIncluded;
         }
 
         self.excluded(value)
     }
+    /// Returns all rules matching the value and the status they result in
+    pub fn explain(&self, value: &PathBuf) -> Explanation<'_> {
+        Explanation {
+            inclusions: matching(&self.inclusion, value),
+            exclusions: matching(&self.exclusion, value),
+            ordered: self
+                .ordered
+                .iter()
+                .rev()
+                .find(|rule| rule.rule.matches(value)),
+            status: self.passes(value),
+        }
+    }
+    /// Returns the named groups the inclusion rules and ordered rules that aren't negated
+    /// capture from a value, the first rule capturing a name takes precedence
+    pub fn captures(&self, value: &PathBuf) -> Vec<(String, String)> {
+        let ordered = self
+            .ordered
+            .iter()
+            .filter(|rule| !rule.negated)
+            .map(|rule| &rule.rule);
+        let mut captures: Vec<(String, String)> = Vec::new();
+        for rule in self.inclusion.iter().chain(ordered) {
+            for (name, capture) in rule.captures(value) {
+                if !captures.iter().any(|(existing, _)| *existing == name) {
+                    captures.push((name, capture));
+                }
+            }
+        }
+        captures
+    }
+    /// Returns true if an exclusion rule matches everything below a directory, so nothing in
+    /// it can ever pass
+    pub fn excludes_subtree(&self, dir: &PathBuf) -> bool {
+        // any path below the dir may be included again by an ordered rule that isn't negated
+        match self.ordered.iter().rposition(|rule| !rule.negated) {
+            None => {
+                self.exclusion.iter().any(|rule| rule.matches_subtree(dir))
+                    || self
+                        .ordered
+                        .iter()
+                        .any(|rule| rule.rule.matches_subtree(dir))
+            }
+            Some(last) => self.ordered[last + 1..]
+                .iter()
+                .any(|rule| rule.rule.matches_subtree(dir)),
+        }
+    }
     /// Adds an inclusion rule
-    pub fn add_inclusion<T: Rule + Send + 'static>(&mut self, rule: T) {
+    pub fn add_inclusion<T: Rule + Send + Sync + 'static>(&mut self, rule: T) {
         self.inclusion.push(Box::new(rule))
     }
     /// Adds an exclusion rule
-    pub fn add_exclusion<T: Rule + Send + 'static>(&mut self, rule: T) {
+    pub fn add_exclusion<T: Rule + Send + Sync + 'static>(&mut self, rule: T) {
         self.exclusion.push(Box::new(rule))
     }
+    /// Adds an ordered rule, it takes precedence over the ordered rules added before it
+    pub fn add_ordered(&mut self, rule: OrderedRule) {
+        self.ordered.push(rule)
+    }
     /// Appends all rules from another instance of rules
     pub fn add_all<T: Into<Rules>>(&mut self, rules: T) {
         let mut rules = rules.into();
         self.exclusion.append(&mut rules.exclusion);
         self.inclusion.append(&mut rules.inclusion);
+        self.ordered.append(&mut rules.ordered);
     }
     /// Getter for inclusion list
     pub fn inclusion_list(&self) -> &RuleList {
@@ -100,6 +250,19 @@ impl Rules {
     pub fn exclusion_list(&self) -> &RuleList {
         &self.exclusion
     }
+    /// Getter for ordered list
+    pub fn ordered_list(&self) -> &[OrderedRule] {
+        &self.ordered
+    }
+}
+
+// returns the rules of a list matching a value
+fn matching<'a>(rules: &'a RuleList, value: &PathBuf) -> Vec<&'a dyn Rule> {
+    rules
+        .iter()
+        .filter(|rule| rule.matches(value))
+        .map(|rule| rule.as_ref() as &dyn Rule)
+        .collect()
 }
 
 /// A rule the matches it's input based on a Regex
@@ -123,6 +286,29 @@ impl Rule for RegexRule {
             .is_match(value.as_os_str().as_bytes())
             .unwrap_or(false)
     }
+
+    fn describe(&self) -> String {
+        format!("regex {}", self.inner.as_str())
+    }
+
+    fn captures(&self, value: &PathBuf) -> Vec<(String, String)> {
+        let captures = match self.inner.captures(value.as_os_str().as_bytes()) {
+            Ok(Some(v)) => v,
+            _ => return Vec::new(),
+        };
+        self.inner
+            .capture_names()
+            .iter()
+            .flatten()
+            .filter_map(|name| {
+                let capture = captures.name(name)?;
+                Some((
+                    name.clone(),
+                    String::from_utf8_lossy(capture.as_bytes()).into_owned(),
+                ))
+            })
+            .collect()
+    }
 }
 
 impl FromStr for RegexRule {
@@ -133,27 +319,113 @@ impl FromStr for RegexRule {
     }
 }
 
-/// A rule the matches it's input based on a Glob pattern, note extended glob is not supported
+// the endings of glob patterns that match anything below the path before them
+const SUBTREE_SUFFIXES: &[&str] = &["/**/*", "/**", "/*"];
+
+/// A rule the matches it's input based on a Glob pattern, extended globs like `@(a|b)` and
+/// braces like `{a,b}` are supported
 #[derive(Debug)]
 pub struct GlobRule {
-    inner: Pattern,
+    pattern: String,
+    // a pattern per alternative of the braces
+    inner: Vec<Pattern>,
+    // the patterns without their subtree suffix, e.g. /var/log/pods for /var/log/pods/**
+    subtrees: Vec<Pattern>,
 }
 
 impl GlobRule {
     /// Creates a new GlobRule from a pattern
     pub fn new<'a, T: Into<&'a str>>(pattern: T) -> Result<Self, PatternError> {
+        let pattern = pattern.into();
+        let patterns = expand_braces(pattern);
         Ok(Self {
-            inner: Pattern::new(pattern.into())?,
+            pattern: pattern.to_string(),
+            inner: patterns
+                .iter()
+                .map(|pattern| Pattern::new(pattern))
+                .collect::<Result<_, _>>()?,
+            subtrees: patterns
+                .iter()
+                .filter_map(|pattern| subtree_prefix(pattern))
+                .filter_map(|prefix| Pattern::new(prefix).ok())
+                .collect(),
         })
     }
 }
 
 impl Rule for GlobRule {
     fn matches(&self, value: &PathBuf) -> bool {
-        self.inner.matches(&value.to_string_lossy())
+        let value = value.to_string_lossy();
+        self.inner.iter().any(|pattern| pattern.matches(&value))
+    }
+
+    fn describe(&self) -> String {
+        format!("glob {}", self.pattern)
+    }
+
+    fn matches_subtree(&self, dir: &PathBuf) -> bool {
+        dir.ancestors().any(|ancestor| {
+            let ancestor = ancestor.to_string_lossy();
+            self.subtrees.iter().any(|prefix| prefix.matches(&ancestor))
+        })
     }
 }
 
+// expands the braces of a pattern into a pattern per alternative, e.g. /{a,b}/*.log into
+// /a/*.log and /b/*.log, braces without a comma are kept as they are like in a shell
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let bytes = pattern.as_bytes();
+    let mut open = 0;
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            // an escaped brace or comma is matched literally
+            b'\\' => i += 1,
+            b'{' => {
+                if depth == 0 {
+                    open = i;
+                    commas.clear();
+                }
+                depth += 1;
+            }
+            b',' if depth == 1 => commas.push(i),
+            b'}' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 && !commas.is_empty() {
+                    let mut bounds = vec![open];
+                    bounds.extend(&commas);
+                    bounds.push(i);
+                    return bounds
+                        .windows(2)
+                        .flat_map(|bounds| {
+                            expand_braces(&format!(
+                                "{}{}{}",
+                                &pattern[..open],
+                                &pattern[bounds[0] + 1..bounds[1]],
+                                &pattern[i + 1..]
+                            ))
+                        })
+                        .collect();
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    vec![pattern.to_string()]
+}
+
+// returns the part of a pattern before a suffix matching any path below it, a directory
+// matching it means every path below the directory matches the whole pattern
+fn subtree_prefix(pattern: &str) -> Option<&str> {
+    SUBTREE_SUFFIXES
+        .iter()
+        .find_map(|suffix| pattern.strip_suffix(suffix))
+        .filter(|prefix| !prefix.is_empty())
+}
+
 impl FromStr for GlobRule {
     type Err = PatternError;
 
@@ -161,3 +433,151 @@ impl FromStr for GlobRule {
         GlobRule::new(s)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn passes(rules: &Rules, path: &str) -> bool {
+        rules.passes(&PathBuf::from(path)).is_ok()
+    }
+
+    #[test]
+    fn ordered_rules() {
+        let mut rules = Rules::new();
+        rules.add_inclusion(GlobRule::new("*.log").unwrap());
+        rules.add_exclusion(GlobRule::new("/var/log/app/*.debug.log").unwrap());
+        for rule in &[
+            "/var/log/app/**",
+            "!*.debug.log",
+            "*/critical.debug.log",
+            "!!(*.*)",
+        ] {
+            rules.add_ordered(rule.parse().unwrap());
+        }
+
+        assert!(passes(&rules, "/var/log/app/app.log"));
+        assert!(passes(&rules, "/var/log/app/app.txt"));
+        assert!(!passes(&rules, "/var/log/app/app.debug.log"));
+        assert!(passes(&rules, "/var/log/app/critical.debug.log"));
+        assert!(!passes(&rules, "/var/log/app/nodot"));
+        // values no ordered rule matches fall back to the inclusion and exclusion rules
+        assert!(passes(&rules, "/var/log/syslog.log"));
+        assert!(!passes(&rules, "/var/log/syslog"));
+    }
+
+    #[test]
+    fn explain_rules() {
+        let mut rules = Rules::new();
+        rules.add_inclusion(GlobRule::new("*.log").unwrap());
+        rules.add_inclusion(RegexRule::new(r"^/var/log/").unwrap());
+        rules.add_exclusion(GlobRule::new("*.debug.log").unwrap());
+        rules.add_ordered("!/var/log/app/**".parse().unwrap());
+
+        let explanation = rules.explain(&PathBuf::from("/var/log/web/a.debug.log"));
+        assert_eq!(
+            explanation
+                .inclusions
+                .iter()
+                .map(|rule| rule.describe())
+                .collect::<Vec<_>>(),
+            vec!["glob *.log", "regex ^/var/log/"]
+        );
+        assert_eq!(explanation.exclusions[0].describe(), "glob *.debug.log");
+        assert!(explanation.ordered.is_none());
+        assert_eq!(explanation.status, Status::Excluded);
+
+        let explanation = rules.explain(&PathBuf::from("/var/log/app/a.log"));
+        assert_eq!(
+            explanation.ordered.map(OrderedRule::describe),
+            Some("!glob /var/log/app/**".to_string())
+        );
+        assert_eq!(explanation.status, Status::Excluded);
+    }
+
+    #[test]
+    fn regex_captures() {
+        let mut rules = Rules::new();
+        rules.add_inclusion(GlobRule::new("*.txt").unwrap());
+        rules
+            .add_inclusion(RegexRule::new(r"^/var/log/(?P<app>[^/]+)/(?P<file>.+)\.log$").unwrap());
+        rules.add_inclusion(RegexRule::new(r"^/var/log/(?P<app>[^/]+)/").unwrap());
+
+        assert_eq!(
+            rules.captures(&PathBuf::from("/var/log/nginx/access.log")),
+            vec![
+                ("app".to_string(), "nginx".to_string()),
+                ("file".to_string(), "access".to_string()),
+            ]
+        );
+        assert_eq!(
+            rules.captures(&PathBuf::from("/var/log/nginx/error.txt")),
+            vec![("app".to_string(), "nginx".to_string())]
+        );
+        assert!(rules.captures(&PathBuf::from("/tmp/a.txt")).is_empty());
+    }
+
+    #[test]
+    fn glob_braces_and_extglob() {
+        let rule = GlobRule::new("/var/log/{app,web/{a,b}}/*.{log,txt}").unwrap();
+        for path in &[
+            "/var/log/app/x.log",
+            "/var/log/web/a/x.txt",
+            "/var/log/web/b/x.log",
+        ] {
+            assert!(rule.matches(&PathBuf::from(path)), "{}", path);
+        }
+        assert!(!rule.matches(&PathBuf::from("/var/log/web/c/x.log")));
+        assert!(!rule.matches(&PathBuf::from("/var/log/app/x.gz")));
+
+        // braces without a comma or escaped ones are matched literally
+        let rule = GlobRule::new(r"/var/log/{app}/\{a,b\}.log").unwrap();
+        assert!(rule.matches(&PathBuf::from("/var/log/{app}/{a,b}.log")));
+
+        let rule = GlobRule::new("/var/log/@(app|web)/+(a|b).log").unwrap();
+        assert!(rule.matches(&PathBuf::from("/var/log/web/abba.log")));
+        assert!(!rule.matches(&PathBuf::from("/var/log/db/a.log")));
+        assert!(!rule.matches(&PathBuf::from("/var/log/app/c.log")));
+    }
+
+    #[test]
+    fn ordered_subtree_exclusions() {
+        let mut rules = Rules::new();
+        rules.add_exclusion(GlobRule::new("/var/log/pods/**").unwrap());
+        rules.add_ordered("!/var/log/journal/**".parse().unwrap());
+        assert!(rules.excludes_subtree(&PathBuf::from("/var/log/pods")));
+        assert!(rules.excludes_subtree(&PathBuf::from("/var/log/journal")));
+
+        // a later rule may include paths below the dirs again
+        rules.add_ordered("/var/log/journal/*/keep.log".parse().unwrap());
+        assert!(!rules.excludes_subtree(&PathBuf::from("/var/log/pods")));
+        assert!(!rules.excludes_subtree(&PathBuf::from("/var/log/journal")));
+
+        rules.add_ordered("!/var/log/journal/**".parse().unwrap());
+        assert!(rules.excludes_subtree(&PathBuf::from("/var/log/journal")));
+    }
+
+    #[test]
+    fn glob_subtree_exclusions() {
+        let mut rules = Rules::new();
+        rules.add_exclusion(GlobRule::new("/var/log/pods/**/*").unwrap());
+        rules.add_exclusion(GlobRule::new("/var/log/journal/**").unwrap());
+        rules.add_exclusion(GlobRule::new("/var/log/*/cache/*").unwrap());
+        rules.add_exclusion(GlobRule::new("/var/log/sar*").unwrap());
+        rules.add_exclusion(GlobRule::new("/var/log/@(web|db)/**").unwrap());
+        rules.add_exclusion(RegexRule::new(r"^/var/log/tmp/.*").unwrap());
+
+        for dir in &[
+            "/var/log/pods",
+            "/var/log/pods/ns_pod/container",
+            "/var/log/journal",
+            "/var/log/nginx/cache",
+            "/var/log/db",
+        ] {
+            assert!(rules.excludes_subtree(&PathBuf::from(dir)), "{}", dir);
+        }
+        for dir in &["/var/log", "/var/log/nginx", "/var/log/sar", "/var/log/tmp"] {
+            assert!(!rules.excludes_subtree(&PathBuf::from(dir)), "{}", dir);
+        }
+    }
+}
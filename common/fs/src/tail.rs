@@ -1,70 +1,293 @@
 use crate::cache::entry::Entry;
 use crate::cache::event::Event;
 pub use crate::cache::DirPathBuf;
-use crate::cache::FileSystem;
+use crate::cache::{FileSystem, Options};
+use crate::compression::{Compression, Decoder};
+use crate::encoding::{EncodingRule, Encodings};
+use crate::identity::{FileId, Fingerprint, FINGERPRINT_LEN};
+pub use crate::lookback::{Lookback, ParseLookbackError, DEFAULT_SMALL_FILES_BYTES};
+use crate::multiline::{Multiline, MultilineRule};
 use crate::rule::Rules;
+use http::source::set_source;
 use http::types::body::LineBuilder;
 use metrics::Metrics;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::io::{self, BufReader, Seek, SeekFrom};
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use futures::channel::mpsc::UnboundedSender;
 use futures::{Stream, StreamExt};
+use hashbrown::{HashMap, HashSet};
 
-use thiserror::Error;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, std::fmt::Debug)]
-pub enum Lookback {
-    Start,
-    SmallFiles,
-    None,
+// how often held back lines are checked for their flush timeout
+const FLUSH_INTERVAL_MS: u64 = 100;
+
+/// The default maximum number of bytes read from a file for one event
+pub const DEFAULT_READ_BUDGET_BYTES: u64 = 1024 * 1024;
+
+/// Limits how much of a file is read for one event, the rest of the file is read once the
+/// other files had their turn so a large backlog doesn't hold up everything else
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReadBudget {
+    /// The maximum number of bytes read per event, unlimited if None
+    pub bytes: Option<u64>,
+    /// The maximum number of lines read per event, unlimited if None
+    pub lines: Option<u64>,
+}
+
+impl ReadBudget {
+    /// A budget that reads files to their end
+    pub const UNLIMITED: ReadBudget = ReadBudget {
+        bytes: None,
+        lines: None,
+    };
+
+    // takes a line of len bytes out of the budget
+    fn spend(&mut self, len: u64) {
+        self.bytes = self.bytes.map(|bytes| bytes.saturating_sub(len));
+        self.lines = self.lines.map(|lines| lines.saturating_sub(1));
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.bytes == Some(0) || self.lines == Some(0)
+    }
 }
 
-#[derive(Error, Debug)]
-pub enum ParseLookbackError {
-    #[error("Unknown lookback strategy: {0}")]
-    Unknown(String),
+impl Default for ReadBudget {
+    fn default() -> Self {
+        Self {
+            bytes: Some(DEFAULT_READ_BUDGET_BYTES),
+            lines: None,
+        }
+    }
 }
 
-impl std::str::FromStr for Lookback {
-    type Err = ParseLookbackError;
+/// The progress of the tailer through a file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FileState {
+    /// The offset up to which lines have been sent
+    pub offset: u64,
+    /// The fingerprint of the file's content at the time the offset was recorded
+    pub fingerprint: Fingerprint,
+    /// Whether a compressed file was read to it's end, compressed files are only read once
+    #[serde(default)]
+    pub completed: bool,
+    /// Whether the line ending at the offset was sent without it's new line once the partial
+    /// line timeout passed, so the new line completing it isn't sent as another line
+    #[serde(default)]
+    pub skip_new_line: bool,
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s
-            .to_lowercase()
-            .split_whitespace()
-            .collect::<String>()
-            .as_str()
-        {
-            "start" => Ok(Lookback::Start),
-            "smallfiles" => Ok(Lookback::SmallFiles),
-            "none" => Ok(Lookback::None),
-            _ => Err(ParseLookbackError::Unknown(s.into())),
+impl FileState {
+    fn new(file_handle: &File, offset: u64) -> Self {
+        let fingerprint = Fingerprint::of(file_handle).unwrap_or_default();
+        // the offset of a compressed file counts decompressed bytes so a lookback that skips
+        // existing content skips the whole file
+        if offset > 0 && Compression::detect(file_handle).ok().flatten().is_some() {
+            return Self {
+                offset: 0,
+                fingerprint,
+                completed: true,
+                skip_new_line: false,
+            };
+        }
+        Self {
+            offset,
+            fingerprint,
+            completed: false,
+            skip_new_line: false,
         }
     }
 }
 
-impl Default for Lookback {
-    fn default() -> Self {
-        Lookback::SmallFiles
+// A line at the end of a file that isn't terminated by a new line yet
+#[derive(Clone, Debug)]
+struct PartialLine {
+    paths: Vec<PathBuf>,
+    line: String,
+    start: u64,
+    end: u64,
+    updated: Instant,
+    flushed: bool,
+}
+
+// Tracks the partial lines of files so they can be sent once their files stop growing
+#[derive(Debug, Default)]
+struct PartialLines {
+    timeout: Option<Duration>,
+    lines: HashMap<FileId, PartialLine>,
+}
+
+impl PartialLines {
+    fn take(&mut self, id: FileId) -> Option<PartialLine> {
+        self.lines.remove(&id)
+    }
+
+    // records the partial line read from a file, the idle timer only restarts if it changed
+    fn insert(
+        &mut self,
+        id: FileId,
+        previous: Option<PartialLine>,
+        paths: &[PathBuf],
+        start: u64,
+        end: u64,
+        line: String,
+    ) {
+        if self.timeout.is_none() {
+            return;
+        }
+
+        let partial = match previous {
+            Some(partial) if partial.start == start && partial.end == end => partial,
+            _ => PartialLine {
+                paths: paths.to_vec(),
+                line,
+                start,
+                end,
+                updated: Instant::now(),
+                flushed: false,
+            },
+        };
+        self.lines.insert(id, partial);
+    }
+
+    // whether the partial line of a file ending at end was already sent
+    fn is_flushed(&self, id: FileId, end: u64) -> bool {
+        self.lines
+            .get(&id)
+            .is_some_and(|partial| partial.flushed && partial.end == end)
+    }
+
+    // marks the partial lines that haven't grown within the timeout as flushed and returns them
+    fn flush_expired(&mut self) -> Vec<(FileId, PartialLine)> {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return Vec::new(),
+        };
+
+        self.lines
+            .iter_mut()
+            .filter(|(_, partial)| !partial.flushed && partial.updated.elapsed() >= timeout)
+            .map(|(id, partial)| {
+                partial.flushed = true;
+                (*id, partial.clone())
+            })
+            .collect()
+    }
+}
+
+// What is kept in memory about reading files besides their offsets
+struct FileReaders {
+    encodings: Encodings,
+    partials: PartialLines,
+    // the compressed files that weren't read to their end yet
+    decoders: HashMap<FileId, Decoder>,
+}
+
+impl FileReaders {
+    // forgets about reading a file, e.g. once it's truncated
+    fn remove(&mut self, id: FileId) {
+        self.encodings.remove(id);
+        self.partials.take(id);
+        self.decoders.remove(&id);
+    }
+}
+
+// Files that ran out of read budget before their end, they are read again after the events
+// that were already waiting
+struct DeferredReads {
+    sender: UnboundedSender<(FileId, PathBuf)>,
+    queued: HashSet<FileId>,
+}
+
+impl DeferredReads {
+    fn push(&mut self, id: FileId, path: PathBuf) {
+        // a file is only queued once, it's next read continues from wherever the last one stopped
+        if self.queued.insert(id) {
+            Metrics::fs().increment_deferred_reads();
+            let _ = self.sender.unbounded_send((id, path));
+        }
+    }
+
+    fn take(&mut self, id: FileId) {
+        self.queued.remove(&id);
     }
 }
 
+// What the tailer reacts to
+enum Input {
+    Event(Event<FileState>),
+    // a file whose read was cut short by the read budget
+    Deferred(FileId, PathBuf),
+    // a heartbeat that flushes held back lines once they time out
+    Tick,
+}
+
+/// Durable store of the last read offset of every tailed file
+pub type OffsetStore = state::Store<FileId, FileState>;
+
 /// Tails files on a filesystem by inheriting events from a Watcher
 pub struct Tailer {
     lookback_config: Lookback,
-    fs_cache: Arc<Mutex<FileSystem<u64>>>,
+    fs_cache: Arc<Mutex<FileSystem<FileState>>>,
+    offsets: Option<Arc<Mutex<OffsetStore>>>,
+    multiline: Multiline,
+    encodings: Encodings,
+    partial_line_timeout: Option<Duration>,
+    read_budget: ReadBudget,
 }
 
 impl Tailer {
     /// Creates new instance of Tailer
     pub fn new(watched_dirs: Vec<DirPathBuf>, rules: Rules, lookback_config: Lookback) -> Self {
+        Self::with_options(watched_dirs, rules, lookback_config, Options::default())
+    }
+    /// Creates new instance of Tailer with the options of the filesystem cache
+    pub fn with_options(
+        watched_dirs: Vec<DirPathBuf>,
+        rules: Rules,
+        lookback_config: Lookback,
+        options: Options,
+    ) -> Self {
         Self {
             lookback_config,
-            fs_cache: Arc::new(Mutex::new(FileSystem::new(watched_dirs, rules))),
+            fs_cache: Arc::new(Mutex::new(FileSystem::with_options(
+                watched_dirs,
+                rules,
+                options,
+            ))),
+            offsets: None,
+            multiline: Multiline::default(),
+            encodings: Encodings::default(),
+            partial_line_timeout: None,
+            read_budget: ReadBudget::default(),
         }
     }
+    /// Persists file offsets in the store and resumes initialized files from it
+    pub fn set_offset_store(&mut self, offsets: Arc<Mutex<OffsetStore>>) {
+        self.offsets = Some(offsets);
+    }
+    /// Merges consecutive lines of the files matching the rules into single lines
+    pub fn set_multiline_rules(&mut self, rules: Vec<MultilineRule>) {
+        self.multiline = Multiline::new(rules);
+    }
+    /// Decodes the files matching the rules with an encoding other than UTF-8, unless they
+    /// start with a byte order mark
+    pub fn set_encoding_rules(&mut self, rules: Vec<EncodingRule>) {
+        self.encodings = Encodings::new(rules);
+    }
+    /// Sends lines without a trailing new line once their file didn't change for the timeout
+    pub fn set_partial_line_timeout(&mut self, timeout: Duration) {
+        self.partial_line_timeout = Some(timeout);
+    }
+    /// Limits how much of a file is read for one event
+    pub fn set_read_budget(&mut self, budget: ReadBudget) {
+        self.read_budget = budget;
+    }
     /// Runs the main logic of the tailer, this can only be run once so Tailer is consumed
     pub fn process<'a>(
         &mut self,
@@ -80,12 +303,95 @@ impl Tailer {
             }
         };
 
-        Ok(events.map({
+        // interleave the events with a heartbeat that flushes held back lines once they time out,
+        // there's nothing to flush without multiline rules or a partial line timeout
+        let ticks = if self.multiline.is_enabled() || self.partial_line_timeout.is_some() {
+            Some(
+                tokio::time::interval(tokio::time::Duration::from_millis(FLUSH_INTERVAL_MS))
+                    .map(|_| Input::Tick),
+            )
+        } else {
+            None
+        };
+        let ticks = futures::stream::iter(ticks).flatten();
+        // deferred reads take turns with new events so every file makes progress
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        let deferred_reads = receiver.map(|(id, path)| Input::Deferred(id, path));
+
+        Ok(futures::stream::select(
+            events.map(Input::Event),
+            futures::stream::select(deferred_reads, ticks),
+        )
+        .map({
             let fs = self.fs_cache.clone();
             let lookback_config = self.lookback_config.clone();
-            move |event| {
+            let offsets = self.offsets.clone();
+            let read_budget = self.read_budget;
+            let mut multiline = std::mem::take(&mut self.multiline);
+            let mut readers = FileReaders {
+                encodings: std::mem::take(&mut self.encodings),
+                partials: PartialLines {
+                    timeout: self.partial_line_timeout,
+                    ..Default::default()
+                },
+                decoders: HashMap::new(),
+            };
+            let mut deferred = DeferredReads {
+                sender,
+                queued: HashSet::new(),
+            };
+            move |input| {
                 let mut final_lines = Vec::new();
 
+                let event = match input {
+                    Input::Event(event) => event,
+                    Input::Deferred(id, path) => {
+                        deferred.take(id);
+                        let mut fs = fs.lock().expect("Couldn't lock fs");
+                        // the file may have been moved or deleted, which reads it anyway
+                        let entry_ptr = match fs.lookup(&path) {
+                            Some(entry_ptr) if unsafe { entry_ptr.as_ref() }.file_id() == Some(id) => entry_ptr,
+                            _ => return futures::stream::iter(final_lines),
+                        };
+                        fs.open_file(entry_ptr);
+                        let entry = unsafe { &mut *entry_ptr.as_ptr() };
+                        let paths = fs.resolve_valid_paths(entry);
+                        if !paths.is_empty() {
+                            if let Entry::File {
+                                ref mut data,
+                                file_handle: Some(file_handle),
+                                ..
+                            } = entry
+                            {
+                                let mut budget = read_budget;
+                                if let Some(mut lines) = Tailer::tail(file_handle, &paths, data, &mut multiline, &mut readers, &mut budget) {
+                                    final_lines.append(&mut lines);
+                                }
+                                Tailer::save_offset(&offsets, file_handle, data, &multiline);
+                                if budget.is_exhausted() {
+                                    deferred.push(id, path);
+                                }
+                            }
+                        }
+                        return futures::stream::iter(final_lines);
+                    }
+                    Input::Tick => {
+                        for (id, partial) in readers.partials.flush_expired() {
+                            for line in multiline.push(id, &partial.paths, partial.start, partial.end, partial.line) {
+                                final_lines.push(Tailer::line_group(&partial.paths, line));
+                            }
+                            let offset = multiline.pending_offset(id).unwrap_or(partial.end);
+                            Tailer::commit_offset(&offsets, id, offset, readers.partials.is_flushed(id, offset));
+                        }
+                        for aggregate in multiline.flush_expired() {
+                            final_lines.push(Tailer::line_group(&aggregate.paths, aggregate.line));
+                            let skip_new_line = readers.partials.is_flushed(aggregate.id, aggregate.end);
+                            Tailer::commit_offset(&offsets, aggregate.id, aggregate.end, skip_new_line);
+                        }
+                        return futures::stream::iter(final_lines);
+                    }
+                };
+
                 let mut fs = fs.lock().expect("Couldn't lock fs");
                 match event {
                     Event::Initialize(mut entry_ptr) => {
@@ -94,69 +400,86 @@ impl Tailer {
                         let path = fs.resolve_direct_path(entry);
                         debug!("Initialise Event");
 
-                        if let Entry::File { ref mut data, .. } = entry {
-                            *data = match lookback_config {
-                                Lookback::Start => {
-                                    info!("initialized {:?} with offset {}", path, 0);
-                                    0
-                                },
-                                Lookback::SmallFiles => {
-                                    let mut len = path.metadata().map(|m| m.len()).unwrap_or(0);
-                                    if len < 8192 {
-                                        info!("initialized {:?} with len {} offset {}", path, len, 0);
-                                        len = 0;
-                                    } else{
-                                        info!("initialized {:?} with offset {}", path, len);
-                                    }
-                                    len
-                                },
-                                Lookback::None => {
-                                    let len = path.metadata().map(|m| m.len()).unwrap_or(0);
-                                    info!("initialized {:?} with offset {}", path, len);
-                                    len
+                        if let Entry::File { ref mut data, file_handle: Some(file_handle), id, .. } = entry {
+                            *data = match Tailer::stored_state(&offsets, file_handle) {
+                                Some(state) => {
+                                    info!("initialized {:?} with stored offset {}", path, state.offset);
+                                    state
                                 }
-                            }
+                                None => {
+                                    let charset = readers.encodings.charset(*id, std::slice::from_ref(&path), file_handle);
+                                    let offset = lookback_config.offset(file_handle, charset).unwrap_or_else(|e| {
+                                        warn!("unable to apply lookback to {:?}: {}", path, e);
+                                        0
+                                    });
+                                    info!("initialized {:?} with offset {}", path, offset);
+                                    FileState::new(file_handle, offset)
+                                }
+                            };
+                            Tailer::save_offset(&offsets, file_handle, data, &multiline);
                         }
                     }
-                    Event::New(mut entry_ptr) => {
-                        Metrics::fs().increment_creates();
+                    Event::New(mut entry_ptr) | Event::Replace(mut entry_ptr) => {
+                        if let Event::Replace(_) = event {
+                            Metrics::fs().increment_replacements();
+                        } else {
+                            Metrics::fs().increment_creates();
+                        }
                         // similar to initiate but sets the offset to 0
                         let entry = unsafe { entry_ptr.as_mut() };
+                        let path = fs.resolve_direct_path(entry);
                         let paths = fs.resolve_valid_paths(entry);
                         debug!("New Event");
                         if !paths.is_empty() {
                             if let Entry::File {
                                 ref mut data,
-                                file_handle,
+                                file_handle: Some(file_handle),
+                                id,
                                 ..
                             } = entry
                             {
                                 info!("added {:?}", paths[0]);
-                                *data = 0;
-                                if let Some(mut lines) = Tailer::tail(file_handle, &paths, data) {
+                                *data = FileState::default();
+                                let mut budget = read_budget;
+                                if let Some(mut lines) = Tailer::tail(file_handle, &paths, data, &mut multiline, &mut readers, &mut budget) {
                                     final_lines.append(&mut lines);
                                 }
+                                Tailer::save_offset(&offsets, file_handle, data, &multiline);
+                                if budget.is_exhausted() {
+                                    deferred.push(*id, path);
+                                }
                             }
                         }
 
 
                     }
-                    Event::Write(mut entry_ptr) => {
-                        Metrics::fs().increment_writes();
+                    Event::Write(mut entry_ptr) | Event::Rotate(mut entry_ptr) => {
+                        if let Event::Rotate(_) = event {
+                            Metrics::fs().increment_rotations();
+                        } else {
+                            Metrics::fs().increment_writes();
+                        }
                         let entry = unsafe { entry_ptr.as_mut() };
+                        let path = fs.resolve_direct_path(entry);
                         let paths = fs.resolve_valid_paths(entry);
                         debug!("Write Event");
                         if !paths.is_empty() {
 
                             if let Entry::File {
                                 ref mut data,
-                                file_handle,
+                                file_handle: Some(file_handle),
+                                id,
                                 ..
                             } = entry
                             {
-                                if let Some(mut lines) = Tailer::tail(file_handle, &paths, data) {
+                                let mut budget = read_budget;
+                                if let Some(mut lines) = Tailer::tail(file_handle, &paths, data, &mut multiline, &mut readers, &mut budget) {
                                     final_lines.append(&mut lines);
                                 }
+                                Tailer::save_offset(&offsets, file_handle, data, &multiline);
+                                if budget.is_exhausted() {
+                                    deferred.push(*id, path);
+                                }
                             }
 
                         }
@@ -167,6 +490,7 @@ impl Tailer {
                         let paths = fs.resolve_valid_paths(entry);
                         debug!("Delete Event");
                         if !paths.is_empty() {
+                            let is_symlink = matches!(entry, Entry::Symlink { .. });
                             if let Entry::Symlink { link, .. } = entry {
                                 if let Some(real_entry) = fs.lookup(link) {
                                     entry = unsafe { &mut *real_entry.as_ptr() };
@@ -174,15 +498,37 @@ impl Tailer {
                                     error!("can't wrap up deleted symlink - pointed to file / directory doesn't exist: {:?}", paths[0]);
                                 }
                             }
+                            // the file may no longer be reachable through the path it's rule was
+                            // matched with
+                            if let Some(id) = entry.file_id() {
+                                multiline.unassign(id);
+                            }
 
                             if let Entry::File {
                                 ref mut data,
                                 file_handle,
+                                id,
                                 ..
                             } = entry
                             {
-                                if let Some(mut lines) = Tailer::tail(file_handle, &paths, data) {
-                                    final_lines.append(&mut lines);
+                                if let Some(file_handle) = file_handle {
+                                    // the handle is closed once the event is handled so the file is read to it's end
+                                    let mut budget = ReadBudget::UNLIMITED;
+                                    if let Some(mut lines) = Tailer::tail(file_handle, &paths, data, &mut multiline, &mut readers, &mut budget) {
+                                        final_lines.append(&mut lines);
+                                    }
+                                } else if !is_symlink {
+                                    // the deleted file can't be reopened through it's path anymore
+                                    warn!("{:?} was deleted while it's handle was closed, lines written after offset {} are skipped", paths[0], data.offset);
+                                }
+                                // the file itself is gone so it's offset is no longer needed,
+                                // symlinks only remove a path to a file that may still exist
+                                if !is_symlink {
+                                    if let Some(aggregate) = multiline.remove(*id) {
+                                        final_lines.push(Tailer::line_group(&paths, aggregate.line));
+                                    }
+                                    readers.remove(*id);
+                                    Tailer::forget_offset(&offsets, *id);
                                 }
                             }
 
@@ -193,81 +539,311 @@ impl Tailer {
             }}).flatten())
     }
 
+    // returns the stored state of a file if it's offset still lies within the same file content
+    fn stored_state(
+        offsets: &Option<Arc<Mutex<OffsetStore>>>,
+        file_handle: &File,
+    ) -> Option<FileState> {
+        let offsets = offsets.as_ref()?;
+        let metadata = file_handle.metadata().ok()?;
+        let state = *offsets
+            .lock()
+            .expect("Couldn't lock offsets")
+            .get(&FileId::from(&metadata))?;
+        // the offset of a compressed file counts decompressed bytes so it can exceed the file's len
+        let compressed = Compression::detect(file_handle).ok()?.is_some();
+        // the inode may have been reused by a different file since the offset was stored
+        if (compressed || state.offset <= metadata.len())
+            && state.fingerprint.matches(file_handle).ok()?
+        {
+            Some(state)
+        } else {
+            None
+        }
+    }
+
+    fn save_offset(
+        offsets: &Option<Arc<Mutex<OffsetStore>>>,
+        file_handle: &File,
+        state: &FileState,
+        multiline: &Multiline,
+    ) {
+        if let Some(offsets) = offsets {
+            if let Ok(metadata) = file_handle.metadata() {
+                let id = FileId::from(&metadata);
+                // lines held back for aggregation haven't been sent yet so they are read again
+                // after a restart
+                let offset = multiline.pending_offset(id).unwrap_or(state.offset);
+                let skip_new_line = state.skip_new_line && offset == state.offset;
+                offsets.lock().expect("Couldn't lock offsets").insert(
+                    id,
+                    FileState {
+                        offset,
+                        skip_new_line,
+                        ..*state
+                    },
+                );
+            }
+        }
+    }
+
+    // records that the lines of a file up to offset have been sent, the line ending at offset
+    // may have been sent without it's new line
+    fn commit_offset(
+        offsets: &Option<Arc<Mutex<OffsetStore>>>,
+        id: FileId,
+        offset: u64,
+        skip_new_line: bool,
+    ) {
+        if let Some(offsets) = offsets {
+            let mut offsets = offsets.lock().expect("Couldn't lock offsets");
+            if let Some(state) = offsets.get(&id).copied() {
+                offsets.insert(
+                    id,
+                    FileState {
+                        offset,
+                        skip_new_line,
+                        ..state
+                    },
+                );
+            }
+        }
+    }
+
+    fn forget_offset(offsets: &Option<Arc<Mutex<OffsetStore>>>, id: FileId) {
+        if let Some(offsets) = offsets {
+            offsets.lock().expect("Couldn't lock offsets").remove(&id);
+        }
+    }
+
     // tail a file for new line(s)
     fn tail(
         file_handle: &File,
         paths: &[PathBuf],
-        offset: &mut u64,
+        state: &mut FileState,
+        multiline: &mut Multiline,
+        readers: &mut FileReaders,
+        budget: &mut ReadBudget,
     ) -> Option<Vec<Vec<LineBuilder>>> {
         // get the file len
-        let len = match file_handle.metadata().map(|m| m.len()) {
-            Ok(v) => v,
+        let (len, id) = match file_handle.metadata() {
+            Ok(v) => (v.len(), FileId::from(&v)),
             Err(e) => {
                 error!("unable to stat {:?}: {:?}", &paths[0], e);
                 return None;
             }
         };
 
+        // compressed files are decompressed as they are written, up to the end of their content
+        match Compression::detect(file_handle) {
+            Ok(Some(_)) if state.completed => return None,
+            Ok(Some(compression)) => {
+                if !readers.decoders.contains_key(&id) {
+                    match Decoder::new(compression, state.offset) {
+                        Ok(decoder) => readers.decoders.insert(id, decoder),
+                        Err(e) => {
+                            error!("unable to decompress {:?}: {:?}", &paths[0], e);
+                            return None;
+                        }
+                    };
+                }
+                let decoder = readers.decoders.get_mut(&id)?;
+                let line_groups = Tailer::tail_compressed(
+                    file_handle,
+                    paths,
+                    id,
+                    decoder,
+                    state,
+                    multiline,
+                    budget,
+                );
+                if state.completed {
+                    readers.decoders.remove(&id);
+                }
+                return line_groups;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("unable to read {:?}: {:?}", &paths[0], e);
+                return None;
+            }
+        }
+
+        let mut line_groups = Vec::new();
+
+        // if the offset is greater than the file's len or the start of the file changed
+        // the file was truncated, e.g. by copytruncate, and possibly written to since
+        // so everything in the file is new
+        if state.offset > len || !state.fingerprint.matches(file_handle).unwrap_or(true) {
+            Metrics::fs().increment_truncations();
+            info!(
+                "{:?} was truncated from {} to {}",
+                &paths[0], state.offset, len
+            );
+            *state = FileState::default();
+            // the new content may start with a different byte order mark
+            readers.remove(id);
+            // lines aggregated before the truncation don't continue in the new content
+            if let Some(aggregate) = multiline.remove(id) {
+                line_groups.push(Tailer::line_group(paths, aggregate.line));
+            }
+        }
+
+        // keep fingerprinting until the fingerprint covers it's full length
+        if state.fingerprint.len < len.min(FINGERPRINT_LEN) {
+            if let Ok(fingerprint) = Fingerprint::of(file_handle) {
+                state.fingerprint = fingerprint;
+            }
+        }
+
+        let offset = &mut state.offset;
+        // a partial line that was already sent is skipped, including the new line that completes
+        // it, which is remembered with the offset as it may only be written after a restart
+        let skip_new_line = &mut state.skip_new_line;
+        let previous = readers.partials.take(id);
+        if let Some(partial) = previous
+            .as_ref()
+            .filter(|p| p.flushed && p.start == *offset)
+        {
+            *offset = partial.end;
+            *skip_new_line = true;
+        }
+        // the byte order mark isn't part of the first line
+        let charset = readers.encodings.charset(id, paths, file_handle);
+        if *offset < charset.bom_len {
+            *offset = charset.bom_len;
+        }
         // if we are at the end of the file there's no work to do
-        if *offset == len {
-            return None;
+        if *offset < len {
+            // open the file, create a reader
+            let mut reader = BufReader::new(file_handle);
+            // seek to the offset, this creates the "tailing" effect
+            if let Err(e) = reader.seek(SeekFrom::Start(*offset)) {
+                error!("error seeking {:?}", e);
+                return None;
+            }
+
+            loop {
+                let mut raw_line = Vec::new();
+                // read until a new line returning the line length
+                let line_len = match charset.read_line(&mut reader, &mut raw_line) {
+                    Ok(v) => v as u64,
+                    Err(e) => {
+                        error!("error reading from file {:?}: {:?}", &paths[0], e);
+                        break;
+                    }
+                };
+                // invalid sequences are replaced with the replacement character
+                let mut line = charset.decode(raw_line);
+                // if the line doesn't end with a new line we might have read in the middle of a write
+                // so we return in this case
+                if !line.ends_with('\n') {
+                    Metrics::fs().increment_partial_reads();
+                    if !line.is_empty() {
+                        readers.partials.insert(
+                            id,
+                            previous,
+                            paths,
+                            *offset,
+                            *offset + line_len,
+                            line,
+                        );
+                    }
+                    break;
+                }
+                // remove the trailing new line
+                line.pop();
+                if std::mem::replace(skip_new_line, false) && line.is_empty() {
+                    *offset += line_len;
+                    continue;
+                }
+                let start = *offset;
+                // increment the offset
+                *offset += line_len;
+                // lines may be held back until it's known whether the next line continues them
+                for line in multiline.push(id, paths, start, *offset, line) {
+                    line_groups.push(Tailer::line_group(paths, line));
+                }
+                // at least one line is read so every event makes progress
+                budget.spend(line_len);
+                if budget.is_exhausted() {
+                    break;
+                }
+            }
         }
-        // open the file, create a reader
-        let mut reader = BufReader::new(file_handle);
-        // if the offset is greater than the file's len
-        // it's very likely a truncation occurred
-        if *offset > len {
-            info!("{:?} was truncated from {} to {}", &paths[0], *offset, len);
-            *offset = if len < 8192 { 0 } else { len };
-            return None;
+
+        if line_groups.is_empty() {
+            None
+        } else {
+            Some(line_groups)
         }
-        // seek to the offset, this creates the "tailing" effect
-        if let Err(e) = reader.seek(SeekFrom::Start(*offset)) {
-            error!("error seeking {:?}", e);
-            return None;
+    }
+
+    // reads what was written to a compressed file since it was last read, the offset counts
+    // decompressed bytes and the file is read up to the end of it's compressed content
+    fn tail_compressed(
+        file_handle: &File,
+        paths: &[PathBuf],
+        id: FileId,
+        decoder: &mut Decoder,
+        state: &mut FileState,
+        multiline: &mut Multiline,
+        budget: &mut ReadBudget,
+    ) -> Option<Vec<Vec<LineBuilder>>> {
+        if state.fingerprint.len < FINGERPRINT_LEN {
+            if let Ok(fingerprint) = Fingerprint::of(file_handle) {
+                state.fingerprint = fingerprint;
+            }
         }
 
+        if let Err(e) = decoder.attach(file_handle) {
+            error!("unable to read {:?}: {:?}", &paths[0], e);
+            return None;
+        }
         let mut line_groups = Vec::new();
-
         loop {
-            let mut raw_line = Vec::new();
-            // read until a new line returning the line length
-            let line_len = match reader.read_until(b'\n', &mut raw_line) {
-                Ok(v) => v as u64,
+            let raw_line = match decoder.read_line() {
+                Ok(Some(raw_line)) => raw_line,
+                Ok(None) => {
+                    info!("finished reading compressed file {:?}", &paths[0]);
+                    Metrics::fs().increment_decompressed_files();
+                    state.completed = true;
+                    break;
+                }
+                // the rest of the file is read once it's written
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
                 Err(e) => {
-                    error!("error reading from file {:?}: {:?}", &paths[0], e);
+                    warn!(
+                        "unable to decompress {:?}, skipping the rest of it: {:?}",
+                        &paths[0], e
+                    );
+                    state.completed = true;
                     break;
                 }
             };
-            // try to parse the raw data as utf8
-            // if that fails replace invalid chars with blank chars
-            // see String::from_utf8_lossy docs
+            let line_len = raw_line.len() as u64;
             let mut line = String::from_utf8(raw_line)
                 .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).to_string());
-            // if the line doesn't end with a new line we might have read in the middle of a write
-            // so we return in this case
-            if !line.ends_with('\n') {
-                Metrics::fs().increment_partial_reads();
+            // the content is complete so a last line without a new line won't grow anymore
+            if line.ends_with('\n') {
+                line.pop();
+            }
+            let start = state.offset;
+            state.offset += line_len;
+            for line in multiline.push(id, paths, start, state.offset, line) {
+                line_groups.push(Tailer::line_group(paths, line));
+            }
+            budget.spend(line_len);
+            if budget.is_exhausted() {
                 break;
             }
-            // remove the trailing new line
-            line.pop();
-            // increment the offset
-            *offset += line_len;
-            // send the line upstream, safe to unwrap
-            debug!("tailer sendings lines for {:?}", paths);
-            line_groups.push(
-                paths
-                    .iter()
-                    .map(|path| {
-                        Metrics::fs().increment_lines();
-                        Metrics::fs().add_bytes(line_len);
-                        LineBuilder::new()
-                            .line(line.clone())
-                            .file(path.to_str().unwrap_or("").to_string())
-                    })
-                    .collect(),
-            );
+        }
+        decoder.detach();
+
+        if state.completed {
+            if let Some(aggregate) = multiline.remove(id) {
+                line_groups.push(Tailer::line_group(paths, aggregate.line));
+            }
         }
 
         if line_groups.is_empty() {
@@ -276,11 +852,30 @@ impl Tailer {
             Some(line_groups)
         }
     }
+
+    // creates a line for every path a file is reachable through
+    fn line_group(paths: &[PathBuf], line: String) -> Vec<LineBuilder> {
+        // send the line upstream, safe to unwrap
+        debug!("tailer sendings lines for {:?}", paths);
+        paths
+            .iter()
+            .map(|path| {
+                Metrics::fs().increment_lines();
+                Metrics::fs().add_bytes(line.len() as u64 + 1);
+                let mut line = LineBuilder::new()
+                    .line(line.clone())
+                    .file(path.to_str().unwrap_or("").to_string());
+                set_source(&mut line, "file");
+                line
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::multiline::Matcher;
     use crate::rule::{GlobRule, Rules};
     use crate::test::LOGGER;
     use std::convert::TryInto;
@@ -383,7 +978,7 @@ mod test {
                         .try_into()
                         .unwrap_or_else(|_| panic!("{:?} is not a directory!", dir.path()))],
                     rules,
-                    Lookback::SmallFiles,
+                    Lookback::SmallFiles(DEFAULT_SMALL_FILES_BYTES),
                 );
                 let mut buf = [0u8; 4096];
 
@@ -460,4 +1055,625 @@ mod test {
             })
         })
     }
+    #[test]
+    fn stored_offset_lookback() {
+        run_test(|| {
+            tokio_test::block_on(async {
+                let mut rules = Rules::new();
+                rules.add_inclusion(GlobRule::new(r"**").unwrap());
+
+                let dir = tempdir().expect("Couldn't create temp dir...");
+                let file_path = dir.path().join("test.log");
+                let mut file = File::create(&file_path).expect("Couldn't create temp log file...");
+                (0..3).for_each(|i| {
+                    writeln!(file, "line {}", i).expect("Couldn't write to temp log file...")
+                });
+                file.sync_all().expect("Failed to sync file");
+
+                // pretend a previous run already sent the first line
+                let id = FileId::from(&file_path.metadata().unwrap());
+                let offsets = Arc::new(Mutex::new(
+                    OffsetStore::open(dir.path().join("state").join("offsets.json")).unwrap(),
+                ));
+                let state =
+                    FileState::new(&File::open(&file_path).unwrap(), "line 0\n".len() as u64);
+                offsets.lock().unwrap().insert(id, state);
+
+                let mut tailer = Tailer::new(
+                    vec![dir
+                        .path()
+                        .try_into()
+                        .unwrap_or_else(|_| panic!("{:?} is not a directory!", dir.path()))],
+                    rules,
+                    Lookback::None,
+                );
+                tailer.set_offset_store(offsets.clone());
+                let mut buf = [0u8; 4096];
+
+                let stream = tailer
+                    .process(&mut buf)
+                    .expect("failed to read events")
+                    .timeout(std::time::Duration::from_millis(500));
+
+                let write_files = async move {
+                    tokio::time::delay_for(tokio::time::Duration::from_millis(250)).await;
+                    writeln!(file, "line 3").expect("Couldn't write to temp log file...");
+                    file.sync_all().expect("Failed to sync file");
+                };
+                let (_, events) =
+                    futures::join!(tokio::spawn(write_files), take_events!(stream, 4));
+                let events = events.iter().flatten().collect::<Vec<_>>();
+                assert_eq!(events.len(), 3);
+                assert_eq!(events[0][0].line.as_deref(), Some("line 1"));
+
+                let len = file_path.metadata().unwrap().len();
+                assert_eq!(
+                    offsets.lock().unwrap().get(&id).map(|s| s.offset),
+                    Some(len)
+                );
+            });
+        });
+    }
+
+    #[test]
+    fn deleted_file_with_closed_handle_forgets_offset() {
+        run_test(|| {
+            tokio_test::block_on(async {
+                let mut rules = Rules::new();
+                rules.add_inclusion(GlobRule::new(r"**").unwrap());
+
+                let dir = tempdir().expect("Couldn't create temp dir...");
+                let paths = [dir.path().join("a.log"), dir.path().join("b.log")];
+                for path in paths.iter() {
+                    let mut file = File::create(path).expect("Couldn't create temp log file...");
+                    writeln!(file, "line 0").expect("Couldn't write to temp log file...");
+                }
+                let ids = paths
+                    .iter()
+                    .map(|path| FileId::from(&path.metadata().unwrap()))
+                    .collect::<Vec<_>>();
+                let offsets = Arc::new(Mutex::new(
+                    OffsetStore::open(dir.path().join("state").join("offsets.json")).unwrap(),
+                ));
+
+                let mut tailer = Tailer::with_options(
+                    vec![dir
+                        .path()
+                        .try_into()
+                        .unwrap_or_else(|_| panic!("{:?} is not a directory!", dir.path()))],
+                    rules,
+                    Lookback::None,
+                    Options {
+                        max_open_files: Some(1),
+                        ..Default::default()
+                    },
+                );
+                tailer.set_offset_store(offsets.clone());
+                let mut buf = [0u8; 4096];
+
+                let stream = tailer
+                    .process(&mut buf)
+                    .expect("failed to read events")
+                    .timeout(std::time::Duration::from_millis(500));
+
+                // reading b.log closes the handle of a.log before it's deleted
+                let (a, b) = (paths[0].clone(), paths[1].clone());
+                let write_files = async move {
+                    tokio::time::delay_for(tokio::time::Duration::from_millis(250)).await;
+                    let mut file = std::fs::OpenOptions::new().append(true).open(&b).unwrap();
+                    writeln!(file, "line 1").expect("Couldn't write to temp log file...");
+                    file.sync_all().expect("Failed to sync file");
+                    tokio::time::delay_for(tokio::time::Duration::from_millis(100)).await;
+                    std::fs::remove_file(&a).unwrap();
+                };
+                let (_, events) =
+                    futures::join!(tokio::spawn(write_files), take_events!(stream, 2));
+                let events = events.iter().flatten().collect::<Vec<_>>();
+                assert_eq!(events.len(), 1);
+                assert_eq!(events[0][0].line.as_deref(), Some("line 1"));
+
+                let offsets = offsets.lock().unwrap();
+                assert_eq!(offsets.get(&ids[0]), None);
+                assert!(offsets.get(&ids[1]).is_some());
+            });
+        });
+    }
+
+    #[test]
+    fn replaced_file_with_closed_handle_counts_failed_reopen() {
+        run_test(|| {
+            tokio_test::block_on(async {
+                let mut rules = Rules::new();
+                rules.add_inclusion(GlobRule::new(r"**").unwrap());
+
+                let dir = tempdir().expect("Couldn't create temp dir...");
+                let other_dir = tempdir().expect("Couldn't create temp dir...");
+                let paths = [dir.path().join("a.log"), dir.path().join("b.log")];
+                for path in paths.iter() {
+                    File::create(path).expect("Couldn't create temp log file...");
+                }
+                let replacement = other_dir.path().join("a.log");
+                let mut file =
+                    File::create(&replacement).expect("Couldn't create temp log file...");
+                writeln!(file, "line 3").expect("Couldn't write to temp log file...");
+                let failed_reopens = Metrics::fs().read_failed_reopens();
+
+                let mut tailer = Tailer::with_options(
+                    vec![dir
+                        .path()
+                        .try_into()
+                        .unwrap_or_else(|_| panic!("{:?} is not a directory!", dir.path()))],
+                    rules,
+                    Lookback::None,
+                    Options {
+                        max_open_files: Some(1),
+                        ..Default::default()
+                    },
+                );
+                let mut buf = [0u8; 4096];
+
+                let stream = tailer
+                    .process(&mut buf)
+                    .expect("failed to read events")
+                    .timeout(std::time::Duration::from_millis(500));
+
+                // reading b.log closes the handle of a.log, which is replaced right after it's
+                // written to so the write can't be read through it's path anymore
+                let (a, b) = (paths[0].clone(), paths[1].clone());
+                let write_files = async move {
+                    tokio::time::delay_for(tokio::time::Duration::from_millis(250)).await;
+                    let mut file = std::fs::OpenOptions::new().append(true).open(&b).unwrap();
+                    writeln!(file, "line 1").expect("Couldn't write to temp log file...");
+                    file.sync_all().expect("Failed to sync file");
+                    tokio::time::delay_for(tokio::time::Duration::from_millis(100)).await;
+                    let mut file = std::fs::OpenOptions::new().append(true).open(&a).unwrap();
+                    writeln!(file, "line 2").expect("Couldn't write to temp log file...");
+                    file.sync_all().expect("Failed to sync file");
+                    std::fs::rename(&replacement, &a).unwrap();
+                };
+                let (_, events) =
+                    futures::join!(tokio::spawn(write_files), take_events!(stream, 3));
+                let lines = events
+                    .iter()
+                    .flatten()
+                    .flatten()
+                    .map(|line| line.line.as_deref().unwrap())
+                    .collect::<Vec<_>>();
+                assert_eq!(lines, vec!["line 1", "line 3"]);
+                assert!(Metrics::fs().read_failed_reopens() > failed_reopens);
+            });
+        });
+    }
+
+    #[test]
+    fn copytruncate_rereads_from_start() {
+        run_test(|| {
+            tokio_test::block_on(async {
+                let mut rules = Rules::new();
+                rules.add_inclusion(GlobRule::new(r"**").unwrap());
+
+                let dir = tempdir().expect("Couldn't create temp dir...");
+                let file_path = dir.path().join("test.log");
+                let mut file = File::create(&file_path).expect("Couldn't create temp log file...");
+                writeln!(file, "old line").expect("Couldn't write to temp log file...");
+                file.sync_all().expect("Failed to sync file");
+
+                let mut tailer = Tailer::new(
+                    vec![dir
+                        .path()
+                        .try_into()
+                        .unwrap_or_else(|_| panic!("{:?} is not a directory!", dir.path()))],
+                    rules,
+                    Lookback::None,
+                );
+                let mut buf = [0u8; 4096];
+
+                let stream = tailer
+                    .process(&mut buf)
+                    .expect("failed to read events")
+                    .timeout(std::time::Duration::from_millis(500));
+
+                // truncate and write past the old offset before the tailer notices, the length
+                // alone can't tell this apart from an append
+                let write_files = async move {
+                    tokio::time::delay_for(tokio::time::Duration::from_millis(250)).await;
+                    let mut file = std::fs::OpenOptions::new()
+                        .write(true)
+                        .truncate(true)
+                        .open(&file_path)
+                        .expect("Couldn't truncate temp log file...");
+                    writeln!(file, "new line 1\nnew line 2")
+                        .expect("Couldn't write to temp log file...");
+                    file.sync_all().expect("Failed to sync file");
+                };
+                let (_, events) =
+                    futures::join!(tokio::spawn(write_files), take_events!(stream, 3));
+                let lines = events
+                    .iter()
+                    .flatten()
+                    .map(|e| e[0].line.clone().unwrap())
+                    .collect::<Vec<_>>();
+                assert_eq!(lines, vec!["new line 1", "new line 2"]);
+            });
+        });
+    }
+
+    #[test]
+    fn compressed_files_are_read_once() {
+        run_test(|| {
+            tokio_test::block_on(async {
+                let mut rules = Rules::new();
+                rules.add_inclusion(GlobRule::new(r"**").unwrap());
+
+                let dir = tempdir().expect("Couldn't create temp dir...");
+                let gzip_path = dir.path().join("test.log.1.gz");
+                let zstd_path = dir.path().join("test.log.2.zst");
+
+                let mut gzip =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                writeln!(gzip, "gzip line 1\ngzip line 2").expect("Couldn't compress lines...");
+                let gzip = gzip.finish().expect("Couldn't compress lines...");
+                let zstd = zstd::stream::encode_all(&b"zstd line 1\nzstd line 2"[..], 0)
+                    .expect("Couldn't compress lines...");
+
+                let mut tailer = Tailer::new(
+                    vec![dir
+                        .path()
+                        .try_into()
+                        .unwrap_or_else(|_| panic!("{:?} is not a directory!", dir.path()))],
+                    rules,
+                    Lookback::None,
+                );
+                let mut buf = [0u8; 4096];
+
+                let stream = tailer
+                    .process(&mut buf)
+                    .expect("failed to read events")
+                    .timeout(std::time::Duration::from_millis(500));
+
+                let write_files = async move {
+                    // the compressed stream is written in two parts, the end of the first part
+                    // can't be decompressed until the rest is written
+                    let (head, tail) = gzip.split_at(gzip.len() / 2);
+                    let mut file =
+                        File::create(&gzip_path).expect("Couldn't create temp log file...");
+                    file.write_all(head)
+                        .expect("Couldn't write to temp log file...");
+                    file.sync_all().expect("Failed to sync file");
+                    tokio::time::delay_for(tokio::time::Duration::from_millis(100)).await;
+                    file.write_all(tail)
+                        .expect("Couldn't write to temp log file...");
+                    file.sync_all().expect("Failed to sync file");
+
+                    std::fs::write(&zstd_path, zstd).expect("Couldn't write to temp log file...");
+
+                    // anything written after the end of a compressed file isn't read again
+                    tokio::time::delay_for(tokio::time::Duration::from_millis(100)).await;
+                    file.write_all(&gzip)
+                        .expect("Couldn't write to temp log file...");
+                    file.sync_all().expect("Failed to sync file");
+                };
+                let (_, events) =
+                    futures::join!(tokio::spawn(write_files), take_events!(stream, 5));
+                let lines = events
+                    .iter()
+                    .flatten()
+                    .map(|e| e[0].line.clone().unwrap())
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    lines,
+                    vec!["gzip line 1", "gzip line 2", "zstd line 1", "zstd line 2"]
+                );
+            });
+        });
+    }
+
+    #[test]
+    fn compressed_read_budget() {
+        let dir = tempdir().expect("Couldn't create temp dir...");
+        let file_path = dir.path().join("test.log.1.gz");
+        let mut gzip = flate2::write::GzEncoder::new(
+            File::create(&file_path).expect("Couldn't create temp log file..."),
+            flate2::Compression::default(),
+        );
+        writeln!(gzip, "line 1\nline 2").expect("Couldn't compress lines...");
+        gzip.finish().expect("Couldn't compress lines...");
+
+        let file = File::open(&file_path).unwrap();
+        let paths = vec![file_path];
+        let mut state = FileState::default();
+        let mut multiline = Multiline::default();
+        let mut readers = FileReaders {
+            encodings: Encodings::default(),
+            partials: PartialLines::default(),
+            decoders: HashMap::new(),
+        };
+        let mut read = || {
+            let mut budget = ReadBudget {
+                bytes: None,
+                lines: Some(1),
+            };
+            Tailer::tail(
+                &file,
+                &paths,
+                &mut state,
+                &mut multiline,
+                &mut readers,
+                &mut budget,
+            )
+            .unwrap_or_default()
+            .into_iter()
+            .map(|lines| lines[0].line.clone().unwrap())
+            .collect::<Vec<_>>()
+        };
+
+        // each read continues where the last one stopped
+        assert_eq!(read(), vec!["line 1"]);
+        assert_eq!(read(), vec!["line 2"]);
+        assert!(read().is_empty());
+        assert!(state.completed);
+        assert_eq!(state.offset, 14);
+        assert!(readers.decoders.is_empty());
+    }
+
+    #[test]
+    fn read_budget_interleaves_files() {
+        run_test(|| {
+            tokio_test::block_on(async {
+                let mut rules = Rules::new();
+                rules.add_inclusion(GlobRule::new(r"**").unwrap());
+
+                let dir = tempdir().expect("Couldn't create temp dir...");
+                let a_path = dir.path().join("a.log");
+                let b_path = dir.path().join("b.log");
+
+                let mut tailer = Tailer::new(
+                    vec![dir
+                        .path()
+                        .try_into()
+                        .unwrap_or_else(|_| panic!("{:?} is not a directory!", dir.path()))],
+                    rules,
+                    Lookback::None,
+                );
+                tailer.set_read_budget(ReadBudget {
+                    bytes: None,
+                    lines: Some(1),
+                });
+                let mut buf = [0u8; 4096];
+
+                let stream = tailer
+                    .process(&mut buf)
+                    .expect("failed to read events")
+                    .timeout(std::time::Duration::from_millis(500));
+
+                let write_files = async move {
+                    std::fs::write(&a_path, "a1\na2\na3\n")
+                        .expect("Couldn't write to temp log file...");
+                    std::fs::write(&b_path, "b1\nb2\nb3\n")
+                        .expect("Couldn't write to temp log file...");
+                };
+                let (_, events) =
+                    futures::join!(tokio::spawn(write_files), take_events!(stream, 7));
+                let lines = events
+                    .iter()
+                    .flatten()
+                    .map(|e| e[0].line.clone().unwrap())
+                    .collect::<Vec<_>>();
+                assert_eq!(lines.len(), 6);
+                let position = |line: &str| lines.iter().position(|l| l == line).unwrap();
+                assert!(position("a1") < position("a2") && position("a2") < position("a3"));
+                assert!(position("b1") < position("b2") && position("b2") < position("b3"));
+                // b is read before a is done even though a is written first
+                assert!(position("b1") < position("a3"));
+            });
+        });
+    }
+
+    #[test]
+    fn utf16_file_with_bom() {
+        run_test(|| {
+            tokio_test::block_on(async {
+                let mut rules = Rules::new();
+                rules.add_inclusion(GlobRule::new(r"**").unwrap());
+
+                let dir = tempdir().expect("Couldn't create temp dir...");
+                let file_path = dir.path().join("test.log");
+
+                let mut tailer = Tailer::new(
+                    vec![dir
+                        .path()
+                        .try_into()
+                        .unwrap_or_else(|_| panic!("{:?} is not a directory!", dir.path()))],
+                    rules,
+                    Lookback::None,
+                );
+                let mut buf = [0u8; 4096];
+
+                let stream = tailer
+                    .process(&mut buf)
+                    .expect("failed to read events")
+                    .timeout(std::time::Duration::from_millis(500));
+
+                let write_files = async move {
+                    let content = b"\xff\xfe"
+                        .iter()
+                        .copied()
+                        .chain("line 1\nline 2\n".encode_utf16().flat_map(u16::to_le_bytes))
+                        .collect::<Vec<_>>();
+                    std::fs::write(&file_path, content)
+                        .expect("Couldn't write to temp log file...");
+                };
+                let (_, events) =
+                    futures::join!(tokio::spawn(write_files), take_events!(stream, 3));
+                let lines = events
+                    .iter()
+                    .flatten()
+                    .map(|e| e[0].line.clone().unwrap())
+                    .collect::<Vec<_>>();
+                assert_eq!(lines, vec!["line 1", "line 2"]);
+            });
+        });
+    }
+
+    #[test]
+    fn multiline_aggregation() {
+        run_test(|| {
+            tokio_test::block_on(async {
+                let mut rules = Rules::new();
+                rules.add_inclusion(GlobRule::new(r"**").unwrap());
+
+                let dir = tempdir().expect("Couldn't create temp dir...");
+                let file_path = dir.path().join("test.log");
+                let mut file = File::create(&file_path).expect("Couldn't create temp log file...");
+
+                let mut tailer = Tailer::new(
+                    vec![dir
+                        .path()
+                        .try_into()
+                        .unwrap_or_else(|_| panic!("{:?} is not a directory!", dir.path()))],
+                    rules,
+                    Lookback::None,
+                );
+                let mut multiline_rules = Rules::new();
+                multiline_rules.add_inclusion(GlobRule::new(r"*.log").unwrap());
+                let mut multiline_rule = MultilineRule::new(multiline_rules, Matcher::Indent);
+                multiline_rule.flush_timeout = std::time::Duration::from_millis(100);
+                tailer.set_multiline_rules(vec![multiline_rule]);
+                let mut buf = [0u8; 4096];
+
+                let stream = tailer
+                    .process(&mut buf)
+                    .expect("failed to read events")
+                    .timeout(std::time::Duration::from_millis(500));
+
+                let write_files = async move {
+                    tokio::time::delay_for(tokio::time::Duration::from_millis(250)).await;
+                    write!(file, "Exception\n\tat a\n\tat b\nnext line\n")
+                        .expect("Couldn't write to temp log file...");
+                    file.sync_all().expect("Failed to sync file");
+                };
+                let (_, events) =
+                    futures::join!(tokio::spawn(write_files), take_events!(stream, 3));
+                let lines = events
+                    .iter()
+                    .flatten()
+                    .map(|e| e[0].line.clone().unwrap())
+                    .collect::<Vec<_>>();
+                // the last line is sent once the flush timeout passed
+                assert_eq!(lines, vec!["Exception\n\tat a\n\tat b", "next line"]);
+            });
+        });
+    }
+
+    #[test]
+    fn partial_line_timeout() {
+        run_test(|| {
+            tokio_test::block_on(async {
+                let mut rules = Rules::new();
+                rules.add_inclusion(GlobRule::new(r"**").unwrap());
+
+                let dir = tempdir().expect("Couldn't create temp dir...");
+                let file_path = dir.path().join("test.log");
+                let mut file = File::create(&file_path).expect("Couldn't create temp log file...");
+
+                let mut tailer = Tailer::new(
+                    vec![dir
+                        .path()
+                        .try_into()
+                        .unwrap_or_else(|_| panic!("{:?} is not a directory!", dir.path()))],
+                    rules,
+                    Lookback::None,
+                );
+                tailer.set_partial_line_timeout(std::time::Duration::from_millis(100));
+                let mut buf = [0u8; 4096];
+
+                let stream = tailer
+                    .process(&mut buf)
+                    .expect("failed to read events")
+                    .timeout(std::time::Duration::from_millis(500));
+
+                let write_files = async move {
+                    tokio::time::delay_for(tokio::time::Duration::from_millis(250)).await;
+                    write!(file, "partial line").expect("Couldn't write to temp log file...");
+                    file.sync_all().expect("Failed to sync file");
+                    tokio::time::delay_for(tokio::time::Duration::from_millis(400)).await;
+                    write!(file, "\nnext line\n").expect("Couldn't write to temp log file...");
+                    file.sync_all().expect("Failed to sync file");
+                };
+                let (_, events) =
+                    futures::join!(tokio::spawn(write_files), take_events!(stream, 3));
+                let lines = events
+                    .iter()
+                    .flatten()
+                    .map(|e| e[0].line.clone().unwrap())
+                    .collect::<Vec<_>>();
+                // the new line completing the already sent partial line doesn't create a line
+                assert_eq!(lines, vec!["partial line", "next line"]);
+            });
+        });
+    }
+
+    #[test]
+    fn partial_line_sent_before_restart() {
+        run_test(|| {
+            tokio_test::block_on(async {
+                let mut rules = Rules::new();
+                rules.add_inclusion(GlobRule::new(r"**").unwrap());
+
+                let dir = tempdir().expect("Couldn't create temp dir...");
+                let file_path = dir.path().join("test.log");
+                let mut file = File::create(&file_path).expect("Couldn't create temp log file...");
+                write!(file, "partial line").expect("Couldn't write to temp log file...");
+                file.sync_all().expect("Failed to sync file");
+
+                // pretend a previous run already sent the partial line
+                let id = FileId::from(&file_path.metadata().unwrap());
+                let offsets = Arc::new(Mutex::new(
+                    OffsetStore::open(dir.path().join("state").join("offsets.json")).unwrap(),
+                ));
+                let state = FileState {
+                    skip_new_line: true,
+                    ..FileState::new(
+                        &File::open(&file_path).unwrap(),
+                        "partial line".len() as u64,
+                    )
+                };
+                offsets.lock().unwrap().insert(id, state);
+
+                let mut tailer = Tailer::new(
+                    vec![dir
+                        .path()
+                        .try_into()
+                        .unwrap_or_else(|_| panic!("{:?} is not a directory!", dir.path()))],
+                    rules,
+                    Lookback::None,
+                );
+                tailer.set_offset_store(offsets.clone());
+                let mut buf = [0u8; 4096];
+
+                let stream = tailer
+                    .process(&mut buf)
+                    .expect("failed to read events")
+                    .timeout(std::time::Duration::from_millis(500));
+
+                let write_files = async move {
+                    tokio::time::delay_for(tokio::time::Duration::from_millis(250)).await;
+                    write!(file, "\nnext line\n").expect("Couldn't write to temp log file...");
+                    file.sync_all().expect("Failed to sync file");
+                };
+                let (_, events) =
+                    futures::join!(tokio::spawn(write_files), take_events!(stream, 2));
+                let lines = events
+                    .iter()
+                    .flatten()
+                    .map(|e| e[0].line.clone().unwrap())
+                    .collect::<Vec<_>>();
+                assert_eq!(lines, vec!["next line"]);
+                assert_eq!(
+                    offsets.lock().unwrap().get(&id).map(|s| s.skip_new_line),
+                    Some(false)
+                );
+            });
+        });
+    }
 }
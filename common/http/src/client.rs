@@ -6,13 +6,17 @@ use tokio::runtime::{Builder, Runtime};
 
 use crate::limit::RateLimiter;
 use crate::retry::Retry;
+use crate::source::take_source;
+use crate::timestamp::take_timestamp;
 use crate::types::body::{IngestBody, Line, LineBuilder};
 use crate::types::client::Client as HttpClient;
 use crate::types::error::HttpError;
 use crate::types::request::RequestTemplate;
 use crate::types::response::Response;
 use metrics::Metrics;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::thread::sleep;
 
 /// Http(s) client used to send logs to the Ingest API
 pub struct Client {
@@ -20,6 +24,9 @@ pub struct Client {
     runtime: Runtime,
     limiter: RateLimiter,
     retry: Arc<Retry>,
+    // requests that haven't completed yet and requests that weren't delivered
+    pending: Arc<AtomicUsize>,
+    undelivered: Arc<AtomicUsize>,
 
     buffer: Vec<Line>,
     buffer_max_size: usize,
@@ -41,6 +48,8 @@ impl Client {
             runtime,
             limiter: RateLimiter::new(10),
             retry: Arc::new(Retry::new()),
+            pending: Arc::new(AtomicUsize::new(0)),
+            undelivered: Arc::new(AtomicUsize::new(0)),
             buffer: Vec::new(),
             buffer_max_size: 2 * 1024 * 1024,
             buffer_bytes: 0,
@@ -64,9 +73,14 @@ impl Client {
         }
     }
     /// The main logic loop, consumes self because it should only be called once
-    pub fn send(&mut self, line: LineBuilder) {
+    pub fn send(&mut self, mut line: LineBuilder) {
         self.poll();
-        if let Ok(line) = line.build() {
+        take_source(&mut line);
+        let timestamp = take_timestamp(&mut line);
+        if let Ok(mut line) = line.build() {
+            if let Some(timestamp) = timestamp {
+                line.timestamp = timestamp;
+            }
             self.buffer_bytes += line.line.len();
             self.buffer.push(line);
         }
@@ -80,6 +94,25 @@ impl Client {
         self.inner.set_timeout(timeout)
     }
 
+    /// Sends the buffered lines and waits up to `timeout` for all requests to complete, returning
+    /// whether every request was delivered. The bodies of failed requests are left for a later
+    /// retry.
+    pub fn flush_and_wait(&mut self, timeout: Duration) -> bool {
+        self.flush();
+        let deadline = Instant::now() + timeout;
+        while self.pending.load(Ordering::SeqCst) > 0 {
+            if Instant::now() >= deadline {
+                warn!(
+                    "gave up waiting for {} requests to complete",
+                    self.pending.load(Ordering::SeqCst)
+                );
+                return false;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        self.undelivered.swap(0, Ordering::SeqCst) == 0
+    }
+
     fn should_flush(&self) -> bool {
         self.buffer_bytes >= self.buffer_max_size
             || self.last_flush.elapsed() > Duration::from_millis(250)
@@ -106,7 +139,13 @@ impl Client {
 
     fn make_request(&mut self, body: IngestBody) {
         let retry = self.retry.clone();
+        let pending = self.pending.clone();
+        let undelivered = self.undelivered.clone();
+        pending.fetch_add(1, Ordering::SeqCst);
         let fut = self.inner.send(self.limiter.get_slot(body)).then(move |r| {
+            if !matches!(r, Ok(Response::Sent)) {
+                undelivered.fetch_add(1, Ordering::SeqCst);
+            }
             match r {
                 Ok(Response::Failed(_, s, r)) => warn!("bad response {}: {}", s, r),
                 Err(HttpError::Send(body, e)) => {
@@ -126,6 +165,7 @@ impl Client {
                 }
                 Ok(Response::Sent) => {} //success
             };
+            pending.fetch_sub(1, Ordering::SeqCst);
             Ok(())
         });
         self.runtime.spawn(fut);
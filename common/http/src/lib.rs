@@ -4,6 +4,8 @@ extern crate log;
 pub mod client;
 pub mod limit;
 pub mod retry;
+pub mod source;
+pub mod timestamp;
 
 pub mod types {
     pub use logdna_client::*;
@@ -0,0 +1,68 @@
+use crate::types::body::LineBuilder;
+use serde_json::{Map, Value};
+
+/// The meta key the source of a line is carried in until the line is sent, so middlewares can
+/// tell the lines of files, journald or syslog apart
+pub const SOURCE_KEY: &str = "__source";
+
+/// The sources lines are read from
+pub const SOURCES: &[&str] = &[
+    "file",
+    "journald",
+    "syslog",
+    "unix_socket",
+    "kmsg",
+    "k8s_events",
+    "pipe",
+];
+
+/// Sets the source a line was read from, e.g. `file` or `journald`. Lines with meta that isn't
+/// an object can't carry a source.
+pub fn set_source(line: &mut LineBuilder, source: &str) {
+    let meta = line.meta.get_or_insert_with(|| Value::Object(Map::new()));
+    if let Value::Object(meta) = meta {
+        meta.insert(SOURCE_KEY.to_string(), Value::from(source));
+    }
+}
+
+/// Returns the source a line was read from, if it has one
+pub fn get_source(line: &LineBuilder) -> Option<&str> {
+    line.meta.as_ref()?.get(SOURCE_KEY)?.as_str()
+}
+
+/// Removes the source from a line before it's sent
+pub fn take_source(line: &mut LineBuilder) -> Option<String> {
+    let meta = match line.meta {
+        Some(Value::Object(ref mut meta)) => meta,
+        _ => return None,
+    };
+    let source = meta.remove(SOURCE_KEY);
+    if meta.is_empty() {
+        line.meta = None;
+    }
+    match source? {
+        Value::String(source) => Some(source),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn carry_source() {
+        let mut line = LineBuilder::new().line("a");
+        set_source(&mut line, "journald");
+        assert_eq!(get_source(&line), Some("journald"));
+        assert_eq!(take_source(&mut line), Some("journald".to_string()));
+        assert_eq!(line.meta, None);
+
+        let mut line = LineBuilder::new()
+            .line("a")
+            .meta(serde_json::json!({"key": "value"}));
+        set_source(&mut line, "file");
+        assert_eq!(take_source(&mut line), Some("file".to_string()));
+        assert_eq!(line.meta, Some(serde_json::json!({"key": "value"})));
+    }
+}
@@ -0,0 +1,61 @@
+use crate::types::body::LineBuilder;
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+
+/// The meta key the timestamp of a line is carried in until the line is sent, line builders
+/// don't have a timestamp of their own
+pub const TIMESTAMP_KEY: &str = "__timestamp";
+
+/// Sets the time a line was logged at, lines without one are sent with the time they are sent
+/// at. Lines with meta that isn't an object can't carry a timestamp.
+pub fn set_timestamp(line: &mut LineBuilder, timestamp: DateTime<Utc>) {
+    let meta = line.meta.get_or_insert_with(|| Value::Object(Map::new()));
+    if let Value::Object(meta) = meta {
+        meta.insert(
+            TIMESTAMP_KEY.to_string(),
+            Value::from(timestamp.timestamp_millis()),
+        );
+    }
+}
+
+/// Returns the time a line was logged at in milliseconds since the epoch, if it has one
+pub fn get_timestamp(line: &LineBuilder) -> Option<i64> {
+    line.meta.as_ref()?.get(TIMESTAMP_KEY)?.as_i64()
+}
+
+/// Removes the timestamp from a line, returning it in milliseconds since the epoch
+pub fn take_timestamp(line: &mut LineBuilder) -> Option<i64> {
+    let meta = match line.meta {
+        Some(Value::Object(ref mut meta)) => meta,
+        _ => return None,
+    };
+    let timestamp = meta.remove(TIMESTAMP_KEY)?.as_i64();
+    if meta.is_empty() {
+        line.meta = None;
+    }
+    timestamp
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn carry_timestamp() {
+        let timestamp = Utc.timestamp_millis(1_600_000_000_123);
+
+        let mut line = LineBuilder::new().line("a");
+        set_timestamp(&mut line, timestamp);
+        assert_eq!(get_timestamp(&line), Some(1_600_000_000_123));
+        assert_eq!(take_timestamp(&mut line), Some(1_600_000_000_123));
+        assert_eq!(line.meta, None);
+
+        let mut line = LineBuilder::new()
+            .line("a")
+            .meta(serde_json::json!({"key": "value"}));
+        set_timestamp(&mut line, timestamp);
+        assert_eq!(take_timestamp(&mut line), Some(1_600_000_000_123));
+        assert_eq!(line.meta, Some(serde_json::json!({"key": "value"})));
+    }
+}
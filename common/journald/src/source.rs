@@ -4,7 +4,12 @@ use http::types::body::LineBuilder;
 use log::{info, warn};
 use std::path::PathBuf;
 
-pub fn create_source(paths: &[PathBuf]) -> impl FutureStream<Item = Vec<LineBuilder>> {
+/// Streams the records of the journals in the paths, records get the level of their `PRIORITY`
+/// if `levels` is set
+pub fn create_source(
+    paths: &[PathBuf],
+    levels: bool,
+) -> impl FutureStream<Item = Vec<LineBuilder>> {
     let mut journal_files: Vec<PathBuf> = Vec::new();
     let mut journal_directories: Vec<PathBuf> = Vec::new();
     for path in paths {
@@ -22,10 +27,10 @@ pub fn create_source(paths: &[PathBuf]) -> impl FutureStream<Item = Vec<LineBuil
 
     let mut streams: Vec<Stream> = journal_directories
         .into_iter()
-        .map(|dir| Stream::new(Path::Directory(dir)))
+        .map(|dir| Stream::new(Path::Directory(dir), levels))
         .collect();
     if !journal_files.is_empty() {
-        streams.push(Stream::new(Path::Files(journal_files)));
+        streams.push(Stream::new(Path::Files(journal_files), levels));
     }
 
     let combined_stream: SelectAll<<Vec<Stream> as IntoIterator>::Item> = select_all(streams);
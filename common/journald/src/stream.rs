@@ -1,8 +1,11 @@
 use crate::error::JournalError;
 use futures::{channel::oneshot, stream::Stream as FutureStream};
+use http::source::set_source;
+use http::timestamp::set_timestamp;
 use http::types::body::LineBuilder;
 use log::{info, warn};
 use metrics::Metrics;
+use middleware::level::Level;
 use std::{
     mem::drop,
     path::PathBuf,
@@ -21,6 +24,7 @@ const KEY_MESSAGE: &str = "MESSAGE";
 const KEY_SYSTEMD_UNIT: &str = "_SYSTEMD_UNIT";
 const KEY_SYSLOG_IDENTIFIER: &str = "SYSLOG_IDENTIFIER";
 const KEY_CONTAINER_NAME: &str = "CONTAINER_NAME";
+const KEY_PRIORITY: &str = "PRIORITY";
 const DEFAULT_APP: &str = "UNKNOWN_SYSTEMD_APP";
 
 #[derive(Clone)]
@@ -38,16 +42,19 @@ pub struct Stream {
     receiver: Option<Receiver<LineBuilder>>,
     shared_state: Arc<Mutex<SharedState>>,
     path: Path,
+    levels: bool,
     thread_stop_chan: Option<oneshot::Sender<()>>,
 }
 
 impl Stream {
-    pub fn new(path: Path) -> Self {
+    /// Streams the records of the path, they get the level of their `PRIORITY` if `levels` is set
+    pub fn new(path: Path, levels: bool) -> Self {
         let mut stream = Self {
             thread: None,
             receiver: None,
             shared_state: Arc::new(Mutex::new(SharedState { waker: None })),
             path,
+            levels,
             thread_stop_chan: None,
         };
 
@@ -64,8 +71,9 @@ impl Stream {
         let (sender, receiver) = sync_channel(100);
         let thread_shared_state = self.shared_state.clone();
         let path = self.path.clone();
+        let levels = self.levels;
         let thread = thread::spawn(move || {
-            let mut journal = Reader::new(path);
+            let mut journal = Reader::new(path, levels);
 
             let call_waker = || {
                 let mut shared_state = match thread_shared_state.lock() {
@@ -181,10 +189,11 @@ impl Drop for Stream {
 
 struct Reader {
     reader: Journal,
+    levels: bool,
 }
 
 impl Reader {
-    fn new(path: Path) -> Self {
+    fn new(path: Path, levels: bool) -> Self {
         let mut reader = match path {
             Path::Directory(path) => Journal::open_directory(&path, JournalFiles::All, false)
                 .expect("Could not open journald reader for directory"),
@@ -197,7 +206,7 @@ impl Reader {
             .seek(JournalSeek::Tail)
             .expect("Could not seek to tail of journald logs");
 
-        Self { reader }
+        Self { reader, levels }
     }
 
     fn process_next_record(&mut self) -> Result<Option<LineBuilder>, JournalError> {
@@ -207,13 +216,8 @@ impl Reader {
             Err(e) => return Err(JournalError::BadRead(e)),
         };
 
-        match self
-            .reader
-            .timestamp()
-            .ok()
-            .map(|timestamp| SystemTime::now().duration_since(timestamp).ok())
-            .flatten()
-        {
+        let timestamp = self.reader.timestamp().ok();
+        match timestamp.and_then(|timestamp| SystemTime::now().duration_since(timestamp).ok()) {
             Some(duration) => {
                 // Reject any records with a timestamp older than 30 seconds
                 if duration >= Duration::from_secs(30) {
@@ -226,9 +230,13 @@ impl Reader {
             None => {
                 warn!("Unable to read timestamp associated with journald record");
             }
-        } //TODO: Actually bake the timestamp into the outgoing line
+        }
 
-        self.process_default_record(&record)
+        let mut line = self.process_default_record(&record)?;
+        if let (Some(line), Some(timestamp)) = (line.as_mut(), timestamp) {
+            set_timestamp(line, timestamp.into());
+        }
+        Ok(line)
     }
 
     fn process_default_record(
@@ -252,7 +260,17 @@ impl Reader {
 
         Metrics::journald().increment_lines();
         Metrics::journald().add_bytes(message.len() as u64);
-        Ok(Some(LineBuilder::new().line(message).file(app)))
+        let mut line = LineBuilder::new().line(message).file(app);
+        if let Some(level) = record
+            .get(KEY_PRIORITY)
+            .filter(|_| self.levels)
+            .and_then(|priority| priority.parse().ok())
+            .and_then(Level::from_syslog_severity)
+        {
+            line = line.level(level.as_str());
+        }
+        set_source(&mut line, "journald");
+        Ok(Some(line))
     }
 }
 
@@ -274,7 +292,7 @@ mod tests {
     async fn reader_gets_new_logs() {
         journal::print(1, "Reader got the correct line!");
         sleep(Duration::from_millis(50));
-        let mut reader = Reader::new(Path::Directory(JOURNALD_LOG_PATH.into()));
+        let mut reader = Reader::new(Path::Directory(JOURNALD_LOG_PATH.into()), false);
 
         let record_status = reader.process_next_record();
         if let Ok(Some(line)) = record_status {
@@ -292,7 +310,7 @@ mod tests {
     async fn stream_gets_new_logs() {
         journal::print(1, "Reader got the correct line 1!");
         sleep(Duration::from_millis(50));
-        let mut stream = Stream::new(Path::Directory(JOURNALD_LOG_PATH.into()));
+        let mut stream = Stream::new(Path::Directory(JOURNALD_LOG_PATH.into()), false);
         sleep(Duration::from_millis(50));
         journal::print(1, "Reader got the correct line 2!");
 
@@ -23,6 +23,7 @@ use pin_utils::pin_mut;
 
 use serde::Serialize;
 
+use http::source::set_source;
 use http::types::body::LineBuilder;
 
 use metrics::Metrics;
@@ -216,6 +217,7 @@ impl TryFrom<EventLog> for LineBuilder {
                 if let Some(level) = &value.level {
                     line = line.level(level);
                 }
+                set_source(&mut line, "k8s_events");
                 line
             })
     }
@@ -1,10 +1,12 @@
+use std::collections::HashMap;
 use std::sync::atomic::AtomicI64;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 
 use chrono::Utc;
 use jemalloc_ctl::stats::{active, active_mib, allocated, allocated_mib, resident, resident_mib};
 use jemalloc_ctl::{epoch, epoch_mib};
-use json::object;
+use json::{object, JsonValue};
 use lazy_static::lazy_static;
 use log::info;
 use std::thread::sleep;
@@ -21,6 +23,12 @@ pub struct Metrics {
     http: Http,
     k8s: K8s,
     journald: Journald,
+    unix_socket: UnixSocket,
+    kmsg: Kmsg,
+    syslog: Syslog,
+    filter: Filter,
+    redact: Redact,
+    levels: Levels,
 }
 
 impl Metrics {
@@ -32,6 +40,12 @@ impl Metrics {
             http: Http::new(),
             k8s: K8s::new(),
             journald: Journald::new(),
+            unix_socket: UnixSocket::new(),
+            kmsg: Kmsg::new(),
+            syslog: Syslog::new(),
+            filter: Filter::new(),
+            redact: Redact::new(),
+            levels: Levels::new(),
         }
     }
 
@@ -52,6 +66,12 @@ impl Metrics {
         Metrics::http().reset();
         Metrics::k8s().reset();
         Metrics::journald().reset();
+        Metrics::unix_socket().reset();
+        Metrics::kmsg().reset();
+        Metrics::syslog().reset();
+        Metrics::filter().reset();
+        Metrics::redact().reset();
+        Metrics::levels().reset();
     }
 
     pub fn elapsed() -> u64 {
@@ -78,12 +98,42 @@ impl Metrics {
         &METRICS.journald
     }
 
+    pub fn unix_socket() -> &'static UnixSocket {
+        &METRICS.unix_socket
+    }
+
+    pub fn kmsg() -> &'static Kmsg {
+        &METRICS.kmsg
+    }
+
+    pub fn syslog() -> &'static Syslog {
+        &METRICS.syslog
+    }
+
+    pub fn filter() -> &'static Filter {
+        &METRICS.filter
+    }
+
+    pub fn redact() -> &'static Redact {
+        &METRICS.redact
+    }
+
+    pub fn levels() -> &'static Levels {
+        &METRICS.levels
+    }
+
     pub fn print() -> String {
         let fs = Metrics::fs();
         let memory = Metrics::memory();
         let http = Metrics::http();
         let k8s = Metrics::k8s();
         let journald = Metrics::journald();
+        let unix_socket = Metrics::unix_socket();
+        let kmsg = Metrics::kmsg();
+        let syslog = Metrics::syslog();
+        let filter = Metrics::filter();
+        let redact = Metrics::redact();
+        let levels = Metrics::levels();
 
         let object = object! {
             "fs" => object!{
@@ -94,6 +144,14 @@ impl Metrics {
                 "lines" => fs.read_lines(),
                 "bytes" => fs.read_bytes(),
                 "partial_reads" => fs.read_partial_reads(),
+                "rotations" => fs.read_rotations(),
+                "truncations" => fs.read_truncations(),
+                "replacements" => fs.read_replacements(),
+                "decompressed_files" => fs.read_decompressed_files(),
+                "overflows" => fs.read_overflows(),
+                "exhausted_watches" => fs.read_exhausted_watches(),
+                "deferred_reads" => fs.read_deferred_reads(),
+                "failed_reopens" => fs.read_failed_reopens(),
             },
             "memory" => object!{
                 "active" => memory.read_active(),
@@ -118,6 +176,32 @@ impl Metrics {
                 "lines" => journald.read_lines(),
                 "bytes" => journald.read_bytes(),
             },
+            "unix_socket" => object!{
+                "lines" => unix_socket.read_lines(),
+                "bytes" => unix_socket.read_bytes(),
+                "connections" => unix_socket.read_connections(),
+            },
+            "kmsg" => object!{
+                "lines" => kmsg.read_lines(),
+                "bytes" => kmsg.read_bytes(),
+                "overwritten" => kmsg.read_overwritten(),
+            },
+            "syslog" => object!{
+                "lines" => syslog.read_lines(),
+                "bytes" => syslog.read_bytes(),
+                "connections" => syslog.read_connections(),
+                "malformed" => syslog.read_malformed(),
+            },
+            "filter" => object!{
+                "dropped" => to_json(filter.read_dropped()),
+            },
+            "redact" => object!{
+                "redactions" => to_json(redact.read_redactions()),
+                "dropped" => to_json(redact.read_dropped()),
+            },
+            "levels" => object!{
+                "dropped" => to_json(levels.read_dropped()),
+            },
         };
 
         object.to_string()
@@ -133,6 +217,14 @@ pub struct Fs {
     lines: AtomicU64,
     bytes: AtomicU64,
     partial_reads: AtomicU64,
+    rotations: AtomicU64,
+    truncations: AtomicU64,
+    replacements: AtomicU64,
+    decompressed_files: AtomicU64,
+    overflows: AtomicU64,
+    exhausted_watches: AtomicU64,
+    deferred_reads: AtomicU64,
+    failed_reopens: AtomicU64,
 }
 
 impl Fs {
@@ -145,6 +237,14 @@ impl Fs {
             lines: AtomicU64::new(0),
             bytes: AtomicU64::new(0),
             partial_reads: AtomicU64::new(0),
+            rotations: AtomicU64::new(0),
+            truncations: AtomicU64::new(0),
+            replacements: AtomicU64::new(0),
+            decompressed_files: AtomicU64::new(0),
+            overflows: AtomicU64::new(0),
+            exhausted_watches: AtomicU64::new(0),
+            deferred_reads: AtomicU64::new(0),
+            failed_reopens: AtomicU64::new(0),
         }
     }
 
@@ -156,6 +256,14 @@ impl Fs {
         self.lines.store(0, Ordering::Relaxed);
         self.bytes.store(0, Ordering::Relaxed);
         self.partial_reads.store(0, Ordering::Relaxed);
+        self.rotations.store(0, Ordering::Relaxed);
+        self.truncations.store(0, Ordering::Relaxed);
+        self.replacements.store(0, Ordering::Relaxed);
+        self.decompressed_files.store(0, Ordering::Relaxed);
+        self.overflows.store(0, Ordering::Relaxed);
+        self.exhausted_watches.store(0, Ordering::Relaxed);
+        self.deferred_reads.store(0, Ordering::Relaxed);
+        self.failed_reopens.store(0, Ordering::Relaxed);
     }
 
     pub fn increment_events(&self) {
@@ -213,6 +321,70 @@ impl Fs {
     pub fn read_partial_reads(&self) -> u64 {
         self.partial_reads.load(Ordering::Relaxed)
     }
+
+    pub fn increment_rotations(&self) {
+        self.rotations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_rotations(&self) -> u64 {
+        self.rotations.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_truncations(&self) {
+        self.truncations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_truncations(&self) -> u64 {
+        self.truncations.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_replacements(&self) {
+        self.replacements.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_replacements(&self) -> u64 {
+        self.replacements.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_decompressed_files(&self) {
+        self.decompressed_files.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_decompressed_files(&self) -> u64 {
+        self.decompressed_files.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_overflows(&self) {
+        self.overflows.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_overflows(&self) -> u64 {
+        self.overflows.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_exhausted_watches(&self) {
+        self.exhausted_watches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_exhausted_watches(&self) -> u64 {
+        self.exhausted_watches.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_deferred_reads(&self) {
+        self.deferred_reads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_deferred_reads(&self) -> u64 {
+        self.deferred_reads.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_failed_reopens(&self) {
+        self.failed_reopens.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_failed_reopens(&self) -> u64 {
+        self.failed_reopens.load(Ordering::Relaxed)
+    }
 }
 
 pub struct Memory {
@@ -429,3 +601,256 @@ impl Journald {
         self.bytes.load(Ordering::Relaxed)
     }
 }
+
+#[derive(Default)]
+pub struct UnixSocket {
+    lines: AtomicU64,
+    bytes: AtomicU64,
+    connections: AtomicU64,
+}
+
+impl UnixSocket {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reset(&self) {
+        self.lines.store(0, Ordering::Relaxed);
+        self.bytes.store(0, Ordering::Relaxed);
+        self.connections.store(0, Ordering::Relaxed);
+    }
+
+    pub fn increment_lines(&self) {
+        self.lines.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_lines(&self) -> u64 {
+        self.lines.load(Ordering::Relaxed)
+    }
+
+    pub fn add_bytes(&self, num: u64) {
+        self.bytes.fetch_add(num, Ordering::Relaxed);
+    }
+
+    pub fn read_bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_connections(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_connections(&self) -> u64 {
+        self.connections.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub struct Kmsg {
+    lines: AtomicU64,
+    bytes: AtomicU64,
+    // the times records were overwritten before they were read
+    overwritten: AtomicU64,
+}
+
+impl Kmsg {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reset(&self) {
+        self.lines.store(0, Ordering::Relaxed);
+        self.bytes.store(0, Ordering::Relaxed);
+        self.overwritten.store(0, Ordering::Relaxed);
+    }
+
+    pub fn increment_lines(&self) {
+        self.lines.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_lines(&self) -> u64 {
+        self.lines.load(Ordering::Relaxed)
+    }
+
+    pub fn add_bytes(&self, num: u64) {
+        self.bytes.fetch_add(num, Ordering::Relaxed);
+    }
+
+    pub fn read_bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_overwritten(&self) {
+        self.overwritten.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_overwritten(&self) -> u64 {
+        self.overwritten.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub struct Syslog {
+    lines: AtomicU64,
+    bytes: AtomicU64,
+    connections: AtomicU64,
+    malformed: AtomicU64,
+}
+
+impl Syslog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reset(&self) {
+        self.lines.store(0, Ordering::Relaxed);
+        self.bytes.store(0, Ordering::Relaxed);
+        self.connections.store(0, Ordering::Relaxed);
+        self.malformed.store(0, Ordering::Relaxed);
+    }
+
+    pub fn increment_lines(&self) {
+        self.lines.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_lines(&self) -> u64 {
+        self.lines.load(Ordering::Relaxed)
+    }
+
+    pub fn add_bytes(&self, num: u64) {
+        self.bytes.fetch_add(num, Ordering::Relaxed);
+    }
+
+    pub fn read_bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_connections(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_connections(&self) -> u64 {
+        self.connections.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_malformed(&self) {
+        self.malformed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_malformed(&self) -> u64 {
+        self.malformed.load(Ordering::Relaxed)
+    }
+}
+
+// counters that are created the first time something is counted under their name
+#[derive(Default)]
+struct NamedCounters(Mutex<HashMap<String, u64>>);
+
+impl NamedCounters {
+    fn reset(&self) {
+        self.0.lock().expect("Couldn't lock counters").clear();
+    }
+
+    fn add(&self, name: &str, num: u64) {
+        *self
+            .0
+            .lock()
+            .expect("Couldn't lock counters")
+            .entry(name.to_string())
+            .or_insert(0) += num;
+    }
+
+    fn read(&self) -> HashMap<String, u64> {
+        self.0.lock().expect("Couldn't lock counters").clone()
+    }
+}
+
+fn to_json(counters: HashMap<String, u64>) -> JsonValue {
+    let mut object = JsonValue::new_object();
+    for (name, num) in counters {
+        object[name.as_str()] = num.into();
+    }
+    object
+}
+
+#[derive(Default)]
+pub struct Filter {
+    // the lines dropped by each line filter rule
+    dropped: NamedCounters,
+}
+
+impl Filter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reset(&self) {
+        self.dropped.reset();
+    }
+
+    pub fn add_dropped(&self, rule: &str, num: u64) {
+        self.dropped.add(rule, num);
+    }
+
+    pub fn read_dropped(&self) -> HashMap<String, u64> {
+        self.dropped.read()
+    }
+}
+
+#[derive(Default)]
+pub struct Redact {
+    // the data redacted and lines dropped by each detector
+    redactions: NamedCounters,
+    dropped: NamedCounters,
+}
+
+impl Redact {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reset(&self) {
+        self.redactions.reset();
+        self.dropped.reset();
+    }
+
+    pub fn add_redactions(&self, detector: &str, num: u64) {
+        self.redactions.add(detector, num);
+    }
+
+    pub fn read_redactions(&self) -> HashMap<String, u64> {
+        self.redactions.read()
+    }
+
+    pub fn add_dropped(&self, detector: &str, num: u64) {
+        self.dropped.add(detector, num);
+    }
+
+    pub fn read_dropped(&self) -> HashMap<String, u64> {
+        self.dropped.read()
+    }
+}
+
+#[derive(Default)]
+pub struct Levels {
+    // the lines dropped below the minimum level, by their level
+    dropped: NamedCounters,
+}
+
+impl Levels {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reset(&self) {
+        self.dropped.reset();
+    }
+
+    pub fn add_dropped(&self, level: &str, num: u64) {
+        self.dropped.add(level, num);
+    }
+
+    pub fn read_dropped(&self) -> HashMap<String, u64> {
+        self.dropped.read()
+    }
+}
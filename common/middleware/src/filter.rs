@@ -0,0 +1,161 @@
+use crate::scope::Scope;
+use crate::{Middleware, Status};
+use http::types::body::LineBuilder;
+use metrics::Metrics;
+use pcre2::bytes::Regex;
+use std::collections::HashMap;
+
+/// Drops the lines in it's scope that match none of the inclusion regexes, if there are any,
+/// or match one of the exclusion regexes
+#[derive(Debug)]
+pub struct LineFilterRule {
+    /// The name the lines dropped by the rule are counted under in the metrics
+    pub name: String,
+    pub include: Vec<Regex>,
+    pub exclude: Vec<Regex>,
+    /// The lines the rule applies to
+    pub scope: Scope,
+}
+
+impl LineFilterRule {
+    /// Creates a rule that doesn't drop anything yet
+    pub fn new<T: Into<String>>(name: T) -> Self {
+        Self {
+            name: name.into(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            scope: Scope::default(),
+        }
+    }
+
+    fn drops(&self, line: &LineBuilder) -> bool {
+        let body = match line.line {
+            Some(ref body) => body,
+            None => return false,
+        };
+        self.scope.in_scope(line)
+            && ((!self.include.is_empty() && !any_match(&self.include, body))
+                || any_match(&self.exclude, body))
+    }
+}
+
+fn any_match(regexes: &[Regex], value: &str) -> bool {
+    regexes
+        .iter()
+        .any(|regex| regex.is_match(value.as_bytes()).unwrap_or(false))
+}
+
+/// Middleware dropping lines by their content, a line is dropped by the first rule that drops
+/// it and counted under it's name
+#[derive(Debug, Default)]
+pub struct LineFilter {
+    rules: Vec<LineFilterRule>,
+}
+
+impl LineFilter {
+    pub fn new(rules: Vec<LineFilterRule>) -> Self {
+        Self { rules }
+    }
+}
+
+impl Middleware for LineFilter {
+    fn run(&self) {}
+
+    fn process(&self, mut lines: Vec<LineBuilder>) -> Status {
+        let mut dropped: HashMap<usize, u64> = HashMap::new();
+        lines.retain(
+            |line| match self.rules.iter().position(|rule| rule.drops(line)) {
+                Some(idx) => {
+                    *dropped.entry(idx).or_insert(0) += 1;
+                    false
+                }
+                None => true,
+            },
+        );
+
+        for (idx, num) in dropped {
+            Metrics::filter().add_dropped(&self.rules[idx].name, num);
+        }
+
+        if lines.is_empty() {
+            Status::Skip
+        } else {
+            Status::Ok(lines)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fs::rule::{GlobRule, Rules};
+
+    fn lines(filter: &LineFilter, lines: Vec<LineBuilder>) -> Vec<String> {
+        match filter.process(lines) {
+            Status::Ok(lines) => lines.into_iter().filter_map(|line| line.line).collect(),
+            Status::Skip => Vec::new(),
+        }
+    }
+
+    fn journald_line(body: &str) -> LineBuilder {
+        let mut line = LineBuilder::new().line(body);
+        http::source::set_source(&mut line, "journald");
+        line
+    }
+
+    #[test]
+    fn filter_lines() {
+        let mut health_checks = LineFilterRule::new("health_checks");
+        health_checks.exclude = vec![Regex::new("GET /healthz").unwrap()];
+        let mut files = Rules::new();
+        files.add_inclusion(GlobRule::new("/var/log/nginx/*.log").unwrap());
+        health_checks.scope.files = Some(files);
+
+        let mut api_errors = LineFilterRule::new("api_errors");
+        api_errors.include = vec![Regex::new("ERROR|WARN").unwrap()];
+        api_errors.scope.apps = vec![Regex::new("^api$").unwrap()];
+
+        let mut journald_debug = LineFilterRule::new("journald_debug");
+        journald_debug.exclude = vec![Regex::new("^DEBUG").unwrap()];
+        journald_debug.scope.sources = vec!["journald".to_string()];
+
+        let filter = LineFilter::new(vec![health_checks, api_errors, journald_debug]);
+        let kept = lines(
+            &filter,
+            vec![
+                LineBuilder::new()
+                    .line("GET /healthz 200")
+                    .file("/var/log/nginx/access.log"),
+                LineBuilder::new()
+                    .line("GET /index.html 200")
+                    .file("/var/log/nginx/access.log"),
+                // out of the scope of the health checks
+                LineBuilder::new()
+                    .line("GET /healthz 200")
+                    .file("/var/log/app.log"),
+                LineBuilder::new().line("INFO started").app("api"),
+                LineBuilder::new().line("ERROR failed").app("api"),
+                LineBuilder::new().line("INFO started").app("web"),
+                journald_line("DEBUG probing"),
+                LineBuilder::new().line("DEBUG probing"),
+            ],
+        );
+        assert_eq!(
+            kept,
+            vec![
+                "GET /index.html 200",
+                "GET /healthz 200",
+                "ERROR failed",
+                "INFO started",
+                "DEBUG probing"
+            ]
+        );
+
+        let only_health_checks = vec![LineBuilder::new()
+            .line("GET /healthz 200")
+            .file("/var/log/nginx/access.log")];
+        assert!(matches!(filter.process(only_health_checks), Status::Skip));
+        assert!(Metrics::filter().read_dropped()["health_checks"] >= 2);
+        assert!(Metrics::filter().read_dropped()["api_errors"] >= 1);
+    }
+}
@@ -0,0 +1,225 @@
+use crate::{Middleware, Status};
+use chrono::{DateTime, TimeZone, Utc};
+use http::timestamp::set_timestamp;
+use http::types::body::LineBuilder;
+use serde_json::{Map, Value};
+
+/// The size in bytes up to which lines are parsed as JSON
+pub const DEFAULT_MAX_BYTES: usize = 64 * 1024;
+/// The depth up to which objects and arrays may be nested in JSON lines
+pub const DEFAULT_MAX_DEPTH: usize = 16;
+
+/// Decides which JSON lines are parsed and which of their keys are promoted to line fields
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonOptions {
+    pub max_bytes: usize,
+    pub max_depth: usize,
+    /// The keys the level of a line is taken from, the first one that is set is promoted
+    pub level_keys: Vec<String>,
+    /// The keys the message replacing the line is taken from
+    pub message_keys: Vec<String>,
+    /// The keys the timestamp of a line is taken from, either RFC 3339 or seconds or
+    /// milliseconds since the epoch
+    pub timestamp_keys: Vec<String>,
+    /// The keys the app of a line is taken from
+    pub app_keys: Vec<String>,
+}
+
+impl Default for JsonOptions {
+    fn default() -> Self {
+        let keys = |keys: &[&str]| keys.iter().map(|key| key.to_string()).collect();
+        Self {
+            max_bytes: DEFAULT_MAX_BYTES,
+            max_depth: DEFAULT_MAX_DEPTH,
+            level_keys: keys(&["level", "severity"]),
+            message_keys: keys(&["msg", "message"]),
+            timestamp_keys: keys(&["timestamp", "time", "ts", "@timestamp"]),
+            app_keys: keys(&["app"]),
+        }
+    }
+}
+
+/// Middleware moving the objects of JSON lines into their meta, well-known keys are promoted
+/// to the line fields instead
+#[derive(Debug, Default)]
+pub struct JsonParser {
+    options: JsonOptions,
+}
+
+impl JsonParser {
+    pub fn new(options: JsonOptions) -> Self {
+        Self { options }
+    }
+
+    // returns the object of a JSON line, unless it's too large or deeply nested
+    fn parse(&self, line: &str) -> Option<Map<String, Value>> {
+        let line = line.trim();
+        if line.len() > self.options.max_bytes || !(line.starts_with('{') && line.ends_with('}')) {
+            return None;
+        }
+        match serde_json::from_str(line) {
+            Ok(Value::Object(object)) if depth(&object) <= self.options.max_depth => Some(object),
+            _ => None,
+        }
+    }
+
+    fn process_line(&self, line: &mut LineBuilder) {
+        let mut object = match line.line.as_deref().and_then(|body| self.parse(body)) {
+            Some(v) => v,
+            None => return,
+        };
+
+        if let Some(level) = promote(&mut object, &self.options.level_keys, string) {
+            line.level = Some(level);
+        }
+        if let Some(app) = promote(&mut object, &self.options.app_keys, string) {
+            line.app = Some(app);
+        }
+        if let Some(timestamp) = promote(&mut object, &self.options.timestamp_keys, parse_timestamp)
+        {
+            set_timestamp(line, timestamp);
+        }
+        // the line is left as it is if there's no message to replace it with
+        if let Some(message) = promote(&mut object, &self.options.message_keys, |value| {
+            string(value).filter(|message| !message.is_empty())
+        }) {
+            line.line = Some(message);
+        }
+
+        // the meta already assigned to the line takes precedence over the keys of the line
+        let mut meta = match line.meta.take() {
+            Some(Value::Object(meta)) => meta,
+            _ => Map::new(),
+        };
+        for (key, value) in object {
+            meta.entry(key).or_insert(value);
+        }
+        line.meta = Some(Value::Object(meta));
+    }
+}
+
+impl Middleware for JsonParser {
+    fn run(&self) {}
+
+    fn process(&self, mut lines: Vec<LineBuilder>) -> Status {
+        for line in lines.iter_mut() {
+            self.process_line(line);
+        }
+        Status::Ok(lines)
+    }
+}
+
+// removes the first of the keys with a value the conversion accepts from the object, returning
+// the converted value
+fn promote<T>(
+    object: &mut Map<String, Value>,
+    keys: &[String],
+    convert: impl Fn(&Value) -> Option<T>,
+) -> Option<T> {
+    let (key, value) = keys
+        .iter()
+        .find_map(|key| Some((key, convert(object.get(key)?)?)))?;
+    object.remove(key);
+    Some(value)
+}
+
+fn string(value: &Value) -> Option<String> {
+    value.as_str().map(str::to_string)
+}
+
+fn parse_timestamp(value: &Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::String(timestamp) => DateTime::parse_from_rfc3339(timestamp)
+            .ok()
+            .map(|timestamp| timestamp.with_timezone(&Utc)),
+        // timestamps before 1973 in milliseconds are mistaken for seconds
+        Value::Number(number) => match number.as_f64()? {
+            millis if millis.abs() >= 1e11 => Utc.timestamp_millis_opt(millis as i64).single(),
+            secs => Utc.timestamp_millis_opt((secs * 1000.0) as i64).single(),
+        },
+        _ => None,
+    }
+}
+
+// returns the depth of the values nested in an object, an object of scalars has a depth of 1
+fn depth(object: &Map<String, Value>) -> usize {
+    fn value_depth(value: &Value) -> usize {
+        match value {
+            Value::Object(object) => depth(object),
+            Value::Array(values) => 1 + values.iter().map(value_depth).max().unwrap_or(0),
+            _ => 0,
+        }
+    }
+    1 + object.values().map(value_depth).max().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use http::timestamp::get_timestamp;
+    use serde_json::json;
+
+    fn process(parser: &JsonParser, line: LineBuilder) -> LineBuilder {
+        match parser.process(vec![line]) {
+            Status::Ok(mut lines) => lines.pop().unwrap(),
+            Status::Skip => panic!("line was skipped"),
+        }
+    }
+
+    #[test]
+    fn parse_json_lines() {
+        let parser = JsonParser::default();
+        let line = process(
+            &parser,
+            LineBuilder::new()
+                .line(r#"{"level":"warn","msg":"disk full","ts":1600000000.5,"app":"db","disk":{"free":0}}"#)
+                .meta(json!({"team": "storage", "disk": "configured"})),
+        );
+        assert_eq!(line.line, Some("disk full".to_string()));
+        assert_eq!(line.level, Some("warn".to_string()));
+        assert_eq!(line.app, Some("db".to_string()));
+        assert_eq!(get_timestamp(&line), Some(1_600_000_000_500));
+        assert_eq!(
+            line.meta.unwrap().as_object().unwrap().get("disk"),
+            Some(&json!("configured"))
+        );
+
+        // lines without a message are kept as they are
+        let body = r#"{"@timestamp":"2020-09-13T12:26:40Z","status":200}"#;
+        let line = process(&parser, LineBuilder::new().line(body));
+        assert_eq!(line.line, Some(body.to_string()));
+        assert_eq!(get_timestamp(&line), Some(1_600_000_000_000));
+        assert_eq!(line.meta.unwrap().get("status"), Some(&json!(200)));
+
+        for body in &["not json", "{\"broken\":", "[1, 2]"] {
+            let line = process(&parser, LineBuilder::new().line(*body));
+            assert_eq!(line.line, Some(body.to_string()));
+            assert_eq!(line.meta, None);
+        }
+    }
+
+    #[test]
+    fn limits_and_key_mappings() {
+        let parser = JsonParser::new(JsonOptions {
+            max_bytes: 64,
+            max_depth: 2,
+            message_keys: vec!["text".to_string()],
+            ..JsonOptions::default()
+        });
+
+        let line = process(
+            &parser,
+            LineBuilder::new().line(r#"{"text":"a","msg":"b"}"#),
+        );
+        assert_eq!(line.line, Some("a".to_string()));
+        assert_eq!(line.meta, Some(json!({"msg": "b"})));
+
+        let nested = r#"{"text":"a","b":{"c":{"d":1}}}"#;
+        let line = process(&parser, LineBuilder::new().line(nested));
+        assert_eq!(line.line, Some(nested.to_string()));
+
+        let large = format!(r#"{{"text":"{}"}}"#, "a".repeat(64));
+        let line = process(&parser, LineBuilder::new().line(large.clone()));
+        assert_eq!(line.line, Some(large));
+    }
+}
@@ -0,0 +1,314 @@
+use crate::scope::Scope;
+use crate::{Middleware, Status};
+use http::types::body::LineBuilder;
+use metrics::Metrics;
+use pcre2::bytes::Regex;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+#[error("Unknown level: {0}")]
+pub struct ParseLevelError(String);
+
+/// The severity of a line, from the least to the most severe
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Notice,
+    Warn,
+    Error,
+    Critical,
+    Alert,
+    Emergency,
+}
+
+impl Level {
+    /// Returns the level of a syslog severity, e.g. of a journald `PRIORITY`
+    pub fn from_syslog_severity(severity: u8) -> Option<Self> {
+        Some(match severity {
+            0 => Level::Emergency,
+            1 => Level::Alert,
+            2 => Level::Critical,
+            3 => Level::Error,
+            4 => Level::Warn,
+            5 => Level::Notice,
+            6 => Level::Info,
+            7 => Level::Debug,
+            _ => return None,
+        })
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Trace => "TRACE",
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Notice => "NOTICE",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+            Level::Critical => "CRITICAL",
+            Level::Alert => "ALERT",
+            Level::Emergency => "EMERGENCY",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Level {
+    type Err = ParseLevelError;
+
+    /// Parses the name of a level or one of it's common abbreviations, ignoring case
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "trace" => Ok(Level::Trace),
+            "debug" | "dbg" => Ok(Level::Debug),
+            "info" | "information" => Ok(Level::Info),
+            "notice" => Ok(Level::Notice),
+            "warn" | "warning" => Ok(Level::Warn),
+            "error" | "err" => Ok(Level::Error),
+            "critical" | "crit" | "fatal" => Ok(Level::Critical),
+            "alert" => Ok(Level::Alert),
+            "emergency" | "emerg" | "panic" => Ok(Level::Emergency),
+            _ => Err(ParseLevelError(s.into())),
+        }
+    }
+}
+
+/// Drops the lines in it's scope below a minimum level
+#[derive(Debug)]
+pub struct MinLevelRule {
+    pub min_level: Level,
+    /// The lines the rule applies to
+    pub scope: Scope,
+}
+
+impl MinLevelRule {
+    pub fn new(min_level: Level) -> Self {
+        Self {
+            min_level,
+            scope: Scope::default(),
+        }
+    }
+}
+
+// the patterns levels are detected with, in order
+#[derive(Debug)]
+struct Patterns {
+    // `<13>` at the start of syslog messages, the severity is the priority modulo 8
+    syslog_pri: Regex,
+    // `E0102 15:04:05.999999` at the start of klog lines
+    klog: Regex,
+    // `[ERROR]`
+    bracketed: Regex,
+    // `level=warn` in logfmt lines
+    logfmt: Regex,
+}
+
+impl Patterns {
+    fn new() -> Self {
+        let regex = |pattern: &str| Regex::new(pattern).expect("level regexes are valid");
+        let names = concat!(
+            "trace|debug|dbg|info|information|notice|warn|warning|error|err|",
+            "critical|crit|fatal|alert|emergency|emerg|panic"
+        );
+        Self {
+            syslog_pri: regex(r"^<(\d{1,3})>"),
+            klog: regex(r"^([IWEF])\d{4} \d\d:\d\d:\d\d"),
+            bracketed: regex(&format!(r"(?i)\[({})\]", names)),
+            logfmt: regex(&format!(
+                r#"(?i)(?<![\w.])(?:level|lvl|severity)=["']?({})\b"#,
+                names
+            )),
+        }
+    }
+
+    fn detect(&self, line: &str) -> Option<Level> {
+        let group = |regex: &Regex| {
+            let captures = regex.captures(line.as_bytes()).ok()??;
+            let group = captures.get(1)?;
+            line.get(group.start()..group.end())
+        };
+        if let Some(pri) = group(&self.syslog_pri) {
+            return Level::from_syslog_severity(pri.parse::<u8>().ok()? % 8);
+        }
+        if let Some(klog) = group(&self.klog) {
+            return Some(match klog {
+                "I" => Level::Info,
+                "W" => Level::Warn,
+                "E" => Level::Error,
+                _ => Level::Critical,
+            });
+        }
+        group(&self.bracketed)
+            .or_else(|| group(&self.logfmt))
+            .and_then(|name| name.parse().ok())
+    }
+}
+
+/// Middleware detecting the level of lines without one and dropping lines below a minimum
+/// level, lines without a level are never dropped
+#[derive(Debug)]
+pub struct Levels {
+    patterns: Option<Patterns>,
+    min_level: Option<Level>,
+    rules: Vec<MinLevelRule>,
+}
+
+impl Levels {
+    /// Creates the middleware, the first rule in the scope of a line decides it's minimum level
+    /// and the `min_level` applies to the lines in the scope of none
+    pub fn new(detect: bool, min_level: Option<Level>, rules: Vec<MinLevelRule>) -> Self {
+        Self {
+            patterns: if detect { Some(Patterns::new()) } else { None },
+            min_level,
+            rules,
+        }
+    }
+}
+
+impl Middleware for Levels {
+    fn run(&self) {}
+
+    fn process(&self, mut lines: Vec<LineBuilder>) -> Status {
+        if let Some(ref patterns) = self.patterns {
+            for line in lines.iter_mut().filter(|line| line.level.is_none()) {
+                if let Some(level) = line.line.as_deref().and_then(|body| patterns.detect(body)) {
+                    line.level = Some(level.to_string());
+                }
+            }
+        }
+
+        if self.min_level.is_none() && self.rules.is_empty() {
+            return Status::Ok(lines);
+        }
+        let mut dropped: HashMap<Level, u64> = HashMap::new();
+        lines.retain(|line| {
+            let level = match line.level.as_deref().map(str::parse::<Level>) {
+                Some(Ok(level)) => level,
+                _ => return true,
+            };
+            let keep = match self.rules.iter().find(|rule| rule.scope.in_scope(line)) {
+                Some(rule) => level >= rule.min_level,
+                None => self.min_level.iter().all(|min_level| level >= *min_level),
+            };
+            if !keep {
+                *dropped.entry(level).or_insert(0) += 1;
+            }
+            keep
+        });
+        for (level, num) in dropped {
+            Metrics::levels().add_dropped(level.as_str(), num);
+        }
+
+        if lines.is_empty() {
+            Status::Skip
+        } else {
+            Status::Ok(lines)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fs::rule::{GlobRule, Rules};
+
+    #[test]
+    fn detect_levels() {
+        let patterns = Patterns::new();
+        let cases = vec![
+            ("<11>Jan  2 15:04:05 host app: failed", Some(Level::Error)),
+            (
+                "<165>1 2020-01-02T15:04:05Z host app - - - notice",
+                Some(Level::Notice),
+            ),
+            (
+                "E0102 15:04:05.999999    1 reflector.go:138] failed",
+                Some(Level::Error),
+            ),
+            (
+                "W0102 15:04:05.999999    1 main.go:10] slow",
+                Some(Level::Warn),
+            ),
+            (
+                "2020-01-02 15:04:05 [warning] disk almost full",
+                Some(Level::Warn),
+            ),
+            (
+                "time=2020-01-02 level=debug msg=\"polling\"",
+                Some(Level::Debug),
+            ),
+            ("lvl=\"FATAL\" msg=crashed", Some(Level::Critical)),
+            ("Info about the error of the day", None),
+            ("[2020-01-02] started", None),
+        ];
+        for (line, level) in cases {
+            assert_eq!(patterns.detect(line), level, "{}", line);
+        }
+    }
+
+    fn kernel_line(body: &str) -> LineBuilder {
+        let mut line = LineBuilder::new().line(body);
+        http::source::set_source(&mut line, "kmsg");
+        line
+    }
+
+    #[test]
+    fn filter_levels() {
+        let mut nginx = MinLevelRule::new(Level::Error);
+        let mut files = Rules::new();
+        files.add_inclusion(GlobRule::new("/var/log/nginx/*.log").unwrap());
+        nginx.scope.files = Some(files);
+
+        let mut kernel = MinLevelRule::new(Level::Warn);
+        kernel.scope.sources = vec!["kmsg".to_string()];
+
+        let levels = Levels::new(true, Some(Level::Info), vec![nginx, kernel]);
+        let lines = match levels.process(vec![
+            LineBuilder::new().line("[DEBUG] polling"),
+            LineBuilder::new().line("[INFO] started"),
+            LineBuilder::new()
+                .line("[warn] slow upstream")
+                .file("/var/log/nginx/error.log"),
+            LineBuilder::new()
+                .line("[error] upstream failed")
+                .file("/var/log/nginx/error.log"),
+            LineBuilder::new().line("no level"),
+            LineBuilder::new()
+                .line("[DEBUG] set by the source")
+                .level("Warning"),
+            kernel_line("[NOTICE] link up"),
+            kernel_line("[ERROR] link down"),
+        ]) {
+            Status::Ok(lines) => lines,
+            Status::Skip => panic!("lines were skipped"),
+        };
+        let levels = lines
+            .iter()
+            .map(|line| line.level.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            levels,
+            vec![
+                Some("INFO"),
+                Some("ERROR"),
+                None,
+                Some("Warning"),
+                Some("ERROR")
+            ]
+        );
+        let dropped = Metrics::levels().read_dropped();
+        assert!(dropped["DEBUG"] >= 1);
+        assert!(dropped["WARN"] >= 1);
+        assert!(dropped["NOTICE"] >= 1);
+    }
+}
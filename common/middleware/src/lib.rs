@@ -3,6 +3,21 @@ use std::sync::Arc;
 use http::types::body::LineBuilder;
 use std::thread::spawn;
 
+/// Keeps or drops lines by their content
+pub mod filter;
+/// Parses JSON lines into their meta
+pub mod json;
+/// Detects the level of lines and drops lines below a minimum level
+pub mod level;
+/// Assigns metadata to the lines of files by their path
+pub mod metadata;
+/// Redacts sensitive data in lines
+pub mod redact;
+/// Limits rules to lines by their file, app and source
+pub mod scope;
+/// Extracts the timestamps of lines from their content
+pub mod timestamp;
+
 pub enum Status {
     Ok(Vec<LineBuilder>),
     Skip,
@@ -0,0 +1,252 @@
+use crate::{Middleware, Status};
+use fs::rule::Rules;
+use http::types::body::LineBuilder;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Assigns metadata to the lines of the files matching rules
+///
+/// The values may contain placeholders like `${app}`, which are replaced with the named groups
+/// the regexes of the rules capture from the path of the file, e.g. `(?P<app>[^/]+)`.
+#[derive(Debug)]
+pub struct MetadataRule {
+    pub rules: Rules,
+    pub app: Option<String>,
+    pub tags: Vec<String>,
+    /// The level of lines that don't have one yet
+    pub level: Option<String>,
+    pub env: Option<String>,
+    pub meta: Vec<(String, String)>,
+}
+
+impl MetadataRule {
+    /// Creates a rule that doesn't assign anything yet
+    pub fn new(rules: Rules) -> Self {
+        Self {
+            rules,
+            app: None,
+            tags: Vec::new(),
+            level: None,
+            env: None,
+            meta: Vec::new(),
+        }
+    }
+}
+
+// the metadata assigned to the lines of a path
+#[derive(Debug, Default, PartialEq)]
+struct Assignment {
+    app: Option<String>,
+    tags: Vec<String>,
+    level: Option<String>,
+    env: Option<String>,
+    meta: Map<String, Value>,
+}
+
+impl Assignment {
+    fn for_path(rules: &[MetadataRule], path: &PathBuf) -> Self {
+        let mut assignment = Assignment::default();
+        for rule in rules.iter() {
+            if !rule.rules.passes(path).is_ok() {
+                continue;
+            }
+
+            let captures = rule.rules.captures(path);
+            let expand = |value: &String| expand(value, &captures);
+            // values that are empty once expanded aren't assigned
+            let non_empty = |value: String| Some(value).filter(|value| !value.is_empty());
+            if let Some(app) = rule.app.as_ref().map(expand).and_then(non_empty) {
+                assignment.app = Some(app);
+            }
+            if let Some(level) = rule.level.as_ref().map(expand).and_then(non_empty) {
+                assignment.level = Some(level);
+            }
+            if let Some(env) = rule.env.as_ref().map(expand).and_then(non_empty) {
+                assignment.env = Some(env);
+            }
+            for tag in rule.tags.iter().map(expand).filter_map(non_empty) {
+                if !assignment.tags.contains(&tag) {
+                    assignment.tags.push(tag);
+                }
+            }
+            for (key, value) in rule.meta.iter() {
+                assignment
+                    .meta
+                    .insert(key.clone(), Value::String(expand(value)));
+            }
+        }
+        assignment
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == Assignment::default()
+    }
+
+    fn apply(&self, line: &mut LineBuilder) {
+        if self.app.is_some() {
+            line.app = self.app.clone();
+        }
+        if self.env.is_some() {
+            line.env = self.env.clone();
+        }
+        if line.level.is_none() {
+            line.level = self.level.clone();
+        }
+
+        if self.meta.is_empty() && self.tags.is_empty() {
+            return;
+        }
+        let mut meta = match line.meta.take() {
+            Some(Value::Object(meta)) => meta,
+            _ => Map::new(),
+        };
+        meta.extend(self.meta.clone());
+        // the ingest api only takes tags for a whole request, so the tags of a path are sent
+        // with it's lines as meta
+        if !self.tags.is_empty() {
+            meta.insert(
+                "tags".to_string(),
+                Value::Array(self.tags.iter().cloned().map(Value::String).collect()),
+            );
+        }
+        line.meta = Some(Value::Object(meta));
+    }
+}
+
+/// Middleware assigning metadata to file lines by the rules matching their path, every
+/// matching rule applies in order so later rules override the values of earlier ones
+#[derive(Debug, Default)]
+pub struct Metadata {
+    rules: Vec<MetadataRule>,
+}
+
+impl Metadata {
+    pub fn new(rules: Vec<MetadataRule>) -> Self {
+        Self { rules }
+    }
+}
+
+impl Middleware for Metadata {
+    fn run(&self) {}
+
+    fn process(&self, mut lines: Vec<LineBuilder>) -> Status {
+        // the lines of a batch mostly come from the same few files
+        let mut assignments: HashMap<String, Assignment> = HashMap::new();
+        for line in lines.iter_mut() {
+            let file = match line.file {
+                Some(ref file) => file.clone(),
+                None => continue,
+            };
+            let assignment = match assignments.get(&file) {
+                Some(v) => v,
+                None => {
+                    let assignment = Assignment::for_path(&self.rules, &PathBuf::from(&file));
+                    assignments.entry(file).or_insert(assignment)
+                }
+            };
+            if !assignment.is_empty() {
+                assignment.apply(line);
+            }
+        }
+        Status::Ok(lines)
+    }
+}
+
+// replaces the ${name} placeholders of a value with the captures of the same name, names that
+// weren't captured are replaced with nothing
+fn expand(value: &str, captures: &[(String, String)]) -> String {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        expanded.push_str(&rest[..start]);
+        let name = &rest[start + 2..end];
+        if let Some((_, capture)) = captures.iter().find(|(key, _)| key == name) {
+            expanded.push_str(capture);
+        }
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fs::rule::{GlobRule, RegexRule};
+
+    fn process(metadata: &Metadata, line: LineBuilder) -> LineBuilder {
+        match metadata.process(vec![line]) {
+            Status::Ok(mut lines) => lines.pop().unwrap(),
+            Status::Skip => panic!("line was skipped"),
+        }
+    }
+
+    #[test]
+    fn expand_placeholders() {
+        let captures = vec![("app".to_string(), "nginx".to_string())];
+        assert_eq!(expand("${app}-web", &captures), "nginx-web");
+        assert_eq!(expand("${app}/${missing}", &captures), "nginx/");
+        assert_eq!(expand("no ${placeholder", &captures), "no ${placeholder");
+    }
+
+    #[test]
+    fn assign_metadata() {
+        let mut rules = Rules::new();
+        rules.add_inclusion(RegexRule::new(r"^/var/log/(?P<app>[^/]+)/").unwrap());
+        let mut by_dir = MetadataRule::new(rules);
+        by_dir.app = Some("${app}".to_string());
+        by_dir.tags = vec!["${app}".to_string(), "web".to_string()];
+        by_dir.level = Some("INFO".to_string());
+        by_dir.meta = vec![("team".to_string(), "${app}-team".to_string())];
+
+        let mut rules = Rules::new();
+        rules.add_inclusion(GlobRule::new("*/error.log").unwrap());
+        let mut errors = MetadataRule::new(rules);
+        errors.level = Some("ERROR".to_string());
+        errors.env = Some("production".to_string());
+
+        let metadata = Metadata::new(vec![by_dir, errors]);
+
+        let line = process(
+            &metadata,
+            LineBuilder::new()
+                .line("a")
+                .file("/var/log/nginx/access.log"),
+        );
+        assert_eq!(line.app, Some("nginx".to_string()));
+        assert_eq!(line.level, Some("INFO".to_string()));
+        assert_eq!(line.env, None);
+        assert_eq!(
+            line.meta,
+            Some(serde_json::json!({"team": "nginx-team", "tags": ["nginx", "web"]}))
+        );
+
+        let line = process(
+            &metadata,
+            LineBuilder::new()
+                .line("a")
+                .file("/var/log/nginx/error.log"),
+        );
+        assert_eq!(line.level, Some("ERROR".to_string()));
+        assert_eq!(line.env, Some("production".to_string()));
+
+        // levels already set are kept
+        let line = process(
+            &metadata,
+            LineBuilder::new()
+                .line("a")
+                .level("WARN")
+                .file("/var/log/nginx/access.log"),
+        );
+        assert_eq!(line.level, Some("WARN".to_string()));
+
+        let line = process(&metadata, LineBuilder::new().line("a").file("/tmp/a.log"));
+        assert_eq!(line.app, None);
+        assert_eq!(line.meta, None);
+    }
+}
@@ -0,0 +1,44 @@
+use fs::rule::Rules;
+use http::source::get_source;
+use http::types::body::LineBuilder;
+use pcre2::bytes::Regex;
+use std::path::PathBuf;
+
+/// The lines a rule applies to by their file, app and source, an empty scope contains all lines
+#[derive(Debug, Default)]
+pub struct Scope {
+    /// Limits the scope to lines of files matching the rules
+    pub files: Option<Rules>,
+    /// Limits the scope to lines with an app matching one of the regexes
+    pub apps: Vec<Regex>,
+    /// Limits the scope to lines read from one of the sources, e.g. `journald`
+    pub sources: Vec<String>,
+}
+
+impl Scope {
+    pub fn in_scope(&self, line: &LineBuilder) -> bool {
+        if let Some(ref files) = self.files {
+            match line.file {
+                Some(ref file) if files.passes(&PathBuf::from(file)).is_ok() => {}
+                _ => return false,
+            }
+        }
+        if !self.apps.is_empty() {
+            match line.app {
+                Some(ref app)
+                    if self
+                        .apps
+                        .iter()
+                        .any(|regex| regex.is_match(app.as_bytes()).unwrap_or(false)) => {}
+                _ => return false,
+            }
+        }
+        if !self.sources.is_empty() {
+            match get_source(line) {
+                Some(source) if self.sources.iter().any(|s| s == source) => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,267 @@
+use crate::{Middleware, Status};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, TimeZone, Utc};
+use http::timestamp::{get_timestamp, set_timestamp};
+use http::types::body::LineBuilder;
+use pcre2::bytes::Regex;
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+/// How far a timestamp may be in the future before it's considered wrong
+pub const DEFAULT_MAX_SKEW: Duration = Duration::from_secs(5 * 60);
+
+const MONTHS: &str = "(?:Jan|Feb|Mar|Apr|May|Jun|Jul|Aug|Sep|Oct|Nov|Dec)";
+
+#[derive(Error, Debug)]
+pub enum ParseFormatError {
+    #[error("Unknown timestamp format: {0}")]
+    Unknown(String),
+    #[error("Invalid strptime pattern: {0}")]
+    Pattern(String),
+}
+
+/// A format timestamps are extracted from lines in
+#[derive(Debug)]
+pub enum Format {
+    /// `2006-01-02T15:04:05.999Z` anywhere in the line, a space may separate the date and time
+    /// and times without an offset are in local time
+    Rfc3339(Regex),
+    /// `Jan  2 15:04:05` anywhere in the line in local time, in the past year
+    Syslog(Regex),
+    /// The Apache common log format `02/Jan/2006:15:04:05 -0700` anywhere in the line
+    Clf(Regex),
+    /// Milliseconds since the epoch anywhere in the line
+    EpochMs(Regex),
+    /// A strptime pattern at the start of the line, optionally in brackets, times without an
+    /// offset are in local time
+    Strptime(String),
+}
+
+impl Format {
+    // returns the timestamp in a line, if it contains one in the format
+    fn extract(&self, line: &str) -> Option<DateTime<Utc>> {
+        match self {
+            Format::Rfc3339(regex) => {
+                let found = find(regex, line)?.replacen(' ', "T", 1).replace(',', ".");
+                DateTime::parse_from_rfc3339(&found)
+                    .map(|timestamp| timestamp.with_timezone(&Utc))
+                    .ok()
+                    .or_else(|| {
+                        local(NaiveDateTime::parse_from_str(&found, "%Y-%m-%dT%H:%M:%S%.f").ok()?)
+                    })
+            }
+            Format::Syslog(regex) => parse_syslog_timestamp(find(regex, line)?),
+            Format::Clf(regex) => {
+                DateTime::parse_from_str(find(regex, line)?, "%d/%b/%Y:%H:%M:%S %z")
+                    .map(|timestamp| timestamp.with_timezone(&Utc))
+                    .ok()
+            }
+            Format::EpochMs(regex) => Utc
+                .timestamp_millis_opt(find(regex, line)?.parse().ok()?)
+                .single(),
+            Format::Strptime(pattern) => {
+                // the pattern is matched against as many words of the line as it has
+                let words = pattern.split_whitespace().count();
+                let prefix = line
+                    .trim_start()
+                    .trim_start_matches('[')
+                    .split_whitespace()
+                    .take(words)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let prefix = prefix.trim_end_matches([']', ',', ':']);
+                DateTime::parse_from_str(prefix, pattern)
+                    .map(|timestamp| timestamp.with_timezone(&Utc))
+                    .ok()
+                    .or_else(|| local(NaiveDateTime::parse_from_str(prefix, pattern).ok()?))
+            }
+        }
+    }
+}
+
+/// Parses a syslog timestamp like `Jan  2 15:04:05` in local time. Syslog timestamps don't have
+/// a year, a timestamp in the future is from last year.
+pub fn parse_syslog_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    let now = Local::now();
+    let parse = |year: i32| {
+        NaiveDateTime::parse_from_str(&format!("{} {}", year, timestamp), "%Y %b %e %H:%M:%S")
+            .ok()
+            .and_then(local)
+    };
+    match parse(now.year())? {
+        parsed if parsed > now + chrono::Duration::days(1) => parse(now.year() - 1),
+        parsed => Some(parsed),
+    }
+}
+
+fn find<'a>(regex: &Regex, line: &'a str) -> Option<&'a str> {
+    let found = regex.find(line.as_bytes()).ok()??;
+    line.get(found.start()..found.end())
+}
+
+fn local(naive: NaiveDateTime) -> Option<DateTime<Utc>> {
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+}
+
+impl FromStr for Format {
+    type Err = ParseFormatError;
+
+    /// Returns the built-in format of a name, or a strptime pattern if it contains a `%`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let regex = |pattern: &str| Regex::new(pattern).expect("built-in format regexes are valid");
+        match s {
+            "rfc3339" => Ok(Format::Rfc3339(regex(
+                r"\d{4}-\d\d-\d\d[T ]\d\d:\d\d:\d\d(?:[.,]\d+)?(?:Z|[+-]\d\d:\d\d)?",
+            ))),
+            "syslog" => Ok(Format::Syslog(regex(&format!(
+                r"\b{} [ \d]\d \d\d:\d\d:\d\d\b",
+                MONTHS
+            )))),
+            "clf" => Ok(Format::Clf(regex(&format!(
+                r"\d\d/{}/\d{{4}}:\d\d:\d\d:\d\d [+-]\d{{4}}",
+                MONTHS
+            )))),
+            "epoch_ms" => Ok(Format::EpochMs(regex(r"(?<![\d.])\d{13}(?![\d.])"))),
+            pattern if pattern.contains('%') => {
+                if StrftimeItems::new(pattern).any(|item| item == Item::Error) {
+                    return Err(ParseFormatError::Pattern(pattern.into()));
+                }
+                Ok(Format::Strptime(pattern.into()))
+            }
+            _ => Err(ParseFormatError::Unknown(s.into())),
+        }
+    }
+}
+
+/// Middleware setting the timestamp of lines to the first timestamp found in them, lines that
+/// already have a timestamp are left as they are
+#[derive(Debug)]
+pub struct TimestampExtractor {
+    formats: Vec<Format>,
+    max_skew: Duration,
+}
+
+impl TimestampExtractor {
+    /// Creates an extractor trying the formats in order, timestamps more than `max_skew` in the
+    /// future are ignored so lines of hosts with a wrong clock don't show up ahead of time
+    pub fn new(formats: Vec<Format>, max_skew: Duration) -> Self {
+        Self { formats, max_skew }
+    }
+
+    fn extract(&self, line: &str) -> Option<DateTime<Utc>> {
+        let latest = Utc::now() + chrono::Duration::from_std(self.max_skew).ok()?;
+        self.formats
+            .iter()
+            .find_map(|format| format.extract(line))
+            .filter(|timestamp| *timestamp <= latest)
+    }
+}
+
+impl Middleware for TimestampExtractor {
+    fn run(&self) {}
+
+    fn process(&self, mut lines: Vec<LineBuilder>) -> Status {
+        for line in lines.iter_mut() {
+            if get_timestamp(line).is_some() {
+                continue;
+            }
+            if let Some(timestamp) = line.line.as_deref().and_then(|body| self.extract(body)) {
+                set_timestamp(line, timestamp);
+            }
+        }
+        Status::Ok(lines)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn extract(format: &str, line: &str) -> Option<i64> {
+        format
+            .parse::<Format>()
+            .unwrap()
+            .extract(line)
+            .map(|timestamp| timestamp.timestamp_millis())
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(
+            extract(
+                "rfc3339",
+                "level=info ts=2020-09-13T12:26:40.5Z msg=started"
+            ),
+            Some(1_600_000_000_500)
+        );
+        assert_eq!(
+            extract("rfc3339", "2020-09-13 14:26:40,500+02:00 INFO started"),
+            Some(1_600_000_000_500)
+        );
+        assert!(extract("syslog", "<13>Sep 13 12:26:40 host app[1]: started").is_some());
+        assert_eq!(
+            extract(
+                "clf",
+                r#"127.0.0.1 - - [13/Sep/2020:14:26:40 +0200] "GET / HTTP/1.1" 200 2326"#
+            ),
+            Some(1_600_000_000_000)
+        );
+        assert_eq!(
+            extract("epoch_ms", "took 12345 ms at 1600000000500"),
+            Some(1_600_000_000_500)
+        );
+        assert_eq!(
+            extract(
+                "%d.%m.%Y %H:%M:%S %z",
+                "[13.09.2020 14:26:40 +0200] started"
+            ),
+            Some(1_600_000_000_000)
+        );
+        assert!(extract("%d.%m.%Y %H:%M:%S", "13.09.2020 14:26:40: started").is_some());
+        assert_eq!(extract("rfc3339", "no timestamp"), None);
+
+        assert!(matches!(
+            "iso".parse::<Format>(),
+            Err(ParseFormatError::Unknown(_))
+        ));
+        assert!(matches!(
+            "%Y-%Q".parse::<Format>(),
+            Err(ParseFormatError::Pattern(_))
+        ));
+    }
+
+    #[test]
+    fn extract_timestamps() {
+        let extractor = TimestampExtractor::new(
+            vec!["clf".parse().unwrap(), "epoch_ms".parse().unwrap()],
+            DEFAULT_MAX_SKEW,
+        );
+        let future = (Utc::now() + chrono::Duration::hours(1)).timestamp_millis();
+        let mut already_set = LineBuilder::new().line("at 1600000000500");
+        set_timestamp(&mut already_set, Utc.timestamp_millis(1_500_000_000_000));
+
+        let lines = match extractor.process(vec![
+            LineBuilder::new().line("[13/Sep/2020:14:26:40 +0200] at 1600000000500"),
+            LineBuilder::new().line("at 1600000000500"),
+            LineBuilder::new().line(format!("at {}", future)),
+            already_set,
+        ]) {
+            Status::Ok(lines) => lines,
+            Status::Skip => panic!("lines were skipped"),
+        };
+        let timestamps = lines.iter().map(get_timestamp).collect::<Vec<_>>();
+        assert_eq!(
+            timestamps,
+            vec![
+                Some(1_600_000_000_000),
+                Some(1_600_000_000_500),
+                None,
+                Some(1_500_000_000_000)
+            ]
+        );
+    }
+}
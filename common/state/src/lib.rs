@@ -0,0 +1,174 @@
+#[macro_use]
+extern crate log;
+
+use std::collections::HashMap;
+use std::fs::{create_dir_all, rename, File};
+use std::hash::Hash;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use thiserror::Error;
+
+const DEFAULT_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Error)]
+pub enum StateError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("serialization error: {0}")]
+    Serde(#[from] serde_json::Error),
+}
+
+/// A key value map that is periodically checkpointed to a file so it survives agent restarts
+#[derive(Debug)]
+pub struct Store<K, V> {
+    path: PathBuf,
+    entries: HashMap<K, V>,
+    checkpoint_interval: Duration,
+    last_flush: Instant,
+    dirty: bool,
+}
+
+impl<K, V> Store<K, V>
+where
+    K: Eq + Hash + Serialize + DeserializeOwned,
+    V: PartialEq + Serialize + DeserializeOwned,
+{
+    /// Opens the store backed by the file at path, loading the entries of the last flush.
+    /// A corrupt file is discarded so the agent can still start.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StateError> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+
+        let mut entries = HashMap::new();
+        if path.exists() {
+            // serde_json only supports string keys in maps so the entries are stored as pairs
+            match serde_json::from_reader::<_, Vec<(K, V)>>(BufReader::new(File::open(&path)?)) {
+                Ok(pairs) => entries.extend(pairs),
+                Err(e) => warn!("discarding corrupt state file {:?}: {}", path, e),
+            }
+        }
+
+        info!(
+            "loaded {} entries from state file {:?}",
+            entries.len(),
+            path
+        );
+        Ok(Self {
+            path,
+            entries,
+            checkpoint_interval: DEFAULT_CHECKPOINT_INTERVAL,
+            last_flush: Instant::now(),
+            dirty: false,
+        })
+    }
+
+    pub fn set_checkpoint_interval(&mut self, interval: Duration) {
+        self.checkpoint_interval = interval;
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(key)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.entries.get(&key) != Some(&value) {
+            self.entries.insert(key, value);
+            self.dirty = true;
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let value = self.entries.remove(key);
+        self.dirty |= value.is_some();
+        value
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Flushes the store if it has changed and the checkpoint interval has elapsed
+    pub fn checkpoint(&mut self) -> Result<(), StateError> {
+        if self.dirty && self.last_flush.elapsed() >= self.checkpoint_interval {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes all entries to disk, the previous file is atomically replaced so a crash
+    /// mid write never leaves a truncated state file behind
+    pub fn flush(&mut self) -> Result<(), StateError> {
+        let tmp_path = self.path.with_extension("tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            serde_json::to_writer(&mut writer, &self.entries.iter().collect::<Vec<_>>())?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+        }
+        rename(&tmp_path, &self.path)?;
+
+        self.dirty = false;
+        self.last_flush = Instant::now();
+        debug!("flushed {} entries to {:?}", self.entries.len(), self.path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn store_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("nested").join("state.json");
+
+        let mut store = Store::<(u64, u64), u64>::open(&path).unwrap();
+        assert!(store.is_empty());
+        store.insert((1, 2), 42);
+        store.insert((3, 4), 7);
+        store.remove(&(3, 4));
+        store.flush().unwrap();
+
+        let store = Store::<(u64, u64), u64>::open(&path).unwrap();
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get(&(1, 2)), Some(&42));
+    }
+
+    #[test]
+    fn store_checkpoint_waits_for_interval() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("state.json");
+
+        let mut store = Store::<String, u64>::open(&path).unwrap();
+        store.set_checkpoint_interval(Duration::from_secs(3600));
+        store.insert("a".into(), 1);
+        store.checkpoint().unwrap();
+        assert!(!path.exists());
+
+        store.set_checkpoint_interval(Duration::from_secs(0));
+        store.checkpoint().unwrap();
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn store_discards_corrupt_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        std::fs::write(&path, b"{not json").unwrap();
+
+        let store = Store::<String, u64>::open(&path).unwrap();
+        assert!(store.is_empty());
+    }
+}
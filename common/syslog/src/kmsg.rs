@@ -0,0 +1,360 @@
+use crate::parse::FACILITIES;
+use chrono::{DateTime, Utc};
+use futures::channel::mpsc::{channel, Receiver, Sender};
+use futures::executor::block_on;
+use futures::{SinkExt, Stream, StreamExt};
+use http::source::set_source;
+use http::timestamp::set_timestamp;
+use http::types::body::LineBuilder;
+use log::{info, warn};
+use metrics::Metrics;
+use middleware::level::Level;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::mem;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// The path of the kernel ring buffer
+pub const DEFAULT_PATH: &str = "/dev/kmsg";
+
+// changes on every boot, the sequence numbers of records start over with it
+const BOOT_ID_PATH: &str = "/proc/sys/kernel/random/boot_id";
+
+// records are at most 8KiB long, including their continuation lines
+const MAX_RECORD_BYTES: usize = 8 * 1024;
+
+// the number of records that are buffered before the reader waits for them to be sent
+const CHANNEL_SIZE: usize = 1024;
+
+/// The last record that was read from a ring buffer
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Position {
+    pub boot_id: String,
+    pub sequence: u64,
+}
+
+/// Stores the position in each ring buffer, so records aren't sent again after a restart
+pub type SequenceStore = state::Store<String, Position>;
+
+/// A record of the kernel ring buffer
+#[derive(Debug, PartialEq)]
+pub struct Record {
+    pub priority: u8,
+    pub sequence: u64,
+    /// The time since boot the record was logged at
+    pub timestamp: Duration,
+    pub message: String,
+    /// The `KEY=value` pairs of the continuation lines following the message
+    pub fields: Map<String, Value>,
+}
+
+impl Record {
+    /// Parses a record like `6,339,5140900,-;NET: Registered protocol family 10`, followed by
+    /// continuation lines like ` SUBSYSTEM=net`. The fragments of a message that older kernels
+    /// flag with `c` and `+` aren't merged, each is a record of it's own.
+    pub fn parse(record: &[u8]) -> Option<Self> {
+        let record = String::from_utf8_lossy(record);
+        let mut lines = record.trim_end_matches('\n').split('\n');
+        let (prefix, message) = lines.next()?.split_once(';')?;
+        let mut prefix = prefix.split(',');
+        let priority = prefix.next()?.parse::<u16>().ok()?;
+        let sequence = prefix.next()?.parse().ok()?;
+        let timestamp = Duration::from_micros(prefix.next()?.parse().ok()?);
+
+        let mut fields = Map::new();
+        for line in lines {
+            if let Some((key, value)) = line.strip_prefix(' ').and_then(|f| f.split_once('=')) {
+                fields.insert(key.to_string(), unescape(value).into());
+            }
+        }
+
+        Some(Self {
+            // the facility of records written by the kernel is 0, it doesn't fit a syslog
+            // priority for the ones written from user space
+            priority: priority.min(191) as u8,
+            sequence,
+            timestamp,
+            message: unescape(message),
+            fields,
+        })
+    }
+
+    /// Returns the line of the record, the time since boot is converted to wall-clock time
+    /// with the time the host booted at
+    pub fn into_line(self, boot_time: DateTime<Utc>) -> LineBuilder {
+        let facility = self.priority / 8;
+        let mut line = LineBuilder::new().line(self.message);
+        if let Some(level) = Level::from_syslog_severity(self.priority % 8) {
+            line = line.level(level.as_str());
+        }
+        if facility == 0 {
+            line = line.app("kernel");
+        }
+
+        let mut meta = self.fields;
+        meta.insert(
+            "facility".to_string(),
+            FACILITIES[usize::from(facility)].into(),
+        );
+        meta.insert("sequence".to_string(), self.sequence.into());
+        line = line.meta(Value::Object(meta));
+        set_source(&mut line, "kmsg");
+
+        if let Ok(since_boot) = chrono::Duration::from_std(self.timestamp) {
+            set_timestamp(&mut line, boot_time + since_boot);
+        }
+        line
+    }
+}
+
+// the kernel escapes non-printable bytes, such as new lines, as `\x0a`
+fn unescape(s: &str) -> String {
+    if !s.contains("\\x") {
+        return s.to_string();
+    }
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .strip_prefix(b"x")
+            .and_then(|hex| hex.get(..2))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(escaped) if byte == b'\\' => {
+                bytes.push(escaped);
+                rest = &tail[3..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Reads the records of a ring buffer on it's own thread, as the reads block until there are
+/// new records. The records of this boot up to the position in the store are skipped.
+pub(crate) fn read(
+    path: PathBuf,
+    sequences: Option<Arc<Mutex<SequenceStore>>>,
+) -> io::Result<impl Stream<Item = Vec<LineBuilder>>> {
+    let file = File::open(&path)?;
+    let boot_id = fs::read_to_string(BOOT_ID_PATH)
+        .map(|id| id.trim().to_string())
+        .unwrap_or_default();
+    let boot_time = boot_time()?;
+    let key = path.to_string_lossy().into_owned();
+    let last = last_sequence(&sequences, &key, &boot_id);
+    let (sender, receiver) = channel(CHANNEL_SIZE);
+    info!("reading kernel messages from {:?}", path);
+    thread::spawn(move || {
+        if let Err(e) = read_records(file, &boot_id, boot_time, last, sender) {
+            warn!("stopped reading kernel messages from {:?}: {}", path, e);
+        }
+    });
+    Ok(store_positions(receiver, key, sequences))
+}
+
+// the sequence of the last record of this boot that was sent
+fn last_sequence(
+    sequences: &Option<Arc<Mutex<SequenceStore>>>,
+    key: &str,
+    boot_id: &str,
+) -> Option<u64> {
+    let sequences = sequences
+        .as_ref()?
+        .lock()
+        .expect("Couldn't lock kmsg sequences");
+    let position = sequences.get(&key.to_string())?;
+    Some(position.sequence).filter(|_| position.boot_id == boot_id)
+}
+
+// the position of a record is stored once it's line is taken to be sent, not while it's still
+// waiting in the channel
+fn store_positions(
+    receiver: Receiver<(LineBuilder, Position)>,
+    key: String,
+    sequences: Option<Arc<Mutex<SequenceStore>>>,
+) -> impl Stream<Item = Vec<LineBuilder>> {
+    receiver.map(move |(line, position)| {
+        if let Some(ref sequences) = sequences {
+            sequences
+                .lock()
+                .expect("Couldn't lock kmsg sequences")
+                .insert(key.clone(), position);
+        }
+        vec![line]
+    })
+}
+
+// each read returns a single record, a record that was overwritten before it was read is
+// reported as a broken pipe
+fn read_records<R: Read>(
+    mut reader: R,
+    boot_id: &str,
+    boot_time: DateTime<Utc>,
+    last: Option<u64>,
+    mut sender: Sender<(LineBuilder, Position)>,
+) -> io::Result<()> {
+    let mut buf = vec![0; MAX_RECORD_BYTES];
+    loop {
+        let len = match reader.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(len) => len,
+            Err(e) if e.raw_os_error() == Some(libc::EPIPE) => {
+                Metrics::kmsg().increment_overwritten();
+                continue;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let record = match Record::parse(&buf[..len]) {
+            Some(record) => record,
+            None => continue,
+        };
+        let sequence = record.sequence;
+        if last.is_some_and(|last| sequence <= last) {
+            continue;
+        }
+
+        Metrics::kmsg().add_bytes(len as u64);
+        Metrics::kmsg().increment_lines();
+        let position = Position {
+            boot_id: boot_id.to_string(),
+            sequence,
+        };
+        if block_on(sender.send((record.into_line(boot_time), position))).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+// the timestamps of records are taken from the monotonic clock
+fn boot_time() -> io::Result<DateTime<Utc>> {
+    // Safety: all fields of the time are valid when zeroed
+    let mut monotonic: libc::timespec = unsafe { mem::zeroed() };
+    // Safety: the time outlives the call
+    if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut monotonic) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let uptime = chrono::Duration::seconds(monotonic.tv_sec as i64)
+        + chrono::Duration::nanoseconds(monotonic.tv_nsec as i64);
+    Ok(Utc::now() - uptime)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+    use futures::channel::mpsc::channel;
+    use futures::StreamExt;
+    use http::timestamp::get_timestamp;
+    use serde_json::json;
+    use std::collections::VecDeque;
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    // returns a record for each read, like the ring buffer
+    struct Records(VecDeque<io::Result<&'static [u8]>>);
+
+    impl Read for Records {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.pop_front() {
+                Some(Ok(record)) => {
+                    buf[..record.len()].copy_from_slice(record);
+                    Ok(record.len())
+                }
+                Some(Err(e)) => Err(e),
+                None => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_records() {
+        let record = Record::parse(
+            b"3,1057,80123456,-;usb 1-1: device descriptor read/64, error -71\\x0aretrying\n SUBSYSTEM=usb\n DEVICE=c189:1\n",
+        )
+        .unwrap();
+        assert_eq!(record.priority, 3);
+        assert_eq!(record.sequence, 1057);
+        assert_eq!(record.timestamp, Duration::from_micros(80_123_456));
+        assert_eq!(
+            record.message,
+            "usb 1-1: device descriptor read/64, error -71\nretrying"
+        );
+
+        let boot_time = Utc.timestamp_millis(1_600_000_000_000);
+        let line = record.into_line(boot_time);
+        assert_eq!(line.level, Some("ERROR".to_string()));
+        assert_eq!(line.app, Some("kernel".to_string()));
+        assert_eq!(get_timestamp(&line), Some(1_600_000_080_123));
+        let meta = line.meta.unwrap();
+        assert_eq!(meta["SUBSYSTEM"], json!("usb"));
+        assert_eq!(meta["DEVICE"], json!("c189:1"));
+        assert_eq!(meta["facility"], json!("kern"));
+        assert_eq!(meta["sequence"], json!(1057));
+
+        // records written from user space have other facilities
+        let line = Record::parse(b"30,1058,80200000,-;systemd[1]: Started Journal Service.\n")
+            .unwrap()
+            .into_line(boot_time);
+        assert_eq!(line.level, Some("INFO".to_string()));
+        assert_eq!(line.app, None);
+
+        assert_eq!(Record::parse(b"not a record"), None);
+        assert_eq!(unescape(r"a\x5cb\x2"), r"a\b\x2");
+    }
+
+    #[test]
+    fn resume_after_sequence() {
+        let dir = tempdir().unwrap();
+        let store = SequenceStore::open(dir.path().join("kmsg.json")).unwrap();
+        let sequences = Arc::new(Mutex::new(store));
+        let path = Path::new(DEFAULT_PATH);
+        let records = || {
+            Records(
+                vec![
+                    Ok(&b"6,1,100,-;first\n"[..]),
+                    Err(io::Error::from_raw_os_error(libc::EPIPE)),
+                    Ok(b"6,3,300,-;third\n"),
+                    Ok(b"6,4,400,-;fourth\n"),
+                ]
+                .into(),
+            )
+        };
+        let key = path.to_string_lossy().into_owned();
+        let read = |boot_id: &str, records: Records| {
+            let (sender, receiver) = channel(8);
+            let boot_time = Utc::now();
+            let last = last_sequence(&Some(sequences.clone()), &key, boot_id);
+            read_records(records, boot_id, boot_time, last, sender).unwrap();
+            block_on(
+                store_positions(receiver, key.clone(), Some(sequences.clone()))
+                    .map(|mut lines| lines.pop().unwrap().line.unwrap())
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        let mut first = records();
+        first.0.truncate(3);
+        assert_eq!(read("boot", first), vec!["first", "third"]);
+        assert!(Metrics::kmsg().read_overwritten() >= 1);
+        assert_eq!(read("boot", records()), vec!["fourth"]);
+        // the sequence starts over after a reboot
+        assert_eq!(read("reboot", records()).len(), 3);
+        assert_eq!(
+            sequences.lock().unwrap().get(&DEFAULT_PATH.to_string()),
+            Some(&Position {
+                boot_id: "reboot".to_string(),
+                sequence: 4
+            })
+        );
+    }
+}
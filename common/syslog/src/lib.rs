@@ -0,0 +1,8 @@
+/// Reads the kernel ring buffer
+pub mod kmsg;
+/// Parses RFC 5424 and RFC 3164 messages into lines
+pub mod parse;
+/// Receives syslog messages over UDP, TCP and TLS
+pub mod source;
+/// Receives local messages on unix sockets, along with the credentials of their senders
+pub mod unix;
@@ -0,0 +1,284 @@
+use chrono::{DateTime, Utc};
+use http::timestamp::set_timestamp;
+use http::types::body::LineBuilder;
+use middleware::level::Level;
+use middleware::timestamp::parse_syslog_timestamp;
+use serde_json::{Map, Value};
+
+pub(crate) const FACILITIES: [&str; 24] = [
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv",
+    "ftp", "ntp", "audit", "alert", "clock", "local0", "local1", "local2", "local3", "local4",
+    "local5", "local6", "local7",
+];
+
+// the nil value of RFC 5424 header fields
+const NIL: &str = "-";
+
+/// The line a message was parsed into
+#[derive(Debug)]
+pub enum Message {
+    Valid(LineBuilder),
+    /// As much of the message as could be parsed, messages without a priority are lines of the
+    /// whole message
+    Malformed(LineBuilder),
+}
+
+impl Message {
+    pub fn is_malformed(&self) -> bool {
+        matches!(self, Message::Malformed(_))
+    }
+
+    pub fn into_line(self) -> LineBuilder {
+        match self {
+            Message::Valid(line) | Message::Malformed(line) => line,
+        }
+    }
+}
+
+/// Parses a RFC 5424 or RFC 3164 message into a line, the app, host and level are taken from
+/// the header and the facility, process id, message id and structured data are added to the
+/// meta
+pub fn parse(message: &[u8]) -> Message {
+    let message = String::from_utf8_lossy(message);
+    let message = message.trim_end_matches(['\n', '\r', '\0']);
+    match parse_pri(message) {
+        Some((pri, rest)) => match rest.strip_prefix("1 ") {
+            Some(rest) => parse_rfc5424(pri, rest),
+            None => Message::Valid(parse_rfc3164(pri, rest)),
+        },
+        None => Message::Malformed(LineBuilder::new().line(message)),
+    }
+}
+
+// returns the priority of a message and the rest of it
+fn parse_pri(message: &str) -> Option<(u8, &str)> {
+    let rest = message.strip_prefix('<')?;
+    let end = rest.find('>').filter(|end| (1..=3).contains(end))?;
+    let pri = rest[..end].parse::<u8>().ok().filter(|pri| *pri < 192)?;
+    Some((pri, &rest[end + 1..]))
+}
+
+// the line of a message with the level and facility of it's priority
+fn with_pri(pri: u8) -> (LineBuilder, Map<String, Value>) {
+    let mut line = LineBuilder::new();
+    if let Some(level) = Level::from_syslog_severity(pri % 8) {
+        line = line.level(level.as_str());
+    }
+    let mut meta = Map::new();
+    meta.insert(
+        "facility".to_string(),
+        FACILITIES[usize::from(pri / 8)].into(),
+    );
+    (line, meta)
+}
+
+// `TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG`
+fn parse_rfc5424(pri: u8, rest: &str) -> Message {
+    let (mut line, mut meta) = with_pri(pri);
+    let mut fields = rest.splitn(6, ' ');
+    let mut field = || {
+        fields
+            .next()
+            .filter(|field| *field != NIL && !field.is_empty())
+    };
+    let timestamp = field().and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok());
+    if let Some(host) = field() {
+        line = line.host(host);
+    }
+    if let Some(app) = field() {
+        line = line.app(app);
+    }
+    if let Some(procid) = field() {
+        meta.insert("procid".to_string(), procid.into());
+    }
+    if let Some(msgid) = field() {
+        meta.insert("msgid".to_string(), msgid.into());
+    }
+    let rest = fields.next().unwrap_or_default();
+
+    let mut malformed = false;
+    let message = match rest.strip_prefix(NIL) {
+        Some(message) => message,
+        None => match parse_structured_data(rest) {
+            Some((data, message)) => {
+                meta.insert("structured_data".to_string(), Value::Object(data));
+                message
+            }
+            None => {
+                malformed = true;
+                rest
+            }
+        },
+    };
+    let message = message.strip_prefix(' ').unwrap_or(message);
+    let message = message.strip_prefix('\u{feff}').unwrap_or(message);
+
+    line = line.line(message).meta(Value::Object(meta));
+    if let Some(timestamp) = timestamp {
+        set_timestamp(&mut line, timestamp.with_timezone(&Utc));
+    }
+    if malformed {
+        Message::Malformed(line)
+    } else {
+        Message::Valid(line)
+    }
+}
+
+// parses `[id param="value" ...]...` into an object of the params of each id, returning it and
+// the rest of the message
+fn parse_structured_data(mut rest: &str) -> Option<(Map<String, Value>, &str)> {
+    let mut data = Map::new();
+    while let Some(element) = rest.strip_prefix('[') {
+        let end = element.find([' ', ']'])?;
+        let id = &element[..end];
+        let mut element = &element[end..];
+        let mut params = Map::new();
+        while let Some(param) = element.strip_prefix(' ') {
+            let eq = param.find("=\"")?;
+            let name = &param[..eq];
+            // values are quoted, quotes, backslashes and brackets in them are escaped
+            let mut value = String::new();
+            let mut chars = param[eq + 2..].char_indices();
+            let end = loop {
+                match chars.next()? {
+                    (_, '\\') => value.push(chars.next()?.1),
+                    (idx, '"') => break eq + 2 + idx + 1,
+                    (_, c) => value.push(c),
+                }
+            };
+            params.insert(name.to_string(), value.into());
+            element = &param[end..];
+        }
+        rest = element.strip_prefix(']')?;
+        data.insert(id.to_string(), Value::Object(params));
+    }
+    if data.is_empty() {
+        None
+    } else {
+        Some((data, rest))
+    }
+}
+
+// `Mmm dd hh:mm:ss HOSTNAME TAG[PID]: MSG`, the timestamp and host may be missing, like in the
+// messages of local senders
+fn parse_rfc3164(pri: u8, rest: &str) -> LineBuilder {
+    let (mut line, mut meta) = with_pri(pri);
+    let timestamp = rest.get(..15).and_then(parse_syslog_timestamp);
+    let mut rest = match timestamp {
+        Some(_) => rest[15..].trim_start(),
+        None => rest,
+    };
+
+    // the host is followed by the tag, which ends in a colon or the pid
+    let mut words = rest.splitn(3, ' ');
+    if let (Some(host), Some(tag)) = (words.next(), words.next()) {
+        if !is_tag(host) && is_tag(tag) {
+            line = line.host(host);
+            rest = &rest[host.len() + 1..];
+        }
+    }
+    if let Some(end) = rest.find(' ').filter(|end| is_tag(&rest[..*end])) {
+        let tag = rest[..end].trim_end_matches(':');
+        let (app, pid) = match tag.find('[') {
+            Some(start) => (&tag[..start], tag[start + 1..].strip_suffix(']')),
+            None => (tag, None),
+        };
+        line = line.app(app);
+        if let Some(pid) = pid {
+            meta.insert("procid".to_string(), pid.into());
+        }
+        rest = &rest[end + 1..];
+    }
+
+    line = line.line(rest).meta(Value::Object(meta));
+    if let Some(timestamp) = timestamp {
+        set_timestamp(&mut line, timestamp);
+    }
+    line
+}
+
+// returns true for words like `app:` or `app[123]:`
+fn is_tag(word: &str) -> bool {
+    let word = match word.strip_suffix(':') {
+        Some(word) => word,
+        None => return word.ends_with(']') && word.contains('['),
+    };
+    let name = word.split('[').next().unwrap_or_default();
+    !name.is_empty() && name.len() <= 48 && !name.contains(|c: char| c.is_whitespace())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use http::timestamp::get_timestamp;
+    use serde_json::json;
+
+    #[test]
+    fn rfc5424() {
+        let line = parse(
+            br#"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 [exampleSDID@32473 iut="3" eventSource="App\"lication\]"][examplePriority@32473 class="high"] An application event"#,
+        )
+        .into_line();
+        assert_eq!(line.line, Some("An application event".to_string()));
+        assert_eq!(line.host, Some("mymachine.example.com".to_string()));
+        assert_eq!(line.app, Some("evntslog".to_string()));
+        assert_eq!(line.level, Some("NOTICE".to_string()));
+        assert_eq!(get_timestamp(&line), Some(1_065_910_455_003));
+        let meta = line.meta.unwrap();
+        assert_eq!(meta["facility"], json!("local4"));
+        assert_eq!(meta["msgid"], json!("ID47"));
+        assert_eq!(meta.get("procid"), None);
+        assert_eq!(
+            meta["structured_data"],
+            json!({
+                "exampleSDID@32473": {"iut": "3", "eventSource": "App\"lication]"},
+                "examplePriority@32473": {"class": "high"}
+            })
+        );
+
+        let line = parse(b"<34>1 - - su 42 - - \xef\xbb\xbf'su root' failed\n").into_line();
+        assert_eq!(line.line, Some("'su root' failed".to_string()));
+        assert_eq!(line.app, Some("su".to_string()));
+        assert_eq!(line.host, None);
+        assert_eq!(line.level, Some("CRITICAL".to_string()));
+        assert_eq!(line.meta.unwrap()["procid"], json!("42"));
+
+        // the header of messages with malformed structured data is still parsed
+        let message = parse(b"<13>1 - host app - - [unterminated message");
+        assert!(message.is_malformed());
+        let line = message.into_line();
+        assert_eq!(line.line, Some("[unterminated message".to_string()));
+        assert_eq!(line.app, Some("app".to_string()));
+    }
+
+    #[test]
+    fn rfc3164() {
+        let line = parse(b"<34>Oct 11 22:14:15 mymachine su[230]: 'su root' failed for lonvick")
+            .into_line();
+        assert_eq!(line.line, Some("'su root' failed for lonvick".to_string()));
+        assert_eq!(line.host, Some("mymachine".to_string()));
+        assert_eq!(line.app, Some("su".to_string()));
+        assert_eq!(line.level, Some("CRITICAL".to_string()));
+        assert!(get_timestamp(&line).is_some());
+        let meta = line.meta.unwrap();
+        assert_eq!(meta["facility"], json!("auth"));
+        assert_eq!(meta["procid"], json!("230"));
+
+        // local senders leave out the host
+        let line = parse(b"<13>Oct 11 22:14:15 cron: job started").into_line();
+        assert_eq!(line.line, Some("job started".to_string()));
+        assert_eq!(line.host, None);
+        assert_eq!(line.app, Some("cron".to_string()));
+
+        let line = parse(b"<13>no header at all").into_line();
+        assert_eq!(line.line, Some("no header at all".to_string()));
+        assert_eq!(line.app, None);
+        assert_eq!(line.level, Some("NOTICE".to_string()));
+
+        let message = parse(b"not syslog");
+        assert!(message.is_malformed());
+        let line = message.into_line();
+        assert_eq!(line.line, Some("not syslog".to_string()));
+        assert_eq!(line.level, None);
+    }
+}
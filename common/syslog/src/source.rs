@@ -0,0 +1,373 @@
+use crate::kmsg::{self, SequenceStore};
+use crate::parse::parse;
+use crate::unix;
+use futures::channel::mpsc::{channel, Sender};
+use futures::{SinkExt, Stream, StreamExt};
+use http::source::set_source;
+use http::types::body::LineBuilder;
+use log::{info, warn};
+use metrics::Metrics;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncReadExt, BufReader};
+use tokio::net::{TcpListener, UdpSocket};
+
+pub use native_tls::Identity;
+
+/// The size in bytes up to which messages are read, the longest UDP datagram
+pub const DEFAULT_MAX_MESSAGE_BYTES: usize = 64 * 1024;
+
+// the number of parsed messages that are buffered before the listeners wait for them to be sent
+const CHANNEL_SIZE: usize = 1024;
+// the most digits the length of an octet-counted message is made of
+const MAX_LEN_DIGITS: usize = 8;
+
+/// The sockets syslog messages are received on
+pub struct Options {
+    pub udp: Vec<SocketAddr>,
+    /// Sockets accepting connections that send messages either octet-counted or newline-framed
+    pub tcp: Vec<SocketAddr>,
+    /// Sockets accepting TLS connections, which require the identity
+    pub tls: Vec<SocketAddr>,
+    pub identity: Option<Identity>,
+    /// Unix datagram sockets like `/dev/log`, messages on them that aren't syslog are sent as
+    /// they are
+    pub unix: Vec<PathBuf>,
+    pub unix_stream: Vec<PathBuf>,
+    /// The kernel ring buffer, usually `/dev/kmsg`
+    pub kmsg: Option<PathBuf>,
+    pub max_message_bytes: usize,
+}
+
+impl Options {
+    pub fn is_empty(&self) -> bool {
+        self.udp.is_empty()
+            && self.tcp.is_empty()
+            && self.tls.is_empty()
+            && self.unix.is_empty()
+            && self.unix_stream.is_empty()
+            && self.kmsg.is_none()
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            udp: Vec::new(),
+            tcp: Vec::new(),
+            tls: Vec::new(),
+            identity: None,
+            unix: Vec::new(),
+            unix_stream: Vec::new(),
+            kmsg: None,
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+        }
+    }
+}
+
+impl fmt::Debug for Options {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Options")
+            .field("udp", &self.udp)
+            .field("tcp", &self.tcp)
+            .field("tls", &self.tls)
+            .field("identity", &self.identity.as_ref().map(|_| "REDACTED"))
+            .field("unix", &self.unix)
+            .field("unix_stream", &self.unix_stream)
+            .field("kmsg", &self.kmsg)
+            .field("max_message_bytes", &self.max_message_bytes)
+            .finish()
+    }
+}
+
+/// Loads the certificate and key TLS connections are accepted with from a PKCS #12 archive
+pub fn load_identity<P: AsRef<Path>>(path: P, password: &str) -> io::Result<Identity> {
+    Identity::from_pkcs12(&std::fs::read(path)?, password)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Listens on the sockets of the options, has to be called within the runtime the listeners are
+/// spawned on. The position in the kernel ring buffer is kept in the store, if there is one.
+pub fn create_source(
+    options: Options,
+    kmsg_sequences: Option<Arc<Mutex<SequenceStore>>>,
+) -> impl Stream<Item = Vec<LineBuilder>> {
+    let (sender, receiver) = channel(CHANNEL_SIZE);
+    let max_bytes = options.max_message_bytes;
+
+    for addr in options.udp {
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            if let Err(e) = listen_udp(addr, max_bytes, sender).await {
+                warn!("syslog listener on udp://{} stopped: {}", addr, e);
+            }
+        });
+    }
+
+    for addr in options.tcp {
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            if let Err(e) = listen_tcp(addr, None, max_bytes, sender).await {
+                warn!("syslog listener on tcp://{} stopped: {}", addr, e);
+            }
+        });
+    }
+
+    if !options.tls.is_empty() {
+        let acceptor = match options.identity.map(native_tls::TlsAcceptor::new) {
+            Some(Ok(acceptor)) => Some(tokio_tls::TlsAcceptor::from(acceptor)),
+            Some(Err(e)) => {
+                warn!("unable to accept syslog TLS connections: {}", e);
+                None
+            }
+            None => {
+                warn!("syslog TLS listeners require an identity");
+                None
+            }
+        };
+        for addr in options.tls.into_iter().filter(|_| acceptor.is_some()) {
+            let sender = sender.clone();
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                if let Err(e) = listen_tcp(addr, acceptor, max_bytes, sender).await {
+                    warn!("syslog listener on tls://{} stopped: {}", addr, e);
+                }
+            });
+        }
+    }
+
+    for path in options.unix {
+        if let Err(e) = unix::listen_datagram(path.clone(), max_bytes, sender.clone()) {
+            warn!("unable to listen on unix socket {:?}: {}", path, e);
+        }
+    }
+
+    for path in options.unix_stream {
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            if let Err(e) = unix::listen_stream(path.clone(), max_bytes, sender).await {
+                warn!("unix socket listener on {:?} stopped: {}", path, e);
+            }
+        });
+    }
+
+    let kmsg = options
+        .kmsg
+        .and_then(|path| match kmsg::read(path.clone(), kmsg_sequences) {
+            Ok(records) => Some(records),
+            Err(e) => {
+                warn!("unable to read kernel messages from {:?}: {}", path, e);
+                None
+            }
+        });
+
+    futures::stream::select(receiver, futures::stream::iter(kmsg).flatten())
+}
+
+async fn listen_udp(
+    addr: SocketAddr,
+    max_bytes: usize,
+    mut sender: Sender<Vec<LineBuilder>>,
+) -> io::Result<()> {
+    let mut socket = UdpSocket::bind(addr).await?;
+    info!("listening for syslog messages on udp://{}", addr);
+    let mut buf = vec![0; max_bytes];
+    loop {
+        let (len, _) = socket.recv_from(&mut buf).await?;
+        if sender.send(vec![to_line(&buf[..len])]).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+async fn listen_tcp(
+    addr: SocketAddr,
+    acceptor: Option<tokio_tls::TlsAcceptor>,
+    max_bytes: usize,
+    sender: Sender<Vec<LineBuilder>>,
+) -> io::Result<()> {
+    let mut listener = TcpListener::bind(addr).await?;
+    info!("listening for syslog connections on {}", addr);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        Metrics::syslog().increment_connections();
+        let sender = sender.clone();
+        let acceptor = acceptor.clone();
+        tokio::spawn(async move {
+            let result = match acceptor {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => read_frames(stream, max_bytes, sender, to_line).await,
+                    Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+                },
+                None => read_frames(stream, max_bytes, sender, to_line).await,
+            };
+            if let Err(e) = result {
+                warn!("closed syslog connection of {}: {}", peer, e);
+            }
+        });
+    }
+}
+
+// reads the messages of a connection until it's closed, messages starting with their length, a
+// space and their priority are octet-counted, e.g. `11 <13>message`, other messages end at a new
+// line or a null byte
+pub(crate) async fn read_frames<R, F>(
+    reader: R,
+    max_bytes: usize,
+    mut sender: Sender<Vec<LineBuilder>>,
+    mut to_line: F,
+) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    F: FnMut(&[u8]) -> LineBuilder,
+{
+    let mut reader = BufReader::new(reader);
+    let mut frame = Vec::new();
+    loop {
+        frame.clear();
+        let first = match read_byte(&mut reader).await? {
+            Some(first) => first,
+            None => return Ok(()),
+        };
+
+        if first.is_ascii_digit() {
+            frame.push(first);
+            let mut next = read_byte(&mut reader).await?;
+            while let Some(digit) =
+                next.filter(|byte| byte.is_ascii_digit() && frame.len() < MAX_LEN_DIGITS)
+            {
+                frame.push(digit);
+                next = read_byte(&mut reader).await?;
+            }
+            let len = std::str::from_utf8(&frame)
+                .ok()
+                .and_then(|len| len.parse::<usize>().ok())
+                .filter(|len| *len <= max_bytes);
+            match (next, len) {
+                (Some(b' '), Some(len)) if peek_byte(&mut reader).await? == Some(b'<') => {
+                    frame.clear();
+                    frame.resize(len, 0);
+                    reader.read_exact(&mut frame).await?;
+                }
+                // a newline-framed message that happens to start with a digit
+                (Some(byte), _) if !is_delimiter(byte) => {
+                    frame.push(byte);
+                    read_until_delimiter(&mut reader, &mut frame, max_bytes).await?;
+                }
+                _ => {}
+            }
+        } else if !is_delimiter(first) {
+            frame.push(first);
+            read_until_delimiter(&mut reader, &mut frame, max_bytes).await?;
+        } else {
+            continue;
+        }
+
+        if sender.send(vec![to_line(&frame)]).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+// returns None once the connection is closed
+async fn read_byte<R: AsyncRead + Unpin>(reader: &mut BufReader<R>) -> io::Result<Option<u8>> {
+    match reader.read_u8().await {
+        Ok(byte) => Ok(Some(byte)),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// returns the next byte without reading it
+async fn peek_byte<R: AsyncRead + Unpin>(reader: &mut BufReader<R>) -> io::Result<Option<u8>> {
+    futures::future::poll_fn(|cx| {
+        Pin::new(&mut *reader)
+            .poll_fill_buf(cx)
+            .map_ok(|buf| buf.first().copied())
+    })
+    .await
+}
+
+// the reader is buffered, the rest of messages that are too long is skipped
+async fn read_until_delimiter<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+    frame: &mut Vec<u8>,
+    max_bytes: usize,
+) -> io::Result<()> {
+    while let Some(byte) = read_byte(reader).await? {
+        if is_delimiter(byte) {
+            break;
+        }
+        if frame.len() < max_bytes {
+            frame.push(byte);
+        }
+    }
+    Ok(())
+}
+
+fn is_delimiter(byte: u8) -> bool {
+    byte == b'\n' || byte == b'\0'
+}
+
+fn to_line(message: &[u8]) -> LineBuilder {
+    Metrics::syslog().add_bytes(message.len() as u64);
+    Metrics::syslog().increment_lines();
+    let message = parse(message);
+    if message.is_malformed() {
+        Metrics::syslog().increment_malformed();
+    }
+    let mut line = message.into_line();
+    set_source(&mut line, "syslog");
+    line
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn frames() {
+        let (sender, receiver) = channel(CHANNEL_SIZE);
+        let input: &[u8] =
+            b"17 <13>octet\ncounted\n<13>newline framed\r\n\n<13>too long to be read here\n<13>null\0<13>last";
+        read_frames(input, 24, sender, to_line).await.unwrap();
+        let lines = receiver
+            .map(|lines| lines[0].line.clone().unwrap())
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(
+            lines,
+            vec![
+                "octet\ncounted",
+                "newline framed",
+                "too long to be read ",
+                "null",
+                "last"
+            ]
+        );
+
+        // messages starting with a digit that aren't octet-counted are newline framed
+        let (sender, receiver) = channel(CHANNEL_SIZE);
+        let input: &[u8] = b"100 <13>too long\n2020-01-02 started\n12 apples\n7\n9 <13>hello";
+        read_frames(input, 24, sender, to_line).await.unwrap();
+        let lines = receiver
+            .map(|lines| lines[0].line.clone().unwrap())
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(
+            lines,
+            vec![
+                "100 <13>too long",
+                "2020-01-02 started",
+                "12 apples",
+                "7",
+                "hello"
+            ]
+        );
+    }
+}
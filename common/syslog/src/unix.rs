@@ -0,0 +1,310 @@
+use crate::parse::parse;
+use crate::source::read_frames;
+use futures::channel::mpsc::Sender;
+use futures::executor::block_on;
+use futures::SinkExt;
+use http::source::set_source;
+use http::types::body::LineBuilder;
+use log::{info, warn};
+use metrics::Metrics;
+use serde_json::{Map, Value};
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::thread;
+use tokio::net::UnixListener;
+
+/// The process that sent a message, as reported by the kernel
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Credentials {
+    pub pid: i32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl From<libc::ucred> for Credentials {
+    fn from(ucred: libc::ucred) -> Self {
+        Self {
+            pid: ucred.pid,
+            uid: ucred.uid,
+            gid: ucred.gid,
+        }
+    }
+}
+
+impl Credentials {
+    // adds the credentials to the meta of a line, next to what was parsed from the message
+    fn add_to(self, line: &mut LineBuilder) {
+        let mut meta = match line.meta.take() {
+            Some(Value::Object(meta)) => meta,
+            _ => Map::new(),
+        };
+        meta.insert("pid".to_string(), self.pid.into());
+        meta.insert("uid".to_string(), self.uid.into());
+        meta.insert("gid".to_string(), self.gid.into());
+        line.meta = Some(Value::Object(meta));
+    }
+}
+
+// local senders often don't use syslog, such messages are sent as they are
+fn to_line(message: &[u8], credentials: Option<Credentials>) -> LineBuilder {
+    Metrics::unix_socket().add_bytes(message.len() as u64);
+    Metrics::unix_socket().increment_lines();
+    let mut line = parse(message).into_line();
+    if let Some(credentials) = credentials {
+        credentials.add_to(&mut line);
+    }
+    set_source(&mut line, "unix_socket");
+    line
+}
+
+/// Receives messages on a datagram socket, like `/dev/log`. The credentials of the senders
+/// are read with blocking calls, so the socket is read on it's own thread.
+pub(crate) fn listen_datagram(
+    path: PathBuf,
+    max_bytes: usize,
+    mut sender: Sender<Vec<LineBuilder>>,
+) -> io::Result<()> {
+    let socket = bind_datagram(&path)?;
+    info!("listening for messages on unix socket {:?}", path);
+    thread::spawn(move || {
+        let mut buf = vec![0; max_bytes];
+        loop {
+            let (len, credentials) = match recv_with_credentials(socket.as_raw_fd(), &mut buf) {
+                Ok(received) => received,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    warn!("unix socket listener on {:?} stopped: {}", path, e);
+                    return;
+                }
+            };
+            if block_on(sender.send(vec![to_line(&buf[..len], credentials)])).is_err() {
+                return;
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Accepts connections on a stream socket, the messages of a connection are framed like the
+/// ones of TCP connections or end with a null byte
+pub(crate) async fn listen_stream(
+    path: PathBuf,
+    max_bytes: usize,
+    sender: Sender<Vec<LineBuilder>>,
+) -> io::Result<()> {
+    remove_stale_socket(&path)?;
+    let mut listener = UnixListener::bind(&path)?;
+    allow_all(&path)?;
+    info!("listening for connections on unix socket {:?}", path);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        Metrics::unix_socket().increment_connections();
+        let credentials = match peer_credentials(stream.as_raw_fd()) {
+            Ok(credentials) => Some(credentials),
+            Err(e) => {
+                warn!(
+                    "unable to read the credentials of a unix socket peer: {}",
+                    e
+                );
+                None
+            }
+        };
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            let result = read_frames(stream, max_bytes, sender, |message| {
+                to_line(message, credentials)
+            })
+            .await;
+            if let Err(e) = result {
+                warn!("closed unix socket connection of {:?}: {}", credentials, e);
+            }
+        });
+    }
+}
+
+fn bind_datagram(path: &Path) -> io::Result<UnixDatagram> {
+    remove_stale_socket(path)?;
+    let socket = UnixDatagram::bind(path)?;
+    allow_all(path)?;
+    let enable: libc::c_int = 1;
+    // Safety: the option is an int that outlives the call
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PASSCRED,
+            &enable as *const _ as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(socket)
+}
+
+// a socket left behind by a previous run has to be removed before the path can be bound again
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "the path exists and isn't a socket",
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+// processes of any user may log, like to the `/dev/log` of syslog daemons
+fn allow_all(path: &Path) -> io::Result<()> {
+    fs::set_permissions(path, fs::Permissions::from_mode(0o666))
+}
+
+// receives a datagram and the credentials the kernel attaches to it once SO_PASSCRED is set,
+// datagrams longer than the buffer are cut off
+fn recv_with_credentials(fd: RawFd, buf: &mut [u8]) -> io::Result<(usize, Option<Credentials>)> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    // aligned for the header of the control message
+    let mut control = [0u64; 8];
+    // Safety: all fields of the header are valid when zeroed
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    // Safety: the header points to the buffers above, which outlive the call
+    let len = unsafe { libc::recvmsg(fd, &mut msg, 0) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut credentials = None;
+    // Safety: the control messages were written by the kernel into the control buffer
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_CREDENTIALS
+            {
+                let ucred = (libc::CMSG_DATA(cmsg) as *const libc::ucred).read_unaligned();
+                credentials = Some(ucred.into());
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    Ok((len as usize, credentials))
+}
+
+// returns the credentials of the process that connected to a stream socket
+fn peer_credentials(fd: RawFd) -> io::Result<Credentials> {
+    // Safety: all fields of the credentials are valid when zeroed
+    let mut ucred: libc::ucred = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // Safety: the credentials and their length outlive the call
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut ucred as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ucred.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::channel::mpsc::channel;
+    use futures::StreamExt;
+    use http::source::{get_source, take_source};
+    use serde_json::json;
+    use tempfile::tempdir;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::UnixStream;
+
+    fn credentials() -> serde_json::Value {
+        // Safety: the calls can't fail
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        json!({"pid": std::process::id(), "uid": uid, "gid": gid})
+    }
+
+    #[tokio::test]
+    async fn datagrams() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("log");
+        // a socket left behind is replaced
+        UnixDatagram::bind(&path).unwrap();
+
+        let (sender, mut receiver) = channel(1);
+        listen_datagram(path.clone(), 1024, sender).unwrap();
+        let client = UnixDatagram::unbound().unwrap();
+        client.send_to(b"<11>app[7]: failed", &path).unwrap();
+        client.send_to(b"plain message", &path).unwrap();
+
+        let line = receiver.next().await.unwrap().pop().unwrap();
+        assert_eq!(line.line, Some("failed".to_string()));
+        assert_eq!(line.app, Some("app".to_string()));
+        assert_eq!(line.level, Some("ERROR".to_string()));
+        let meta = line.meta.unwrap();
+        assert_eq!(meta["procid"], json!("7"));
+        for key in &["pid", "uid", "gid"] {
+            assert_eq!(meta[key], credentials()[key]);
+        }
+
+        let mut line = receiver.next().await.unwrap().pop().unwrap();
+        assert_eq!(line.line, Some("plain message".to_string()));
+        assert_eq!(get_source(&line), Some("unix_socket"));
+        take_source(&mut line);
+        assert_eq!(line.meta, Some(credentials()));
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o666
+        );
+
+        fs::write(dir.path().join("file"), "").unwrap();
+        let (sender, _) = channel(1);
+        assert!(listen_datagram(dir.path().join("file"), 1024, sender).is_err());
+    }
+
+    #[tokio::test]
+    async fn streams() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("log");
+        let (sender, receiver) = channel(1);
+        tokio::spawn(listen_stream(path.clone(), 1024, sender));
+
+        let mut client = loop {
+            match UnixStream::connect(&path).await {
+                Ok(client) => break client,
+                Err(_) => tokio::task::yield_now().await,
+            }
+        };
+        client
+            .write_all(b"<13>app: first\0<13>app: second\0")
+            .await
+            .unwrap();
+        drop(client);
+
+        let lines = receiver.take(2).collect::<Vec<_>>().await;
+        assert_eq!(lines[0][0].line, Some("first".to_string()));
+        assert_eq!(lines[1][0].line, Some("second".to_string()));
+        assert_eq!(
+            lines[1][0].meta.as_ref().unwrap()["pid"],
+            credentials()["pid"]
+        );
+    }
+}